@@ -1,16 +1,15 @@
 use std::{net::SocketAddr, sync::Arc};
 
 use axum::{
-    extract::{Query, State},
+    extract::{RawQuery, State},
     http::StatusCode,
     response::{Html, IntoResponse, Response},
     routing::get,
     Router, Server,
 };
 use clap::Parser;
-use ghoauth::GithubClient;
+use ghoauth::{AuthorizationCode, Callback, GithubClient};
 use maud::{html, DOCTYPE};
-use serde::Deserialize;
 use tokio::sync::RwLock;
 
 #[derive(Debug, Parser)]
@@ -85,15 +84,15 @@ async fn login_page(State(app_state): State<AppState>) -> Html<String> {
     Html(fragment.into_string())
 }
 
-#[derive(Debug, Deserialize)]
-struct CallbackHandlerQueryParams {
-    code: String,
-}
-
 async fn callback_handler(
     State(app_state): State<AppState>,
-    Query(CallbackHandlerQueryParams { code }): Query<CallbackHandlerQueryParams>,
+    RawQuery(query): RawQuery,
 ) -> Response {
+    let code = match Callback::from_query(query.as_deref().unwrap_or_default()) {
+        Ok(Callback::Code(AuthorizationCode { code, .. })) => code,
+        Ok(Callback::Error(e)) => return (StatusCode::FORBIDDEN, format!("{}", e)).into_response(),
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("{}", e)).into_response(),
+    };
     let github_client = app_state.github_client.read().await;
     let access_token = match github_client.get_access_token(&code).await {
         Ok(r) => r,
@@ -0,0 +1,194 @@
+use async_trait::async_trait;
+
+#[cfg(feature = "keys")]
+use crate::keys::{GpgKey, SshKey, SshSigningKey};
+use crate::{
+    error::Error,
+    graphql::ViewerIdentity,
+    shapes::{
+        Affiliation, AuthorizationRequest, Email, GetAccessTokenResponse, Organization, Permission,
+        Repository, Team,
+    },
+    GithubClient, UserDetailResponse,
+};
+
+/// The operations of [`GithubClient`], for applications that want to
+/// swap Github out in their tests. Depend on `dyn GithubApi` and hand
+/// in a [`GithubClient`] in production.
+///
+/// See also [`crate::fakehub::InMemoryGithub`], which answers from
+/// Fakehub's state without any HTTP at all.
+///
+/// The key lookups of the `keys` feature have default bodies that
+/// answer [`Error::Unsupported`], so implementations that don't
+/// override them keep compiling when another crate turns it on.
+#[async_trait]
+pub trait GithubApi: Send + Sync {
+    /// The URL to send a user to in order to start the OAuth workflow.
+    fn authorization_url(&self) -> String;
+
+    /// The URL to send a user to in order to start the OAuth workflow,
+    /// with a redirect, scopes, state or PKCE challenge.
+    fn authorization_url_with(&self, request: &AuthorizationRequest) -> String;
+
+    /// Exchange a login code for an access token.
+    async fn get_access_token(&self, code: &str) -> Result<GetAccessTokenResponse, Error>;
+
+    /// Exchange a login code for an access token, proving with the PKCE
+    /// verifier that we are the ones who started the authorization.
+    async fn get_access_token_with_verifier(
+        &self,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<GetAccessTokenResponse, Error>;
+
+    /// Exchange a refresh token for a new access token, and a new
+    /// refresh token.
+    async fn refresh_access_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<GetAccessTokenResponse, Error>;
+
+    /// Use an access token to query the user this token is associated with.
+    async fn get_user_detail(&self, access_token: &str) -> Result<UserDetailResponse, Error>;
+
+    /// List the organizations the user this token is associated with
+    /// belongs to.
+    async fn list_user_orgs(&self, access_token: &str) -> Result<Vec<Organization>, Error>;
+
+    /// List the teams the user this token is associated with belongs to.
+    async fn list_user_teams(&self, access_token: &str) -> Result<Vec<Team>, Error>;
+
+    /// List the email addresses of the user this token is associated
+    /// with.
+    async fn list_user_emails(&self, access_token: &str) -> Result<Vec<Email>, Error>;
+
+    /// How much a user may do in a repository.
+    async fn get_repo_permission(
+        &self,
+        access_token: &str,
+        owner: &str,
+        repo: &str,
+        username: &str,
+    ) -> Result<Permission, Error>;
+
+    /// List the repositories the user this token is associated with can
+    /// access, narrowed to the given affiliations.
+    async fn list_user_repos(
+        &self,
+        access_token: &str,
+        affiliations: &[Affiliation],
+    ) -> Result<Vec<Repository>, Error>;
+
+    /// Get the user this token is associated with, their organizations
+    /// and their teams.
+    async fn viewer_identity(&self, access_token: &str) -> Result<ViewerIdentity, Error>;
+
+    /// Get a user's public profile.
+    async fn get_user_detail_public(&self, username: &str) -> Result<UserDetailResponse, Error>;
+
+    /// List the SSH keys a user authenticates with.
+    #[cfg(feature = "keys")]
+    async fn list_ssh_keys(&self, _username: &str) -> Result<Vec<SshKey>, Error> {
+        Err(Error::Unsupported("listing SSH keys".to_owned()))
+    }
+
+    /// List the SSH keys a user signs commits with.
+    #[cfg(feature = "keys")]
+    async fn list_ssh_signing_keys(&self, _username: &str) -> Result<Vec<SshSigningKey>, Error> {
+        Err(Error::Unsupported("listing SSH signing keys".to_owned()))
+    }
+
+    /// List a user's GPG keys.
+    #[cfg(feature = "keys")]
+    async fn list_gpg_keys(&self, _username: &str) -> Result<Vec<GpgKey>, Error> {
+        Err(Error::Unsupported("listing GPG keys".to_owned()))
+    }
+}
+
+#[async_trait]
+impl GithubApi for GithubClient {
+    fn authorization_url(&self) -> String {
+        GithubClient::authorization_url(self)
+    }
+
+    fn authorization_url_with(&self, request: &AuthorizationRequest) -> String {
+        GithubClient::authorization_url_with(self, request)
+    }
+
+    async fn get_access_token(&self, code: &str) -> Result<GetAccessTokenResponse, Error> {
+        GithubClient::get_access_token(self, code).await
+    }
+
+    async fn get_access_token_with_verifier(
+        &self,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<GetAccessTokenResponse, Error> {
+        GithubClient::get_access_token_with_verifier(self, code, code_verifier).await
+    }
+
+    async fn refresh_access_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<GetAccessTokenResponse, Error> {
+        GithubClient::refresh_access_token(self, refresh_token).await
+    }
+
+    async fn get_user_detail(&self, access_token: &str) -> Result<UserDetailResponse, Error> {
+        GithubClient::get_user_detail(self, access_token).await
+    }
+
+    async fn list_user_orgs(&self, access_token: &str) -> Result<Vec<Organization>, Error> {
+        GithubClient::list_user_orgs(self, access_token).await
+    }
+
+    async fn list_user_teams(&self, access_token: &str) -> Result<Vec<Team>, Error> {
+        GithubClient::list_user_teams(self, access_token).await
+    }
+
+    async fn list_user_emails(&self, access_token: &str) -> Result<Vec<Email>, Error> {
+        GithubClient::list_user_emails(self, access_token).await
+    }
+
+    async fn get_repo_permission(
+        &self,
+        access_token: &str,
+        owner: &str,
+        repo: &str,
+        username: &str,
+    ) -> Result<Permission, Error> {
+        GithubClient::get_repo_permission(self, access_token, owner, repo, username).await
+    }
+
+    async fn list_user_repos(
+        &self,
+        access_token: &str,
+        affiliations: &[Affiliation],
+    ) -> Result<Vec<Repository>, Error> {
+        GithubClient::list_user_repos(self, access_token, affiliations).await
+    }
+
+    async fn viewer_identity(&self, access_token: &str) -> Result<ViewerIdentity, Error> {
+        GithubClient::viewer_identity(self, access_token).await
+    }
+
+    async fn get_user_detail_public(&self, username: &str) -> Result<UserDetailResponse, Error> {
+        GithubClient::get_user_detail_public(self, username).await
+    }
+
+    #[cfg(feature = "keys")]
+    async fn list_ssh_keys(&self, username: &str) -> Result<Vec<SshKey>, Error> {
+        GithubClient::list_ssh_keys(self, username).await
+    }
+
+    #[cfg(feature = "keys")]
+    async fn list_ssh_signing_keys(&self, username: &str) -> Result<Vec<SshSigningKey>, Error> {
+        GithubClient::list_ssh_signing_keys(self, username).await
+    }
+
+    #[cfg(feature = "keys")]
+    async fn list_gpg_keys(&self, username: &str) -> Result<Vec<GpgKey>, Error> {
+        GithubClient::list_gpg_keys(self, username).await
+    }
+}
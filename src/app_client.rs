@@ -0,0 +1,321 @@
+use chrono::Utc;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    client::Requester,
+    error::Error,
+    shapes::{Installation, InstallationAccessToken, InstallationTokenRequest},
+};
+#[cfg(feature = "interceptors")]
+use crate::{interceptor::Interceptor, transport::Transport};
+
+const API_BASE_URL: &str = "https://api.github.com";
+
+/// How far to backdate the issued-at time of app JWTs, allowing for
+/// clock drift between us and Github.
+const JWT_BACKDATE_SECONDS: i64 = 60;
+/// How long app JWTs are good for. Github refuses any that expire more
+/// than ten minutes into the future.
+const JWT_LIFETIME_SECONDS: i64 = 600;
+
+/// The claims Github expects in the JWT a Github App authenticates
+/// with.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct AppClaims {
+    /// Issued at, in seconds since the epoch.
+    pub iat: i64,
+    /// Expiry, in seconds since the epoch.
+    pub exp: i64,
+    /// The app id.
+    pub iss: String,
+}
+
+/// A client for acting as a Github App, rather than as an OAuth app.
+#[derive(Clone)]
+pub struct AppClient {
+    requester: Requester,
+    /// The Github App's id.
+    app_id: i64,
+    /// The app's private key, which signs its JWTs. Keep this one
+    /// private!
+    encoding_key: EncodingKey,
+    /// The base url that the client uses to communicate with Github.
+    api_base_url: String,
+}
+
+impl AppClient {
+    /// Create a new app client configured to use the public Github API.
+    /// The private key is the PEM file Github generates for the app.
+    pub fn new(app_id: i64, private_key_pem: &[u8]) -> Result<Self, Error> {
+        Self::new_with_url(app_id, private_key_pem, API_BASE_URL)
+    }
+
+    /// Create a new app client configured to use an arbitrary API
+    /// endpoint.
+    ///
+    /// See also [`crate::fakehub::Fakehub::add_app`].
+    pub fn new_with_url(
+        app_id: i64,
+        private_key_pem: &[u8],
+        api_base_url: &str,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            requester: Requester::new()?,
+            app_id,
+            encoding_key: EncodingKey::from_rsa_pem(private_key_pem)?,
+            api_base_url: api_base_url.to_owned(),
+        })
+    }
+
+    /// Run an interceptor around every request this client makes, after
+    /// any already added.
+    #[cfg(feature = "interceptors")]
+    pub fn with_interceptor(mut self, interceptor: impl Interceptor) -> Self {
+        self.requester.add_interceptor(interceptor);
+        self
+    }
+
+    /// Send requests through a transport other than the network, eg. a
+    /// [`ServiceTransport`](crate::transport::ServiceTransport) to an
+    /// in-process Fakehub.
+    #[cfg(feature = "interceptors")]
+    pub fn with_transport(mut self, transport: impl Transport) -> Self {
+        self.requester.set_transport(transport);
+        self
+    }
+
+    /// Sign a fresh JWT identifying as this app. The issued-at time is
+    /// backdated a minute to allow for clock drift.
+    pub fn jwt(&self) -> Result<String, Error> {
+        let now = Utc::now().timestamp();
+        let claims = AppClaims {
+            iat: now - JWT_BACKDATE_SECONDS,
+            exp: now + JWT_LIFETIME_SECONDS,
+            iss: self.app_id.to_string(),
+        };
+
+        Ok(jsonwebtoken::encode(
+            &Header::new(Algorithm::RS256),
+            &claims,
+            &self.encoding_key,
+        )?)
+    }
+
+    /// List every installation of this app.
+    pub async fn list_installations(&self) -> Result<Vec<Installation>, Error> {
+        let jwt = self.jwt()?;
+
+        self.requester
+            .get_all_pages(
+                &self.api_base_url,
+                "/app/installations?per_page=100",
+                "/app/installations",
+                |request| {
+                    request
+                        .bearer_auth(&jwt)
+                        .header("Accept", "application/vnd.github+json")
+                },
+            )
+            .await
+    }
+
+    /// Mint an access token for acting as one of this app's
+    /// installations, optionally narrowed to fewer repositories or
+    /// permissions.
+    pub async fn create_installation_access_token(
+        &self,
+        installation_id: i64,
+        request: &InstallationTokenRequest,
+    ) -> Result<InstallationAccessToken, Error> {
+        Ok(self
+            .requester
+            .execute(
+                self.requester
+                    .http_client
+                    .post(format!(
+                        "{}/app/installations/{}/access_tokens",
+                        self.api_base_url, installation_id
+                    ))
+                    .bearer_auth(self.jwt()?)
+                    .header("Accept", "application/vnd.github+json")
+                    .json(request),
+                "/app/installations/{installation_id}/access_tokens",
+            )
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+impl std::fmt::Debug for AppClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "AppClient {{ http_client: {:?}, app_id: {}, \
+            encoding_key: REDACTED }}",
+            self.requester.http_client, self.app_id,
+        )
+    }
+}
+
+#[cfg(all(test, feature = "fakehub"))]
+mod tests {
+    use crate::{
+        fakehub::{App, Fakehub, Installation},
+        testing::{
+            add_test_user, user_named, Audit, APP_ID, APP_PRIVATE_KEY, APP_PUBLIC_KEY,
+            INSTALLATION_ID, USER, USER_ID,
+        },
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn app_installation_token() {
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+
+        add_test_user(&fakehub).await;
+        fakehub
+            .add_app(
+                APP_ID,
+                App {
+                    name: "app".to_string(),
+                    public_key_pem: APP_PUBLIC_KEY.to_string(),
+                },
+            )
+            .await;
+        fakehub
+            .add_installation(
+                INSTALLATION_ID,
+                Installation {
+                    app_id: APP_ID,
+                    account_id: USER_ID,
+                    permissions: [("contents".to_string(), "write".to_string())].into(),
+                    repositories: Some(vec!["repo".to_string()]),
+                },
+            )
+            .await
+            .unwrap();
+
+        let app_client = AppClient::new_with_url(
+            APP_ID,
+            APP_PRIVATE_KEY.as_bytes(),
+            &fakehub.api_dot_github_dot_com_url(),
+        )
+        .unwrap();
+
+        let installations = app_client.list_installations().await.unwrap();
+
+        assert_eq!(1, installations.len());
+        assert_eq!(USER, installations[0].account.login);
+
+        // 100 to a page, every page is followed, and each goes through
+        // the client's interceptors
+        for installation_id in INSTALLATION_ID + 100..INSTALLATION_ID + 250 {
+            fakehub
+                .add_installation(
+                    installation_id,
+                    Installation {
+                        app_id: APP_ID,
+                        account_id: USER_ID,
+                        permissions: Default::default(),
+                        repositories: None,
+                    },
+                )
+                .await
+                .unwrap();
+        }
+        let audit = Audit::default();
+        let installations = app_client
+            .clone()
+            .with_interceptor(audit.clone())
+            .list_installations()
+            .await
+            .unwrap();
+
+        assert_eq!(151, installations.len());
+        assert_eq!(
+            vec!["/app/installations", "/app/installations"],
+            audit
+                .0
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(path, _, _, _)| path.as_str())
+                .collect::<Vec<_>>()
+        );
+
+        let token = app_client
+            .create_installation_access_token(
+                INSTALLATION_ID,
+                &InstallationTokenRequest {
+                    permissions: Some([("contents".to_string(), "read".to_string())].into()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        assert!(token.token.starts_with("ghs_"));
+        assert_eq!("read", token.permissions["contents"]);
+
+        // a token cannot be given more access than the installation has
+        assert!(app_client
+            .create_installation_access_token(
+                INSTALLATION_ID,
+                &InstallationTokenRequest {
+                    permissions: Some([("contents".to_string(), "admin".to_string())].into()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .is_err());
+
+        // removing a user's installation never lets a new token take the
+        // id of another installation's live one
+        let other_user_id = USER_ID + 1;
+        let other_installation_id = INSTALLATION_ID + 1;
+        fakehub.add_user(other_user_id, user_named("other")).await;
+        fakehub
+            .add_installation(
+                other_installation_id,
+                Installation {
+                    app_id: APP_ID,
+                    account_id: other_user_id,
+                    permissions: [("contents".to_string(), "read".to_string())].into(),
+                    repositories: None,
+                },
+            )
+            .await
+            .unwrap();
+        let other_token = app_client
+            .create_installation_access_token(other_installation_id, &Default::default())
+            .await
+            .unwrap();
+
+        fakehub.remove_user(USER_ID).await.unwrap();
+        add_test_user(&fakehub).await;
+        fakehub
+            .add_installation(
+                INSTALLATION_ID,
+                Installation {
+                    app_id: APP_ID,
+                    account_id: USER_ID,
+                    permissions: [("contents".to_string(), "write".to_string())].into(),
+                    repositories: Some(vec!["repo".to_string()]),
+                },
+            )
+            .await
+            .unwrap();
+        let token = app_client
+            .create_installation_access_token(INSTALLATION_ID, &Default::default())
+            .await
+            .unwrap();
+
+        assert_ne!(other_token.token, token.token);
+
+        fakehub.shutdown().await;
+    }
+}
@@ -0,0 +1,325 @@
+//! A tower middleware that authenticates requests by the Github token
+//! they carry, for APIs called directly by CLIs and scripts rather than
+//! by browsers.
+//!
+//! Requests without a token, or with one Github doesn't recognize, are
+//! answered with 401. Requests from users the policy turns away are
+//! answered with 403. Everything else reaches the inner service with
+//! the user's [`UserDetailResponse`] in its extensions.
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use ghoauth::{bearer::BearerAuthLayer, GithubClient, UserDetailResponse};
+//! # fn layer(github_client: GithubClient) {
+//! let layer = BearerAuthLayer::new(github_client, |user: &UserDetailResponse| {
+//!     ["alice", "bob"].contains(&user.login.as_str())
+//! })
+//! .cache_ttl(Duration::from_secs(60));
+//! # }
+//! ```
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use http::{header, HeaderValue, Request, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{GithubClient, UserDetailResponse};
+
+/// How long a resolved token is trusted before asking Github again.
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Decides whether an authenticated user may use the service.
+#[async_trait]
+pub trait AuthorizationPolicy: Send + Sync + 'static {
+    /// Whether to let the user through. The token is theirs, for
+    /// policies that need to ask Github more about them.
+    async fn authorize(&self, access_token: &str, user: &UserDetailResponse) -> bool;
+}
+
+#[async_trait]
+impl<F> AuthorizationPolicy for F
+where
+    F: Fn(&UserDetailResponse) -> bool + Send + Sync + 'static,
+{
+    async fn authorize(&self, _access_token: &str, user: &UserDetailResponse) -> bool {
+        self(user)
+    }
+}
+
+/// Lets every user Github recognizes through.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AllowAll;
+
+#[async_trait]
+impl AuthorizationPolicy for AllowAll {
+    async fn authorize(&self, _access_token: &str, _user: &UserDetailResponse) -> bool {
+        true
+    }
+}
+
+struct CachedUser {
+    user: UserDetailResponse,
+    resolved_at: Instant,
+}
+
+struct Shared<P> {
+    github_client: GithubClient,
+    policy: P,
+    /// Users by the SHA-256 of their token, so the cache doesn't hold
+    /// live tokens.
+    cache: Mutex<HashMap<Vec<u8>, CachedUser>>,
+}
+
+impl<P: AuthorizationPolicy> Shared<P> {
+    async fn resolve(
+        &self,
+        access_token: &str,
+        cache_ttl: Duration,
+    ) -> Result<UserDetailResponse, crate::Error> {
+        let key = Sha256::digest(access_token.as_bytes()).to_vec();
+
+        if let Some(cached) = self.cache.lock().unwrap().get(&key) {
+            if cached.resolved_at.elapsed() < cache_ttl {
+                return Ok(cached.user.clone());
+            }
+        }
+
+        let user = self.github_client.get_user_detail(access_token).await?;
+        let mut cache = self.cache.lock().unwrap();
+
+        cache.retain(|_, cached| cached.resolved_at.elapsed() < cache_ttl);
+        cache.insert(
+            key,
+            CachedUser {
+                user: user.clone(),
+                resolved_at: Instant::now(),
+            },
+        );
+
+        Ok(user)
+    }
+}
+
+/// Wraps services in [`BearerAuth`].
+pub struct BearerAuthLayer<P> {
+    shared: Arc<Shared<P>>,
+    cache_ttl: Duration,
+}
+
+impl<P: AuthorizationPolicy> BearerAuthLayer<P> {
+    pub fn new(github_client: GithubClient, policy: P) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                github_client,
+                policy,
+                cache: Mutex::new(HashMap::new()),
+            }),
+            cache_ttl: DEFAULT_CACHE_TTL,
+        }
+    }
+
+    /// How long to trust a resolved token before asking Github about it
+    /// again. Revoked tokens keep working for up to this long. Defaults
+    /// to five minutes.
+    pub fn cache_ttl(mut self, cache_ttl: Duration) -> Self {
+        self.cache_ttl = cache_ttl;
+        self
+    }
+}
+
+impl<P> Clone for BearerAuthLayer<P> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            cache_ttl: self.cache_ttl,
+        }
+    }
+}
+
+impl<S, P> Layer<S> for BearerAuthLayer<P> {
+    type Service = BearerAuth<S, P>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BearerAuth {
+            inner,
+            shared: self.shared.clone(),
+            cache_ttl: self.cache_ttl,
+        }
+    }
+}
+
+/// Authenticates requests by their bearer token before passing them on
+/// to the inner service.
+pub struct BearerAuth<S, P> {
+    inner: S,
+    shared: Arc<Shared<P>>,
+    cache_ttl: Duration,
+}
+
+impl<S: Clone, P> Clone for BearerAuth<S, P> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            shared: self.shared.clone(),
+            cache_ttl: self.cache_ttl,
+        }
+    }
+}
+
+impl<S, P, ReqBody, ResBody> Service<Request<ReqBody>> for BearerAuth<S, P>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    P: AuthorizationPolicy,
+    ReqBody: Send + 'static,
+    ResBody: Default,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        // the clone may not be ready, so swap it for the one that is
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let shared = self.shared.clone();
+        let cache_ttl = self.cache_ttl;
+
+        Box::pin(async move {
+            let access_token = match bearer_token(&req) {
+                Some(access_token) => access_token,
+                None => return Ok(rejection(StatusCode::UNAUTHORIZED)),
+            };
+            let user = match shared.resolve(&access_token, cache_ttl).await {
+                Ok(user) => user,
+                Err(crate::Error::Http(Some(401), _)) => {
+                    return Ok(rejection(StatusCode::UNAUTHORIZED))
+                }
+                Err(_) => return Ok(rejection(StatusCode::BAD_GATEWAY)),
+            };
+
+            if !shared.policy.authorize(&access_token, &user).await {
+                return Ok(rejection(StatusCode::FORBIDDEN));
+            }
+
+            req.extensions_mut().insert(user);
+
+            inner.call(req).await
+        })
+    }
+}
+
+/// Pull the token out of an `Authorization: Bearer` header, also
+/// accepting Github's own `token` scheme.
+fn bearer_token<B>(req: &Request<B>) -> Option<String> {
+    let authorization = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = authorization.split_once(' ')?;
+
+    if scheme.eq_ignore_ascii_case("bearer") || scheme.eq_ignore_ascii_case("token") {
+        Some(token.trim().to_owned())
+    } else {
+        None
+    }
+}
+
+fn rejection<B: Default>(status: StatusCode) -> Response<B> {
+    let mut response = Response::new(B::default());
+
+    *response.status_mut() = status;
+    if status == StatusCode::UNAUTHORIZED {
+        response
+            .headers_mut()
+            .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    }
+
+    response
+}
+
+#[cfg(all(test, feature = "fakehub"))]
+mod tests {
+    use http::StatusCode;
+    use tower::{service_fn, Layer, ServiceExt};
+
+    use crate::{
+        fakehub::Fakehub,
+        testing::{add_test_user, CLIENT_ID, CLIENT_SECRET, USER, USER_ID},
+        UserDetailResponse,
+    };
+
+    use super::BearerAuthLayer;
+
+    #[tokio::test]
+    async fn bearer_auth_layer() {
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+        add_test_user(&fakehub).await;
+
+        let code = fakehub.get_code(USER_ID).await.unwrap();
+        let token = github_client.get_access_token(&code).await.unwrap();
+
+        // a bare tower service, to show the layer needs no particular
+        // framework
+        let echo_login = service_fn(|req: http::Request<String>| async move {
+            let user = req.extensions().get::<UserDetailResponse>().unwrap();
+
+            Ok::<_, std::convert::Infallible>(http::Response::new(user.login.clone()))
+        });
+        let request = |token: Option<&str>| {
+            let mut request = http::Request::builder();
+            if let Some(token) = token {
+                request = request.header("Authorization", format!("Bearer {}", token));
+            }
+            request.body(String::new()).unwrap()
+        };
+
+        let allow_user =
+            BearerAuthLayer::new(github_client.clone(), |user: &UserDetailResponse| {
+                user.id == USER_ID
+            });
+        let response = allow_user
+            .layer(echo_login)
+            .oneshot(request(Some(&token.access_token)))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(USER, response.into_body());
+
+        let response = allow_user
+            .layer(echo_login)
+            .oneshot(request(None))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+
+        let response = allow_user
+            .layer(echo_login)
+            .oneshot(request(Some("not a token")))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, response.status());
+
+        let deny_everyone = BearerAuthLayer::new(github_client, |_: &UserDetailResponse| false);
+        let response = deny_everyone
+            .layer(echo_login)
+            .oneshot(request(Some(&token.access_token)))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+
+        fakehub.shutdown().await;
+    }
+}
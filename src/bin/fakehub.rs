@@ -0,0 +1,128 @@
+//! Fakehub on its own, for running apps against without writing any
+//! Rust. It serves the clients, users and the rest of a config file, and
+//! picks up changes to the file as they're made.
+//!
+//! ```sh
+//! cargo run --features fakehub-bin --bin fakehub -- testdata/fakehub.toml
+//! ```
+
+use std::{
+    net::IpAddr,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, SystemTime},
+};
+
+use clap::Parser;
+use ghoauth::fakehub::{Fakehub, FakehubConfig};
+
+/// How often to check whether the config file has changed.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Parser)]
+struct Args {
+    /// The config file, in TOML, JSON or YAML.
+    config: PathBuf,
+    /// The address to bind to, instead of the config's.
+    #[clap(long = "host")]
+    host: Option<IpAddr>,
+    /// The port of the web host, instead of the config's.
+    #[clap(long = "web-port")]
+    web_port: Option<u16>,
+    /// The port of the API host, instead of the config's.
+    #[clap(long = "api-port")]
+    api_port: Option<u16>,
+    /// Don't reload the config when the file changes.
+    #[clap(long = "no-reload")]
+    no_reload: bool,
+}
+
+impl Args {
+    fn read_config(&self) -> ghoauth::fakehub::Result<FakehubConfig> {
+        let mut config = FakehubConfig::from_path(&self.config)?;
+
+        config.host = self.host.unwrap_or(config.host);
+        config.web_port = self.web_port.unwrap_or(config.web_port);
+        config.api_port = self.api_port.unwrap_or(config.api_port);
+
+        Ok(config)
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+
+    tracing_subscriber::fmt::init();
+
+    let mut config = match args.read_config() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let fakehub = match Fakehub::from_config(&config).await {
+        Ok(fakehub) => fakehub,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    println!("Now serving github.com on {}", fakehub.github_dot_com_url());
+    println!(
+        "Now serving api.github.com on {}",
+        fakehub.api_dot_github_dot_com_url()
+    );
+    println!("Now serving the admin API on {}", fakehub.admin_url());
+
+    let mut modified = modified_at(&args.config);
+    let mut reload = tokio::time::interval(RELOAD_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = reload.tick(), if !args.no_reload => {
+                let now = modified_at(&args.config);
+
+                if now == modified {
+                    continue;
+                }
+                modified = now;
+
+                // a half-written or broken file leaves things as they were
+                let reloaded = match args.read_config() {
+                    Ok(reloaded) => reloaded,
+                    Err(e) => {
+                        tracing::error!("not reloading {}: {}", args.config.display(), e);
+                        continue;
+                    }
+                };
+
+                if (reloaded.host, reloaded.web_port, reloaded.api_port, reloaded.flavor)
+                    != (config.host, config.web_port, config.api_port, config.flavor)
+                {
+                    tracing::warn!("the host, ports and flavor only change on a restart");
+                }
+
+                fakehub.reset().await;
+                match fakehub.load(&reloaded.fixtures).await {
+                    Ok(()) => tracing::info!("reloaded {}", args.config.display()),
+                    Err(e) => tracing::error!("reloading {} failed: {}", args.config.display(), e),
+                }
+                config = reloaded;
+            }
+        }
+    }
+
+    fakehub.shutdown().await;
+
+    ExitCode::SUCCESS
+}
+
+fn modified_at(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,501 @@
+//! A synchronous [`GithubClient`], for scripts and tools that would
+//! rather not start an async runtime just to log someone in.
+//!
+//! It mirrors [`crate::GithubClient`] call for call, sharing how it
+//! builds URLs and follows pages, and must not be used from within an
+//! async runtime. Interceptors and transports are async, so they're
+//! only on the async client.
+//!
+//! ```no_run
+//! # use ghoauth::blocking::GithubClient;
+//! # fn login(code: &str) -> Result<(), ghoauth::Error> {
+//! let github_client = GithubClient::new("client id", "client secret")?;
+//! let token = github_client.get_access_token(code)?;
+//! let user = github_client.get_user_detail(&token.access_token)?;
+//!
+//! println!("Hello, {}!", user.login);
+//! # Ok(())
+//! # }
+//! ```
+
+use reqwest::{
+    blocking::{Client as ReqwestClient, RequestBuilder, Response},
+    StatusCode, Url,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+#[cfg(feature = "keys")]
+use crate::keys::{GpgKey, SshKey, SshSigningKey};
+use crate::{
+    client::{authorization_url_with, next_page, parse_authorize_url, path_segment},
+    error::Error,
+    graphql::{
+        self, ViewerData, ViewerIdentity, ViewerLoginData, VIEWER_IDENTITY_PAGE_SIZE,
+        VIEWER_IDENTITY_QUERY, VIEWER_LOGIN_QUERY,
+    },
+    provider::Provider,
+    shapes::{
+        AccessTokenExchange, Affiliation, AuthorizationRequest, CollaboratorPermission, Email,
+        GetAccessTokenResponse, Organization, Permission, Repository, Team,
+    },
+    telemetry::{self, RequestSpan},
+    UserDetailResponse,
+};
+
+/// A blocking client for interacting with Github programmatically.
+#[derive(Clone)]
+pub struct GithubClient {
+    http_client: ReqwestClient,
+    /// The github client id. This one gets exposed publicly.
+    client_id: String,
+    /// The secret key that is known only to us and Github. Keep this
+    /// one private!
+    client_secret: String,
+    /// Where Github, or a forge like it, is.
+    provider: Provider,
+    /// The provider's authorize URL, checked when the client is made.
+    authorize_url: Url,
+}
+
+impl GithubClient {
+    /// Create a new Github client configured to use the public Github
+    /// API.
+    pub fn new(client_id: &str, client_secret: &str) -> Result<Self, Error> {
+        Self::new_with_provider(client_id, client_secret, Provider::github())
+    }
+
+    /// Create a new Github client configured to use arbitrary API
+    /// endpoints.
+    ///
+    /// See also [`crate::fakehub::Fakehub::github_dot_com_url`].
+    pub fn new_with_urls(
+        client_id: &str,
+        client_secret: &str,
+        base_url: &str,
+        api_base_url: &str,
+    ) -> Result<Self, Error> {
+        Self::new_with_provider(
+            client_id,
+            client_secret,
+            Provider::custom(base_url, api_base_url),
+        )
+    }
+
+    /// Create a client for a Github Enterprise Server, a Gitea or
+    /// Forgejo instance, or any other forge that speaks Github's OAuth.
+    pub fn new_with_provider(
+        client_id: &str,
+        client_secret: &str,
+        provider: Provider,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            http_client: reqwest::blocking::ClientBuilder::new()
+                .user_agent("Rust/request/ghoauth")
+                .build()?,
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.to_owned(),
+            authorize_url: parse_authorize_url(&provider)?,
+            provider,
+        })
+    }
+
+    /// Where the client finds Github, or the forge standing in for it.
+    pub fn provider(&self) -> &Provider {
+        &self.provider
+    }
+
+    /// The URL to send a user to in order to start the OAuth workflow.
+    pub fn authorization_url(&self) -> String {
+        telemetry::authorization_url_issued();
+
+        format!(
+            "{}?client_id={}",
+            self.provider.authorize_url(),
+            self.client_id
+        )
+    }
+
+    /// The URL to send a user to in order to start the OAuth workflow,
+    /// with a redirect, scopes, state or PKCE challenge.
+    pub fn authorization_url_with(&self, request: &AuthorizationRequest) -> String {
+        authorization_url_with(&self.authorize_url, &self.client_id, request)
+    }
+
+    /// Exchange a login code for an access token.
+    pub fn get_access_token(&self, code: &str) -> Result<GetAccessTokenResponse, Error> {
+        self.exchange_code(code, None)
+    }
+
+    /// Exchange a login code for an access token, proving with the PKCE
+    /// verifier that we are the ones who started the authorization.
+    pub fn get_access_token_with_verifier(
+        &self,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<GetAccessTokenResponse, Error> {
+        self.exchange_code(code, Some(code_verifier))
+    }
+
+    fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: Option<&str>,
+    ) -> Result<GetAccessTokenResponse, Error> {
+        let mut params = vec![
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+            ("code", code),
+        ];
+
+        if let Some(code_verifier) = code_verifier {
+            params.push(("code_verifier", code_verifier));
+        }
+        if self.provider.send_grant_type {
+            params.push(("grant_type", "authorization_code"));
+        }
+
+        let token = self.request_token(&params);
+
+        telemetry::code_exchanged(&token);
+
+        token
+    }
+
+    /// Exchange a refresh token for a new access token, and a new
+    /// refresh token. Only apps with expiring user tokens get refresh
+    /// tokens, and each can only be used once.
+    pub fn refresh_access_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<GetAccessTokenResponse, Error> {
+        self.request_token(&[
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ])
+    }
+
+    fn request_token(&self, params: &[(&str, &str)]) -> Result<GetAccessTokenResponse, Error> {
+        let response = self.execute(
+            self.http_client
+                .post(self.provider.token_url())
+                .form(params)
+                .header("Accept", "application/json"),
+            "/login/oauth/access_token",
+        )?;
+
+        if response.status() == StatusCode::BAD_REQUEST {
+            return Err(AccessTokenExchange::refusal(&response.text()?));
+        }
+
+        response
+            .error_for_status()?
+            .json::<AccessTokenExchange>()?
+            .into_result()
+    }
+
+    /// Use an access token to query the user this token is associated with.
+    pub fn get_user_detail(&self, access_token: &str) -> Result<UserDetailResponse, Error> {
+        let user =
+            self.get_authenticated_at::<Value>(access_token, &self.provider.user_path, "/user")?;
+
+        self.provider.user_detail(&user)
+    }
+
+    /// List the organizations the user this token is associated with
+    /// belongs to. Needs the `read:org` scope to see private
+    /// memberships.
+    pub fn list_user_orgs(&self, access_token: &str) -> Result<Vec<Organization>, Error> {
+        self.get_all_pages(access_token, "/user/orgs?per_page=100", "/user/orgs")
+    }
+
+    /// List the teams the user this token is associated with belongs to,
+    /// across all of their organizations. Needs the `read:org` scope.
+    pub fn list_user_teams(&self, access_token: &str) -> Result<Vec<Team>, Error> {
+        self.get_all_pages(access_token, "/user/teams?per_page=100", "/user/teams")
+    }
+
+    /// List the email addresses of the user this token is associated
+    /// with. Needs the `user:email` scope.
+    pub fn list_user_emails(&self, access_token: &str) -> Result<Vec<Email>, Error> {
+        self.get_all_pages(access_token, "/user/emails?per_page=100", "/user/emails")
+    }
+
+    /// How much a user may do in a repository, as seen by the user this
+    /// token is associated with, who must be able to see the repository.
+    pub fn get_repo_permission(
+        &self,
+        access_token: &str,
+        owner: &str,
+        repo: &str,
+        username: &str,
+    ) -> Result<Permission, Error> {
+        Ok(self
+            .get_authenticated_at::<CollaboratorPermission>(
+                access_token,
+                &format!(
+                    "/repos/{}/{}/collaborators/{}/permission",
+                    path_segment(owner)?,
+                    path_segment(repo)?,
+                    path_segment(username)?
+                ),
+                "/repos/{owner}/{repo}/collaborators/{username}/permission",
+            )?
+            .level())
+    }
+
+    /// List the repositories the user this token is associated with can
+    /// access, narrowed to the given affiliations. Without any, Github
+    /// lists those of every affiliation.
+    pub fn list_user_repos(
+        &self,
+        access_token: &str,
+        affiliations: &[Affiliation],
+    ) -> Result<Vec<Repository>, Error> {
+        let mut path = "/user/repos?per_page=100".to_owned();
+
+        if !affiliations.is_empty() {
+            let affiliations = affiliations
+                .iter()
+                .map(Affiliation::as_str)
+                .collect::<Vec<_>>()
+                .join(",");
+
+            path.push_str(&format!("&affiliation={}", affiliations));
+        }
+
+        self.get_all_pages(access_token, &path, "/user/repos")
+    }
+
+    /// Get every page of a list, following the `Link` to the next page
+    /// Github answers with until there isn't one.
+    fn get_all_pages<T: DeserializeOwned>(
+        &self,
+        access_token: &str,
+        path: &str,
+        route: &'static str,
+    ) -> Result<Vec<T>, Error> {
+        let api_base_url = &self.provider.api_base_url;
+        let mut url = format!("{}{}", api_base_url, path);
+        let mut items = Vec::new();
+
+        loop {
+            let response = self
+                .execute(
+                    self.http_client
+                        .get(&url)
+                        .header("Authorization", format!("token {}", access_token))
+                        .header("Accept", "application/json"),
+                    route,
+                )?
+                .error_for_status()?;
+            let next = next_page(api_base_url, &url, response.headers())?;
+
+            items.extend(response.json::<Vec<T>>()?);
+
+            match next {
+                Some(next) => url = next,
+                None => return Ok(items),
+            }
+        }
+    }
+
+    /// Get a path the route template of which, for tracing, differs
+    /// from the path itself.
+    fn get_authenticated_at<T: DeserializeOwned>(
+        &self,
+        access_token: &str,
+        path: &str,
+        route: &'static str,
+    ) -> Result<T, Error> {
+        Ok(self
+            .execute(
+                self.http_client
+                    .get(format!("{}{}", self.provider.api_base_url, path))
+                    .header("Authorization", format!("token {}", access_token))
+                    .header("Accept", "application/json"),
+                route,
+            )?
+            .error_for_status()?
+            .json()?)
+    }
+
+    /// Send a GraphQL query, with its variables, as the user this token
+    /// is associated with. Errors in Github's answer, even alongside
+    /// partial data, are reported as [`Error::GraphQL`].
+    pub fn graphql<V: Serialize, T: DeserializeOwned>(
+        &self,
+        access_token: &str,
+        query: &str,
+        variables: &V,
+    ) -> Result<T, Error> {
+        self.execute(
+            self.http_client
+                .post(self.provider.graphql_url()?)
+                .header("Authorization", format!("bearer {}", access_token))
+                .header("Accept", "application/json")
+                .json(&graphql::Request { query, variables }),
+            "/graphql",
+        )?
+        .error_for_status()?
+        .json::<graphql::Response<T>>()?
+        .into_result()
+    }
+
+    /// Get the user this token is associated with, their organizations
+    /// and their teams, in two GraphQL requests: one for their login,
+    /// and one for everything else. Needs the `read:org` scope to see
+    /// private memberships. More than 100 organizations, or teams in
+    /// one, is an [`Error::Incomplete`].
+    pub fn viewer_identity(&self, access_token: &str) -> Result<ViewerIdentity, Error> {
+        let login = self
+            .graphql::<_, ViewerLoginData>(access_token, VIEWER_LOGIN_QUERY, &json!({}))?
+            .viewer
+            .login;
+
+        self.graphql::<_, ViewerData>(
+            access_token,
+            VIEWER_IDENTITY_QUERY,
+            &json!({ "first": VIEWER_IDENTITY_PAGE_SIZE, "login": login }),
+        )?
+        .try_into()
+    }
+
+    /// Get a user's public profile.
+    pub fn get_user_detail_public(&self, username: &str) -> Result<UserDetailResponse, Error> {
+        Ok(self
+            .execute(
+                self.http_client
+                    .get(format!(
+                        "{}/users/{}",
+                        self.provider.api_base_url,
+                        path_segment(username)?
+                    ))
+                    .header("Accept", "application/json"),
+                "/users/{username}",
+            )?
+            .error_for_status()?
+            .json()?)
+    }
+
+    /// List the SSH keys a user authenticates with.
+    #[cfg(feature = "keys")]
+    pub fn list_ssh_keys(&self, username: &str) -> Result<Vec<SshKey>, Error> {
+        self.get_public(
+            &format!("/users/{}/keys?per_page=100", path_segment(username)?),
+            "/users/{username}/keys",
+        )
+    }
+
+    /// List the SSH keys a user signs commits with.
+    #[cfg(feature = "keys")]
+    pub fn list_ssh_signing_keys(&self, username: &str) -> Result<Vec<SshSigningKey>, Error> {
+        self.get_public(
+            &format!(
+                "/users/{}/ssh_signing_keys?per_page=100",
+                path_segment(username)?
+            ),
+            "/users/{username}/ssh_signing_keys",
+        )
+    }
+
+    /// List a user's GPG keys.
+    #[cfg(feature = "keys")]
+    pub fn list_gpg_keys(&self, username: &str) -> Result<Vec<GpgKey>, Error> {
+        self.get_public(
+            &format!("/users/{}/gpg_keys?per_page=100", path_segment(username)?),
+            "/users/{username}/gpg_keys",
+        )
+    }
+
+    #[cfg(feature = "keys")]
+    fn get_public<T: DeserializeOwned>(&self, path: &str, route: &'static str) -> Result<T, Error> {
+        Ok(self
+            .execute(
+                self.http_client
+                    .get(format!("{}{}", self.provider.api_base_url, path))
+                    .header("Accept", "application/json"),
+                route,
+            )?
+            .error_for_status()?
+            .json()?)
+    }
+
+    /// Send a request to Github. Every request goes through here, so
+    /// it's where they're traced.
+    fn execute(&self, request: RequestBuilder, route: &'static str) -> Result<Response, Error> {
+        let request = request.build()?;
+        let span = RequestSpan::new(request.method(), route);
+        let response = span.in_scope(|| self.http_client.execute(request));
+
+        match &response {
+            Ok(response) => span.record(Some(response.status()), Some(response.headers())),
+            Err(error) => span.record(error.status(), None),
+        }
+
+        Ok(response?)
+    }
+}
+
+impl std::fmt::Debug for GithubClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "GithubClient {{ http_client: {:?}, client_id: {}, \
+            client_secret: REDACTED }}",
+            self.http_client, self.client_id,
+        )
+    }
+}
+
+#[cfg(all(test, feature = "fakehub"))]
+mod tests {
+    use crate::{
+        fakehub::Fakehub,
+        testing::{add_test_user, CLIENT_ID, CLIENT_SECRET, USER, USER_ID},
+    };
+
+    use super::GithubClient;
+
+    #[test]
+    fn blocking_oauth_flow() {
+        // Fakehub lives on a runtime of its own, since the blocking
+        // client refuses to run inside one
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let (fakehub, code) = runtime.block_on(async {
+            let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+            fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+            add_test_user(&fakehub).await;
+            for i in 0..150 {
+                fakehub
+                    .add_email(USER_ID, &format!("user{}@example.com", i), true)
+                    .await
+                    .unwrap();
+            }
+            let code = fakehub.get_code(USER_ID).await.unwrap();
+
+            (fakehub, code)
+        });
+
+        let github_client = GithubClient::new_with_urls(
+            CLIENT_ID,
+            CLIENT_SECRET,
+            &fakehub.github_dot_com_url(),
+            &fakehub.api_dot_github_dot_com_url(),
+        )
+        .unwrap();
+        let token = github_client.get_access_token(&code).unwrap();
+        let user_detail = github_client.get_user_detail(&token.access_token).unwrap();
+
+        assert_eq!(USER, user_detail.login);
+        assert!(github_client.get_user_detail("not a token").is_err());
+
+        // lists follow every page, like the async client's
+        let emails = github_client.list_user_emails(&token.access_token).unwrap();
+        assert_eq!(150, emails.len());
+
+        runtime.block_on(fakehub.shutdown());
+    }
+}
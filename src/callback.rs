@@ -0,0 +1,233 @@
+use reqwest::Url;
+
+use crate::error::Error;
+
+/// Where Github sent the user back to once they were done on its
+/// authorization page: either with a code to exchange for a token, or
+/// with the reason there isn't one.
+///
+/// ```
+/// # use ghoauth::{AuthorizationErrorCode, Callback};
+/// # fn main() -> Result<(), ghoauth::Error> {
+/// let callback = Callback::from_url(
+///     "https://example.com/callback?error=access_denied&state=xyz",
+/// )?;
+///
+/// match callback {
+///     Callback::Code(code) => println!("exchange {:?}", code),
+///     Callback::Error(error) => assert_eq!(AuthorizationErrorCode::AccessDenied, error.error),
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Callback {
+    Code(AuthorizationCode),
+    Error(AuthorizationError),
+}
+
+impl Callback {
+    /// Parse the full URL Github redirected the user to.
+    pub fn from_url(url: &str) -> Result<Self, Error> {
+        let url =
+            Url::parse(url).map_err(|error| Error::Callback(format!("invalid URL: {}", error)))?;
+
+        Self::from_pairs(url.query_pairs())
+    }
+
+    /// Parse just the query string of the URL Github redirected the user
+    /// to, with or without its leading `?`.
+    pub fn from_query(query: &str) -> Result<Self, Error> {
+        // hang the query off a throwaway URL to borrow its decoding
+        let mut url = Url::parse("http://callback.invalid/").unwrap();
+
+        url.set_query(Some(query.strip_prefix('?').unwrap_or(query)));
+
+        Self::from_pairs(url.query_pairs())
+    }
+
+    fn from_pairs<'a>(
+        pairs: impl Iterator<Item = (std::borrow::Cow<'a, str>, std::borrow::Cow<'a, str>)>,
+    ) -> Result<Self, Error> {
+        let mut code = None;
+        let mut state = None;
+        let mut error = None;
+        let mut error_description = None;
+        let mut error_uri = None;
+
+        for (name, value) in pairs {
+            let parameter = match name.as_ref() {
+                "code" => &mut code,
+                "state" => &mut state,
+                "error" => &mut error,
+                "error_description" => &mut error_description,
+                "error_uri" => &mut error_uri,
+                _ => continue,
+            };
+
+            // a parameter given twice is either a broken redirect or
+            // someone tampering with it
+            if parameter.replace(value.into_owned()).is_some() {
+                return Err(Error::Callback(format!("{} is given more than once", name)));
+            }
+        }
+
+        match (code, error) {
+            (_, Some(error)) => Ok(Self::Error(AuthorizationError {
+                error: AuthorizationErrorCode::from(error.as_str()),
+                error_description,
+                error_uri,
+                state,
+            })),
+            (Some(code), None) if !code.is_empty() => {
+                Ok(Self::Code(AuthorizationCode { code, state }))
+            }
+            _ => Err(Error::Callback("missing the code parameter".to_owned())),
+        }
+    }
+
+    /// The state the authorization was started with, which Github
+    /// echoes back either way.
+    pub fn state(&self) -> Option<&str> {
+        match self {
+            Self::Code(code) => code.state.as_deref(),
+            Self::Error(error) => error.state.as_deref(),
+        }
+    }
+
+    /// Check the callback carries the state the authorization was
+    /// started with, so it can't have been forged by someone else.
+    pub fn verify_state(self, expected: &str) -> Result<Self, Error> {
+        match self.state() {
+            Some(state) if state == expected => Ok(self),
+            Some(_) => Err(Error::Callback("state does not match".to_owned())),
+            None => Err(Error::Callback("missing the state parameter".to_owned())),
+        }
+    }
+}
+
+/// A code to exchange for an access token, with
+/// [`crate::GithubClient::get_access_token`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct AuthorizationCode {
+    pub code: String,
+    pub state: Option<String>,
+}
+
+// Custom debug printer omits the code, which can be exchanged for a
+// token until it expires.
+impl std::fmt::Debug for AuthorizationCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "AuthorizationCode {{ code: REDACTED, state: {:?} }}",
+            self.state
+        )
+    }
+}
+
+/// Github's reason for not handing out a code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthorizationError {
+    pub error: AuthorizationErrorCode,
+    pub error_description: Option<String>,
+    pub error_uri: Option<String>,
+    pub state: Option<String>,
+}
+
+impl std::fmt::Display for AuthorizationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.error_description {
+            Some(description) => write!(f, "{}: {}", self.error, description),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+impl std::error::Error for AuthorizationError {}
+
+/// The errors Github redirects back with. See
+/// https://docs.github.com/en/apps/oauth-apps/maintaining-oauth-apps/troubleshooting-authorization-request-errors
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuthorizationErrorCode {
+    /// The user clicked Cancel rather than authorizing the app.
+    AccessDenied,
+    /// The redirect URI doesn't fall under the app's callback URL.
+    RedirectUriMismatch,
+    /// The app has been suspended.
+    ApplicationSuspended,
+    /// Any other error, as Github sent it.
+    Other(String),
+}
+
+impl AuthorizationErrorCode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::AccessDenied => "access_denied",
+            Self::RedirectUriMismatch => "redirect_uri_mismatch",
+            Self::ApplicationSuspended => "application_suspended",
+            Self::Other(error) => error,
+        }
+    }
+}
+
+impl From<&str> for AuthorizationErrorCode {
+    fn from(value: &str) -> Self {
+        match value {
+            "access_denied" => Self::AccessDenied,
+            "redirect_uri_mismatch" => Self::RedirectUriMismatch,
+            "application_suspended" => Self::ApplicationSuspended,
+            _ => Self::Other(value.to_owned()),
+        }
+    }
+}
+
+impl std::fmt::Display for AuthorizationErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuthorizationCode, AuthorizationErrorCode, Callback};
+
+    #[test]
+    fn oauth_callback() {
+        let callback = Callback::from_url("https://example.com/callback?code=abc&state=xyz")
+            .unwrap()
+            .verify_state("xyz")
+            .unwrap();
+        assert_eq!(
+            Callback::Code(AuthorizationCode {
+                code: "abc".to_string(),
+                state: Some("xyz".to_string()),
+            }),
+            callback
+        );
+        assert!(!format!("{:?}", callback).contains("abc"));
+
+        let callback = Callback::from_query(
+            "?error=redirect_uri_mismatch&error_description=The+redirect_uri+MUST+match",
+        )
+        .unwrap();
+        match &callback {
+            Callback::Error(error) => {
+                assert_eq!(AuthorizationErrorCode::RedirectUriMismatch, error.error);
+                assert_eq!(
+                    Some("The redirect_uri MUST match"),
+                    error.error_description.as_deref()
+                );
+            }
+            callback => panic!("expected an error, got {:?}", callback),
+        }
+        assert!(callback.verify_state("xyz").is_err());
+
+        assert!(Callback::from_query("code=abc&state=xyz")
+            .unwrap()
+            .verify_state("abc")
+            .is_err());
+        assert!(Callback::from_query("state=xyz").is_err());
+        assert!(Callback::from_query("code=abc&code=def").is_err());
+    }
+}
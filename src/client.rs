@@ -1,30 +1,55 @@
-use crate::{error::Error, shapes::GetAccessTokenResponse, UserDetailResponse};
+#[cfg(feature = "interceptors")]
+use std::sync::Arc;
 
-use reqwest::Client as ReqwestClient;
+#[cfg(feature = "keys")]
+use crate::keys::{GpgKey, SshKey, SshSigningKey};
+use crate::{
+    error::Error,
+    graphql::{
+        self, ViewerData, ViewerIdentity, ViewerLoginData, VIEWER_IDENTITY_PAGE_SIZE,
+        VIEWER_IDENTITY_QUERY, VIEWER_LOGIN_QUERY,
+    },
+    provider::Provider,
+    shapes::{
+        AccessTokenExchange, Affiliation, AuthorizationRequest, CollaboratorPermission, Email,
+        GetAccessTokenResponse, Organization, Permission, Repository, Team,
+    },
+    telemetry::{self, RequestSpan},
+    UserDetailResponse,
+};
+#[cfg(feature = "interceptors")]
+use crate::{
+    interceptor::{self, Interceptor},
+    transport::Transport,
+};
 
-const BASE_URL: &str = "https://github.com";
-const API_BASE_URL: &str = "https://api.github.com";
+use reqwest::{
+    header::{HeaderMap, LINK},
+    Client as ReqwestClient, RequestBuilder, Response, StatusCode, Url,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
 
 /// A client for interacting with Github programmatically.
 #[derive(Clone)]
 pub struct GithubClient {
-    http_client: ReqwestClient,
+    requester: Requester,
     /// The github client id. This one gets exposed publicly.
     client_id: String,
     /// The secret key that is known only to us and Github. Keep this
     /// one private!
     client_secret: String,
-    /// The base url that authorization urls are based on.
-    base_url: &'static str,
-    /// The base url that the client uses to communicate with Github.
-    api_base_url: &'static str,
+    /// Where Github, or a forge like it, is.
+    provider: Provider,
+    /// The provider's authorize URL, checked when the client is made.
+    authorize_url: Url,
 }
 
 impl GithubClient {
     /// Create a new Github client configured to use the public Github
     /// API.
     pub fn new(client_id: &str, client_secret: &str) -> Result<Self, Error> {
-        Self::new_with_urls(client_id, client_secret, BASE_URL, API_BASE_URL)
+        Self::new_with_provider(client_id, client_secret, Provider::github())
     }
 
     /// Create a new Github client configured to use arbitrary API
@@ -34,83 +59,862 @@ impl GithubClient {
     pub fn new_with_urls(
         client_id: &str,
         client_secret: &str,
-        base_url: &'static str,
-        api_base_url: &'static str,
+        base_url: &str,
+        api_base_url: &str,
     ) -> Result<Self, Error> {
+        Self::new_with_provider(
+            client_id,
+            client_secret,
+            Provider::custom(base_url, api_base_url),
+        )
+    }
+
+    /// Create a client for a Github Enterprise Server, a Gitea or
+    /// Forgejo instance, or any other forge that speaks Github's OAuth.
+    pub fn new_with_provider(
+        client_id: &str,
+        client_secret: &str,
+        provider: Provider,
+    ) -> Result<Self, Error> {
+        let authorize_url = parse_authorize_url(&provider)?;
+
         Ok(Self {
-            http_client: reqwest::ClientBuilder::new()
-                .user_agent("Rust/request/ghoauth")
-                .build()?,
+            requester: Requester::new()?,
             client_id: client_id.to_owned(),
             client_secret: client_secret.to_owned(),
-            base_url,
-            api_base_url,
+            provider,
+            authorize_url,
         })
     }
 
+    /// Run an interceptor around every request this client makes, after
+    /// any already added.
+    #[cfg(feature = "interceptors")]
+    pub fn with_interceptor(mut self, interceptor: impl Interceptor) -> Self {
+        self.requester.add_interceptor(interceptor);
+        self
+    }
+
+    /// Send requests through a transport other than the network, eg. a
+    /// [`ServiceTransport`](crate::transport::ServiceTransport) to an
+    /// in-process Fakehub.
+    #[cfg(feature = "interceptors")]
+    pub fn with_transport(mut self, transport: impl Transport) -> Self {
+        self.requester.set_transport(transport);
+        self
+    }
+
+    /// Where the client finds Github, or the forge standing in for it.
+    pub fn provider(&self) -> &Provider {
+        &self.provider
+    }
+
     /// The URL to send a user to in order to start the OAuth workflow.
     pub fn authorization_url(&self) -> String {
+        telemetry::authorization_url_issued();
+
         format!(
-            "{}/login/oauth/authorize?client_id={}",
-            self.base_url, self.client_id
+            "{}?client_id={}",
+            self.provider.authorize_url(),
+            self.client_id
         )
     }
 
+    /// The URL to send a user to in order to start the OAuth workflow,
+    /// with a redirect, scopes, state or PKCE challenge.
+    pub fn authorization_url_with(&self, request: &AuthorizationRequest) -> String {
+        authorization_url_with(&self.authorize_url, &self.client_id, request)
+    }
+
     /// Exchange a login code for an access token.
     pub async fn get_access_token(&self, code: &str) -> Result<GetAccessTokenResponse, Error> {
-        let params = [
+        self.exchange_code(code, None).await
+    }
+
+    /// Exchange a login code for an access token, proving with the PKCE
+    /// verifier that we are the ones who started the authorization.
+    pub async fn get_access_token_with_verifier(
+        &self,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<GetAccessTokenResponse, Error> {
+        self.exchange_code(code, Some(code_verifier)).await
+    }
+
+    async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: Option<&str>,
+    ) -> Result<GetAccessTokenResponse, Error> {
+        let mut params = vec![
             ("client_id", self.client_id.as_str()),
             ("client_secret", self.client_secret.as_str()),
             ("code", code),
         ];
 
-        Ok(self
-            .http_client
-            .post(format!("{}/login/oauth/access_token", self.base_url))
-            .form(&params)
-            .header("Accept", "application/json")
-            .send()
+        if let Some(code_verifier) = code_verifier {
+            params.push(("code_verifier", code_verifier));
+        }
+        if self.provider.send_grant_type {
+            params.push(("grant_type", "authorization_code"));
+        }
+
+        let token = self.request_token(&params).await;
+
+        telemetry::code_exchanged(&token);
+
+        token
+    }
+
+    /// Exchange a refresh token for a new access token, and a new
+    /// refresh token. Only apps with expiring user tokens get refresh
+    /// tokens, and each can only be used once.
+    pub async fn refresh_access_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<GetAccessTokenResponse, Error> {
+        self.request_token(&[
+            ("client_id", self.client_id.as_str()),
+            ("client_secret", self.client_secret.as_str()),
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ])
+        .await
+    }
+
+    async fn request_token(
+        &self,
+        params: &[(&str, &str)],
+    ) -> Result<GetAccessTokenResponse, Error> {
+        let response = self
+            .requester
+            .execute(
+                self.requester
+                    .http_client
+                    .post(self.provider.token_url())
+                    .form(params)
+                    .header("Accept", "application/json"),
+                "/login/oauth/access_token",
+            )
+            .await?;
+
+        if response.status() == StatusCode::BAD_REQUEST {
+            return Err(AccessTokenExchange::refusal(&response.text().await?));
+        }
+
+        response
+            .error_for_status()?
+            .json::<AccessTokenExchange>()
             .await?
-            .json()
-            .await?)
+            .into_result()
     }
 
     /// Use an access token to query the user this token is associated with.
     pub async fn get_user_detail(&self, access_token: &str) -> Result<UserDetailResponse, Error> {
+        let user = self
+            .get_authenticated_at::<Value>(access_token, &self.provider.user_path, "/user")
+            .await?;
+
+        self.provider.user_detail(&user)
+    }
+
+    /// List the organizations the user this token is associated with
+    /// belongs to. Needs the `read:org` scope to see private
+    /// memberships.
+    pub async fn list_user_orgs(&self, access_token: &str) -> Result<Vec<Organization>, Error> {
+        self.get_all_pages(access_token, "/user/orgs?per_page=100", "/user/orgs")
+            .await
+    }
+
+    /// List the teams the user this token is associated with belongs to,
+    /// across all of their organizations. Needs the `read:org` scope.
+    pub async fn list_user_teams(&self, access_token: &str) -> Result<Vec<Team>, Error> {
+        self.get_all_pages(access_token, "/user/teams?per_page=100", "/user/teams")
+            .await
+    }
+
+    /// List the email addresses of the user this token is associated
+    /// with. Needs the `user:email` scope.
+    pub async fn list_user_emails(&self, access_token: &str) -> Result<Vec<Email>, Error> {
+        self.get_all_pages(access_token, "/user/emails?per_page=100", "/user/emails")
+            .await
+    }
+
+    /// How much a user may do in a repository, as seen by the user this
+    /// token is associated with, who must be able to see the repository.
+    pub async fn get_repo_permission(
+        &self,
+        access_token: &str,
+        owner: &str,
+        repo: &str,
+        username: &str,
+    ) -> Result<Permission, Error> {
+        Ok(self
+            .get_authenticated_at::<CollaboratorPermission>(
+                access_token,
+                &format!(
+                    "/repos/{}/{}/collaborators/{}/permission",
+                    path_segment(owner)?,
+                    path_segment(repo)?,
+                    path_segment(username)?
+                ),
+                "/repos/{owner}/{repo}/collaborators/{username}/permission",
+            )
+            .await?
+            .level())
+    }
+
+    /// List the repositories the user this token is associated with can
+    /// access, narrowed to the given affiliations. Without any, Github
+    /// lists those of every affiliation.
+    pub async fn list_user_repos(
+        &self,
+        access_token: &str,
+        affiliations: &[Affiliation],
+    ) -> Result<Vec<Repository>, Error> {
+        let mut path = "/user/repos?per_page=100".to_owned();
+
+        if !affiliations.is_empty() {
+            let affiliations = affiliations
+                .iter()
+                .map(Affiliation::as_str)
+                .collect::<Vec<_>>()
+                .join(",");
+
+            path.push_str(&format!("&affiliation={}", affiliations));
+        }
+
+        self.get_all_pages(access_token, &path, "/user/repos").await
+    }
+
+    /// Get every page of a list, following the `Link` to the next page
+    /// Github answers with until there isn't one.
+    async fn get_all_pages<T: DeserializeOwned>(
+        &self,
+        access_token: &str,
+        path: &str,
+        route: &'static str,
+    ) -> Result<Vec<T>, Error> {
+        self.requester
+            .get_all_pages(&self.provider.api_base_url, path, route, |request| {
+                request
+                    .header("Authorization", format!("token {}", access_token))
+                    .header("Accept", "application/json")
+            })
+            .await
+    }
+
+    /// Get a path the route template of which, for tracing, differs
+    /// from the path itself.
+    async fn get_authenticated_at<T: DeserializeOwned>(
+        &self,
+        access_token: &str,
+        path: &str,
+        route: &'static str,
+    ) -> Result<T, Error> {
         Ok(self
-            .http_client
-            .get(format!("{}/user", self.api_base_url))
-            .header("Authorization", format!("token {}", access_token))
-            .header("Accept", "application/json")
-            .send()
+            .requester
+            .execute(
+                self.requester
+                    .http_client
+                    .get(format!("{}{}", self.provider.api_base_url, path))
+                    .header("Authorization", format!("token {}", access_token))
+                    .header("Accept", "application/json"),
+                route,
+            )
             .await?
+            .error_for_status()?
             .json()
             .await?)
     }
 
+    /// Send a GraphQL query, with its variables, as the user this token
+    /// is associated with. Errors in Github's answer, even alongside
+    /// partial data, are reported as [`Error::GraphQL`].
+    pub async fn graphql<V: Serialize, T: DeserializeOwned>(
+        &self,
+        access_token: &str,
+        query: &str,
+        variables: &V,
+    ) -> Result<T, Error> {
+        self.requester
+            .execute(
+                self.requester
+                    .http_client
+                    .post(self.provider.graphql_url()?)
+                    .header("Authorization", format!("bearer {}", access_token))
+                    .header("Accept", "application/json")
+                    .json(&graphql::Request { query, variables }),
+                "/graphql",
+            )
+            .await?
+            .error_for_status()?
+            .json::<graphql::Response<T>>()
+            .await?
+            .into_result()
+    }
+
+    /// Get the user this token is associated with, their organizations
+    /// and their teams, in two GraphQL requests: one for their login,
+    /// and one for everything else. Needs the `read:org` scope to see
+    /// private memberships. More than 100 organizations, or teams in
+    /// one, is an [`Error::Incomplete`].
+    pub async fn viewer_identity(&self, access_token: &str) -> Result<ViewerIdentity, Error> {
+        let login = self
+            .graphql::<_, ViewerLoginData>(access_token, VIEWER_LOGIN_QUERY, &json!({}))
+            .await?
+            .viewer
+            .login;
+
+        self.graphql::<_, ViewerData>(
+            access_token,
+            VIEWER_IDENTITY_QUERY,
+            &json!({ "first": VIEWER_IDENTITY_PAGE_SIZE, "login": login }),
+        )
+        .await?
+        .try_into()
+    }
+
     /// Get a user's public profile.
     pub async fn get_user_detail_public(
         &self,
         username: &str,
     ) -> Result<UserDetailResponse, Error> {
         Ok(self
-            .http_client
-            .get(format!("{}/user/{}", self.api_base_url, username))
-            .header("Accept", "application/json")
-            .send()
+            .requester
+            .execute(
+                self.requester
+                    .http_client
+                    .get(format!(
+                        "{}/users/{}",
+                        self.provider.api_base_url,
+                        path_segment(username)?
+                    ))
+                    .header("Accept", "application/json"),
+                "/users/{username}",
+            )
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    /// List the SSH keys a user authenticates with.
+    #[cfg(feature = "keys")]
+    pub async fn list_ssh_keys(&self, username: &str) -> Result<Vec<SshKey>, Error> {
+        self.get_public(
+            &format!("/users/{}/keys?per_page=100", path_segment(username)?),
+            "/users/{username}/keys",
+        )
+        .await
+    }
+
+    /// List the SSH keys a user signs commits with.
+    #[cfg(feature = "keys")]
+    pub async fn list_ssh_signing_keys(&self, username: &str) -> Result<Vec<SshSigningKey>, Error> {
+        self.get_public(
+            &format!(
+                "/users/{}/ssh_signing_keys?per_page=100",
+                path_segment(username)?
+            ),
+            "/users/{username}/ssh_signing_keys",
+        )
+        .await
+    }
+
+    /// List a user's GPG keys.
+    #[cfg(feature = "keys")]
+    pub async fn list_gpg_keys(&self, username: &str) -> Result<Vec<GpgKey>, Error> {
+        self.get_public(
+            &format!("/users/{}/gpg_keys?per_page=100", path_segment(username)?),
+            "/users/{username}/gpg_keys",
+        )
+        .await
+    }
+
+    #[cfg(feature = "keys")]
+    async fn get_public<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        route: &'static str,
+    ) -> Result<T, Error> {
+        Ok(self
+            .requester
+            .execute(
+                self.requester
+                    .http_client
+                    .get(format!("{}{}", self.provider.api_base_url, path))
+                    .header("Accept", "application/json"),
+                route,
+            )
             .await?
+            .error_for_status()?
             .json()
             .await?)
     }
 }
 
+/// What sends a client's requests, through its interceptors and
+/// transport, and traces them. Shared by [`GithubClient`] and
+/// [`crate::AppClient`].
+#[derive(Clone)]
+pub(crate) struct Requester {
+    pub(crate) http_client: ReqwestClient,
+    /// Hooks run around every request, in order.
+    #[cfg(feature = "interceptors")]
+    interceptors: Vec<Arc<dyn Interceptor>>,
+    /// What sends requests once the interceptors are done with them.
+    #[cfg(feature = "interceptors")]
+    transport: Arc<dyn Transport>,
+}
+
+impl Requester {
+    pub(crate) fn new() -> Result<Self, Error> {
+        let http_client = reqwest::ClientBuilder::new()
+            .user_agent("Rust/request/ghoauth")
+            .build()?;
+
+        Ok(Self {
+            #[cfg(feature = "interceptors")]
+            transport: Arc::new(http_client.clone()),
+            http_client,
+            #[cfg(feature = "interceptors")]
+            interceptors: Vec::new(),
+        })
+    }
+
+    #[cfg(feature = "interceptors")]
+    pub(crate) fn add_interceptor(&mut self, interceptor: impl Interceptor) {
+        self.interceptors.push(Arc::new(interceptor));
+    }
+
+    #[cfg(feature = "interceptors")]
+    pub(crate) fn set_transport(&mut self, transport: impl Transport) {
+        self.transport = Arc::new(transport);
+    }
+
+    /// Send a request to Github. Every request goes through here, so
+    /// it's where they're traced.
+    pub(crate) async fn execute(
+        &self,
+        request: RequestBuilder,
+        route: &'static str,
+    ) -> Result<Response, Error> {
+        let request = request.build()?;
+        let span = RequestSpan::new(request.method(), route);
+        #[cfg(feature = "interceptors")]
+        let response = interceptor::execute(self.transport.as_ref(), &self.interceptors, request);
+        #[cfg(not(feature = "interceptors"))]
+        let response = async { Ok(self.http_client.execute(request).await?) };
+        let response = span.instrument(response).await;
+
+        match &response {
+            Ok(response) => span.record(Some(response.status()), Some(response.headers())),
+            Err(_) => span.record(None, None),
+        }
+
+        response
+    }
+
+    /// Get every page of a list on the API, following the `Link` to the
+    /// next page until there isn't one. `authorize` adds the headers
+    /// each page is requested with.
+    pub(crate) async fn get_all_pages<T: DeserializeOwned>(
+        &self,
+        api_base_url: &str,
+        path: &str,
+        route: &'static str,
+        authorize: impl Fn(RequestBuilder) -> RequestBuilder,
+    ) -> Result<Vec<T>, Error> {
+        let mut url = format!("{}{}", api_base_url, path);
+        let mut items = Vec::new();
+
+        loop {
+            let response = self
+                .execute(authorize(self.http_client.get(&url)), route)
+                .await?
+                .error_for_status()?;
+            let next = next_page(api_base_url, &url, response.headers())?;
+
+            items.extend(response.json::<Vec<T>>().await?);
+
+            match next {
+                Some(next) => url = next,
+                None => return Ok(items),
+            }
+        }
+    }
+}
+
+/// The next page of a list, from the `Link` header of the page at
+/// `url`. Credentials go along to it, so it must be on the API.
+pub(crate) fn next_page(
+    api_base_url: &str,
+    url: &str,
+    headers: &HeaderMap,
+) -> Result<Option<String>, Error> {
+    let next = headers
+        .get_all(LINK)
+        .iter()
+        .filter_map(|link| link.to_str().ok())
+        .flat_map(|link| link.split(','))
+        .find_map(|link| {
+            let (target, params) = link.split_once(';')?;
+
+            params
+                .split(';')
+                .any(|param| param.trim() == r#"rel="next""#)
+                .then(|| target.trim().trim_start_matches('<').trim_end_matches('>'))
+        });
+    let next = match next {
+        Some(next) => next,
+        None => return Ok(None),
+    };
+    let invalid = |reason: String| Error::Decode(format!("invalid next page {}: {}", next, reason));
+    let next = Url::parse(url)
+        .and_then(|url| url.join(next))
+        .map_err(|err| invalid(err.to_string()))?;
+    let api = Url::parse(api_base_url).map_err(|err| invalid(err.to_string()))?;
+
+    if next.origin() != api.origin() {
+        return Err(invalid("not on the API".to_owned()));
+    }
+
+    Ok(Some(next.into()))
+}
+
+/// Percent-encode a name to look up, so that it stays within its own
+/// segment of the path. Names that are path segments in their own
+/// right, like `..`, are refused however they're encoded.
+pub(crate) fn path_segment(name: &str) -> Result<String, Error> {
+    if matches!(name, "" | "." | "..") {
+        return Err(Error::InvalidName(name.to_owned()));
+    }
+
+    Ok(name
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(byte).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect())
+}
+
+/// Parse the provider's authorize URL, so a client with a bad one is
+/// never made.
+pub(crate) fn parse_authorize_url(provider: &Provider) -> Result<Url, Error> {
+    let authorize_url = provider.authorize_url();
+
+    Url::parse(&authorize_url).map_err(|err| {
+        Error::ClientCreation(format!("invalid authorize url {}: {}", authorize_url, err))
+    })
+}
+
+/// Build an authorization URL, shared by the async and blocking clients.
+pub(crate) fn authorization_url_with(
+    authorize_url: &Url,
+    client_id: &str,
+    request: &AuthorizationRequest,
+) -> String {
+    telemetry::authorization_url_issued();
+
+    let mut url = authorize_url.clone();
+
+    {
+        let mut query = url.query_pairs_mut();
+
+        query.append_pair("client_id", client_id);
+        if let Some(redirect_uri) = &request.redirect_uri {
+            query.append_pair("redirect_uri", redirect_uri);
+        }
+        if !request.scopes.is_empty() {
+            query.append_pair("scope", &request.scopes.join(" "));
+        }
+        if let Some(state) = &request.state {
+            query.append_pair("state", state);
+        }
+        if let Some(code_challenge) = &request.code_challenge {
+            query.append_pair("code_challenge", code_challenge);
+            query.append_pair("code_challenge_method", "S256");
+        }
+        if let Some(login) = &request.login {
+            query.append_pair("login", login);
+        }
+    }
+
+    url.into()
+}
+
 impl std::fmt::Debug for GithubClient {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
             "GithubClient {{ http_client: {:?}, client_id: {}, \
             client_secret: REDACTED }}",
-            self.http_client, self.client_id,
+            self.requester.http_client, self.client_id,
+        )
+    }
+}
+
+#[cfg(all(test, feature = "fakehub"))]
+mod tests {
+    use reqwest::{
+        header::{HeaderName, HeaderValue},
+        StatusCode,
+    };
+    use serde_json::json;
+
+    use crate::{
+        fakehub::{Fakehub, Org, Repo},
+        interceptor::{InterceptedRequest, InterceptedResponse, Interceptor},
+        testing::{
+            add_test_user, user_named, CLIENT_ID, CLIENT_SECRET, ORG, ORG_ID, REPO_ID, USER,
+            USER_ID,
+        },
+        Affiliation, GithubApi, Permission,
+    };
+
+    use super::GithubClient;
+
+    #[tokio::test]
+    async fn paginated_lists() {
+        for fakehub in [
+            Fakehub::new().expect("cannot start local fakehub server"),
+            Fakehub::new_in_process().expect("cannot start in-process fakehub"),
+        ] {
+            let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+            add_test_user(&fakehub).await;
+            for org_id in ORG_ID..ORG_ID + 150 {
+                fakehub
+                    .add_org(
+                        org_id,
+                        Org {
+                            login: format!("org-{}", org_id),
+                        },
+                    )
+                    .await;
+                fakehub.add_org_member(org_id, USER_ID).await.unwrap();
+            }
+
+            let code = fakehub.get_code(USER_ID).await.unwrap();
+            let token = github_client.get_access_token(&code).await.unwrap();
+
+            // 100 to a page, and every page is followed
+            let orgs = github_client
+                .list_user_orgs(&token.access_token)
+                .await
+                .unwrap();
+            assert_eq!(150, orgs.len());
+            assert_eq!(
+                150,
+                orgs.iter()
+                    .map(|org| &org.login)
+                    .collect::<std::collections::HashSet<_>>()
+                    .len()
+            );
+            assert_eq!(
+                vec!["/user/orgs?per_page=100", "/user/orgs?per_page=100&page=2"],
+                fakehub
+                    .requests()
+                    .await
+                    .iter()
+                    .map(|request| request.uri.as_str())
+                    .filter(|uri| uri.starts_with("/user/orgs"))
+                    .collect::<Vec<_>>()
+            );
+
+            fakehub.shutdown().await;
+        }
+
+        // the token never follows a link off the API
+        let github_client = GithubClient::new_with_urls(
+            CLIENT_ID,
+            CLIENT_SECRET,
+            "http://github.invalid",
+            "http://api.github.invalid",
         )
+        .unwrap()
+        .with_interceptor(LinkElsewhere);
+        assert!(matches!(
+            github_client.list_user_emails("token").await,
+            Err(crate::Error::Decode(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn repo_permissions() {
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+        for (user_id, login) in [(USER_ID, USER), (USER_ID + 1, "collaborator")] {
+            fakehub.add_user(user_id, user_named(login)).await;
+        }
+        fakehub
+            .add_org(
+                ORG_ID,
+                Org {
+                    login: ORG.to_string(),
+                },
+            )
+            .await;
+        fakehub.add_org_member(ORG_ID, USER_ID).await.unwrap();
+        for (repo_id, owner_id, name) in [
+            (REPO_ID, USER_ID, "dotfiles"),
+            (REPO_ID + 1, ORG_ID, "service"),
+            (REPO_ID + 2, USER_ID + 1, "shared"),
+            (REPO_ID + 3, USER_ID + 1, "hidden"),
+        ] {
+            fakehub
+                .add_repo(
+                    repo_id,
+                    Repo {
+                        owner_id,
+                        name: name.to_string(),
+                        private: true,
+                    },
+                )
+                .await
+                .unwrap();
+        }
+        fakehub
+            .add_collaborator(REPO_ID + 1, USER_ID + 1, Permission::Write)
+            .await
+            .unwrap();
+        fakehub
+            .add_collaborator(REPO_ID + 2, USER_ID, Permission::Triage)
+            .await
+            .unwrap();
+
+        let code = fakehub.get_code(USER_ID).await.unwrap();
+        let token = github_client.get_access_token(&code).await.unwrap();
+        let permission = |owner: &'static str, repo: &'static str, username: &'static str| {
+            let github_client = github_client.clone();
+            let access_token = token.access_token.clone();
+
+            async move {
+                github_client
+                    .get_repo_permission(&access_token, owner, repo, username)
+                    .await
+            }
+        };
+
+        assert_eq!(
+            Permission::Admin,
+            permission(USER, "dotfiles", USER).await.unwrap()
+        );
+        assert_eq!(
+            Permission::Read,
+            permission(ORG, "service", USER).await.unwrap()
+        );
+        assert_eq!(
+            Permission::Write,
+            permission(ORG, "service", "collaborator").await.unwrap()
+        );
+        assert!(permission(ORG, "service", "collaborator").await.unwrap() >= Permission::Write);
+        assert_eq!(
+            Permission::None,
+            permission(USER, "dotfiles", "collaborator").await.unwrap()
+        );
+        assert!(matches!(
+            permission("collaborator", "hidden", USER).await,
+            Err(crate::Error::Http(Some(404), _))
+        ));
+        // names can't reach beyond their own segment of the path
+        assert!(matches!(
+            permission(
+                USER,
+                "dotfiles/collaborators/collaborator/permission#",
+                USER
+            )
+            .await,
+            Err(crate::Error::Http(Some(404), _))
+        ));
+        assert!(matches!(
+            permission(USER, "..", USER).await,
+            Err(crate::Error::InvalidName(_))
+        ));
+
+        // a role on top of the org's base permission wins
+        fakehub
+            .add_collaborator(REPO_ID + 1, USER_ID, Permission::Maintain)
+            .await
+            .unwrap();
+        assert_eq!(
+            Permission::Maintain,
+            permission(ORG, "service", USER).await.unwrap()
+        );
+
+        let repos = |affiliations: &'static [Affiliation]| {
+            let github_client = github_client.clone();
+            let access_token = token.access_token.clone();
+
+            async move {
+                github_client
+                    .list_user_repos(&access_token, affiliations)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|repo| (repo.full_name.clone(), repo.permission()))
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(
+            vec![
+                ("user/dotfiles".to_string(), Permission::Admin),
+                ("org/service".to_string(), Permission::Maintain),
+                ("collaborator/shared".to_string(), Permission::Triage),
+            ],
+            repos(&[]).await
+        );
+        assert_eq!(
+            vec![("user/dotfiles".to_string(), Permission::Admin)],
+            repos(&[Affiliation::Owner]).await
+        );
+        assert_eq!(
+            vec![
+                ("org/service".to_string(), Permission::Maintain),
+                ("collaborator/shared".to_string(), Permission::Triage),
+            ],
+            repos(&[Affiliation::Collaborator]).await
+        );
+        assert_eq!(
+            vec![("org/service".to_string(), Permission::Maintain)],
+            repos(&[Affiliation::OrganizationMember]).await
+        );
+
+        let in_memory = fakehub.in_memory_client(CLIENT_ID, CLIENT_SECRET).await;
+        assert_eq!(
+            Permission::Triage,
+            in_memory
+                .get_repo_permission(&token.access_token, "collaborator", "shared", USER)
+                .await
+                .unwrap()
+        );
+
+        fakehub.shutdown().await;
+    }
+
+    /// Answers every list with an empty page, and a link to the next
+    /// page somewhere other than the API.
+    struct LinkElsewhere;
+
+    #[async_trait::async_trait]
+    impl Interceptor for LinkElsewhere {
+        async fn before(
+            &self,
+            _request: &mut InterceptedRequest,
+        ) -> Result<Option<InterceptedResponse>, crate::Error> {
+            let mut response = InterceptedResponse::json(StatusCode::OK, &json!([]))?;
+            response.headers.insert(
+                HeaderName::from_static("link"),
+                HeaderValue::from_static(
+                    "<http://elsewhere.invalid/user/emails?page=2>; rel=\"next\"",
+                ),
+            );
+
+            Ok(Some(response))
+        }
     }
 }
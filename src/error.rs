@@ -10,6 +10,52 @@ pub enum Error {
     Decode(String),
     #[error("{0}")]
     OtherHttp(String),
+    #[error("{0}")]
+    Jwt(String),
+    #[error("Interceptor failed: {0}")]
+    Interceptor(String),
+    /// Github answered with an OAuth error, eg. `bad_verification_code`,
+    /// and maybe a description of it.
+    #[error("Github answered with OAuth error {0}")]
+    OAuth(String, Option<String>),
+    /// The request Github redirected back to the app with is malformed,
+    /// or doesn't carry the expected state.
+    #[error("Invalid OAuth callback: {0}")]
+    Callback(String),
+    #[error("Token store failed: {0}")]
+    TokenStore(String),
+    #[error("Identity repository failed: {0}")]
+    IdentityRepository(String),
+    /// A public key Github handed out, or Fakehub was given, can't be
+    /// parsed.
+    #[error("Malformed key: {0}")]
+    MalformedKey(String),
+    /// Github answered a GraphQL query with errors.
+    #[error(
+        "Github answered the GraphQL query with errors: {}",
+        .0.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+    )]
+    GraphQL(Vec<crate::graphql::GraphQLError>),
+    /// The provider doesn't offer what was asked of it, eg. a GraphQL
+    /// query to Gitea, or a `GithubApi` implementation doesn't.
+    #[error("Unsupported by the provider: {0}")]
+    Unsupported(String),
+    /// Waited too long for something, eg. the user to come back from
+    /// Github.
+    #[error("Timed out: {0}")]
+    Timeout(String),
+    /// Github has more of what was asked for than fits in one answer,
+    /// and a partial answer would mislead.
+    #[error("Incomplete answer: {0}")]
+    Incomplete(String),
+    /// A name to look up, eg. a repository's, can't be a segment of a
+    /// URL path, whatever its encoding.
+    #[error("Invalid name: {0:?}")]
+    InvalidName(String),
+    /// The access token has expired, and there is no refresh token to
+    /// get another one with.
+    #[error("Access token expired and cannot be refreshed")]
+    TokenExpired,
 }
 
 impl From<reqwest::Error> for Error {
@@ -32,3 +78,10 @@ impl From<reqwest::Error> for Error {
         Self::OtherHttp(err.to_string())
     }
 }
+
+#[cfg(any(feature = "app", feature = "oidc"))]
+impl From<jsonwebtoken::errors::Error> for Error {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        Self::Jwt(err.to_string())
+    }
+}
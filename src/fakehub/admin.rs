@@ -0,0 +1,593 @@
+use axum::{
+    extract::{Path, State},
+    http::{header::HOST, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde_json::{json, Value};
+use url::Url;
+
+use super::{
+    config::{ClientFixture, UserFixture},
+    error::{Error, Result},
+    state::{CapturedRequest, Client, FakehubState, FakehubStateRef, User, UserId},
+};
+
+/// Where the admin API is mounted on the web host.
+pub(super) const ADMIN_PREFIX: &str = "/_fakehub";
+
+/// Control over Fakehub for tests that run outside the Rust process, eg.
+/// in a browser. `GET /_fakehub/openapi.json` describes it.
+pub(super) fn routes() -> Router<FakehubStateRef> {
+    Router::new()
+        .route("/openapi.json", get(openapi))
+        .route("/clients", post(add_client))
+        .route("/users", post(add_user))
+        .route("/users/:user_id", delete(remove_user))
+        .route("/users/:user_id/code", post(get_code))
+        .route("/reset", post(reset))
+        .route("/state", get(dump_state))
+        .route("/requests", get(list_requests).delete(clear_requests))
+}
+
+/// Add every request but the admin API's own to the request log.
+pub(super) async fn capture_request<B>(
+    State(fakehub_state): State<FakehubStateRef>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let method = request.method().to_string();
+    let uri = request.uri().clone();
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    let response = next.run(request).await;
+
+    if !uri.path().starts_with(ADMIN_PREFIX) {
+        let mut fakehub_state = fakehub_state.lock().await;
+        let at = fakehub_state.now();
+
+        fakehub_state.capture_request(CapturedRequest {
+            method,
+            host,
+            // in-process requests carry their absolute url
+            uri: uri
+                .path_and_query()
+                .map(|path_and_query| path_and_query.to_string())
+                .unwrap_or_default(),
+            status: response.status().as_u16(),
+            at,
+        });
+    }
+
+    response
+}
+
+// POST /_fakehub/clients
+async fn add_client(
+    State(fakehub_state): State<FakehubStateRef>,
+    Json(client): Json<ClientFixture>,
+) -> Result<Response> {
+    let mut fakehub_state = fakehub_state.lock().await;
+    let redirect_url = match &client.redirect_url {
+        Some(redirect_url) => Url::parse(redirect_url)?,
+        None => fakehub_state.default_redirect_url.clone(),
+    };
+
+    fakehub_state.clients.insert(
+        client.id.clone(),
+        Client {
+            secret: client.secret.clone(),
+            redirect_url: redirect_url.clone(),
+        },
+    );
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ClientFixture {
+            redirect_url: Some(redirect_url.to_string()),
+            ..client
+        }),
+    )
+        .into_response())
+}
+
+// POST /_fakehub/users
+async fn add_user(
+    State(fakehub_state): State<FakehubStateRef>,
+    Json(user): Json<UserFixture>,
+) -> Result<Response> {
+    let mut fakehub_state = fakehub_state.lock().await;
+
+    if fakehub_state.users.contains_key(&user.id) {
+        return Err(Error::Unprocessable(format!(
+            "User {} already exists",
+            user.id
+        )));
+    }
+
+    fakehub_state.users.insert(
+        user.id,
+        User {
+            login: user.login.clone(),
+            avatar_url: user.avatar_url(),
+            html_url: user.html_url(),
+        },
+    );
+
+    let added = add_user_details(&mut fakehub_state, &user);
+
+    // a user with a bad key isn't half added
+    if let Err(error) = added {
+        fakehub_state.remove_user(user.id)?;
+
+        return Err(error);
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(UserFixture {
+            avatar_url: Some(user.avatar_url()),
+            html_url: Some(user.html_url()),
+            ..user
+        }),
+    )
+        .into_response())
+}
+
+fn add_user_details(fakehub_state: &mut FakehubState, user: &UserFixture) -> Result<()> {
+    for email in &user.emails {
+        fakehub_state.add_email(user.id, &email.email, email.verified)?;
+    }
+    for key in &user.ssh_keys {
+        fakehub_state.add_ssh_key(user.id, key)?;
+    }
+    for key in &user.gpg_keys {
+        fakehub_state.add_gpg_key(user.id, key)?;
+    }
+
+    Ok(())
+}
+
+// DELETE /_fakehub/users/:user_id
+async fn remove_user(
+    State(fakehub_state): State<FakehubStateRef>,
+    Path(user_id): Path<UserId>,
+) -> std::result::Result<StatusCode, Response> {
+    let mut fakehub_state = fakehub_state.lock().await;
+
+    fakehub_state.remove_user(user_id).map_err(user_not_found)?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// POST /_fakehub/users/:user_id/code
+// Stands in for the user logging in on the login page
+async fn get_code(
+    State(fakehub_state): State<FakehubStateRef>,
+    Path(user_id): Path<UserId>,
+) -> std::result::Result<Json<Value>, Response> {
+    let mut fakehub_state = fakehub_state.lock().await;
+    let code = fakehub_state.get_code(user_id).map_err(user_not_found)?;
+
+    Ok(Json(json!({ "code": code })))
+}
+
+// POST /_fakehub/reset
+async fn reset(State(fakehub_state): State<FakehubStateRef>) -> StatusCode {
+    fakehub_state.lock().await.reset();
+
+    StatusCode::NO_CONTENT
+}
+
+// GET /_fakehub/state
+// The fixtures, which load back into a Fakehub as they are, plus the
+// flavor and counters
+async fn dump_state(State(fakehub_state): State<FakehubStateRef>) -> Result<Json<Value>> {
+    let fakehub_state = fakehub_state.lock().await;
+    let mut dump = serde_json::to_value(fakehub_state.fixtures())?;
+
+    dump["flavor"] = serde_json::to_value(fakehub_state.flavor)?;
+    dump["counters"] = serde_json::to_value(&fakehub_state.counters)?;
+
+    Ok(Json(dump))
+}
+
+// GET /_fakehub/requests
+async fn list_requests(State(fakehub_state): State<FakehubStateRef>) -> Json<Vec<CapturedRequest>> {
+    let fakehub_state = fakehub_state.lock().await;
+
+    Json(fakehub_state.requests.iter().cloned().collect())
+}
+
+// DELETE /_fakehub/requests
+async fn clear_requests(State(fakehub_state): State<FakehubStateRef>) -> StatusCode {
+    fakehub_state.lock().await.requests.clear();
+
+    StatusCode::NO_CONTENT
+}
+
+/// Unknown users are a 404 here, where the Github routes have them a
+/// 401.
+fn user_not_found(error: Error) -> Response {
+    match error {
+        Error::NoSuchUserId(_) => (StatusCode::NOT_FOUND, error.to_string()).into_response(),
+        error => error.into_response(),
+    }
+}
+
+// GET /_fakehub/openapi.json
+async fn openapi(State(fakehub_state): State<FakehubStateRef>) -> Json<Value> {
+    let default_redirect_url = fakehub_state.lock().await.default_redirect_url.to_string();
+
+    Json(json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "Fakehub admin API",
+            "description": "Control over a running Fakehub, for tests outside the Rust process.",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "servers": [{ "url": ADMIN_PREFIX }],
+        "paths": {
+            "/clients": {
+                "post": {
+                    "operationId": "addClient",
+                    "summary": "Register an OAuth app, or replace one with the same id.",
+                    "requestBody": json_body("Client"),
+                    "responses": {
+                        "201": json_response("The client, with its redirect url filled in.", "Client"),
+                        "422": text_response("The redirect url is not a url."),
+                    },
+                },
+            },
+            "/users": {
+                "post": {
+                    "operationId": "addUser",
+                    "summary": "Add a user, with their email addresses and keys.",
+                    "requestBody": json_body("User"),
+                    "responses": {
+                        "201": json_response("The user, with their urls filled in.", "User"),
+                        "422": text_response("The user exists, or a key is malformed."),
+                    },
+                },
+            },
+            "/users/{userId}": {
+                "delete": {
+                    "operationId": "removeUser",
+                    "summary": "Remove a user, with their tokens, keys, memberships and repositories.",
+                    "parameters": [user_id_parameter()],
+                    "responses": {
+                        "204": { "description": "The user is gone." },
+                        "404": text_response("No such user."),
+                    },
+                },
+            },
+            "/users/{userId}/code": {
+                "post": {
+                    "operationId": "getCode",
+                    "summary": "Issue a login code, as though the user logged in on the login page.",
+                    "parameters": [user_id_parameter()],
+                    "responses": {
+                        "200": json_response("A code to exchange for a token.", "Code"),
+                        "404": text_response("No such user."),
+                    },
+                },
+            },
+            "/reset": {
+                "post": {
+                    "operationId": "reset",
+                    "summary": "Forget everything, including the request log, as though Fakehub had just started.",
+                    "responses": { "204": { "description": "Fakehub is empty." } },
+                },
+            },
+            "/state": {
+                "get": {
+                    "operationId": "dumpState",
+                    "summary": "Everything Fakehub holds, in the format of its config file.",
+                    "responses": { "200": json_response("The state.", "State") },
+                },
+            },
+            "/requests": {
+                "get": {
+                    "operationId": "listRequests",
+                    "summary": "The most recent requests, oldest first, leaving out the admin API's own.",
+                    "responses": {
+                        "200": {
+                            "description": "The request log.",
+                            "content": {
+                                "application/json": {
+                                    "schema": { "type": "array", "items": schema_ref("CapturedRequest") },
+                                },
+                            },
+                        },
+                    },
+                },
+                "delete": {
+                    "operationId": "clearRequests",
+                    "summary": "Empty the request log.",
+                    "responses": { "204": { "description": "The request log is empty." } },
+                },
+            },
+        },
+        "components": {
+            "schemas": {
+                "Client": {
+                    "type": "object",
+                    "required": ["id", "secret"],
+                    "properties": {
+                        "id": { "type": "string" },
+                        "secret": { "type": "string" },
+                        "redirect_url": {
+                            "type": "string",
+                            "nullable": true,
+                            "description": format!("Redirects must fall under it. Defaults to {}.", default_redirect_url),
+                        },
+                    },
+                },
+                "User": {
+                    "type": "object",
+                    "required": ["id", "login"],
+                    "properties": {
+                        "id": { "type": "integer", "format": "int64" },
+                        "login": { "type": "string" },
+                        "avatar_url": { "type": "string", "nullable": true },
+                        "html_url": { "type": "string", "nullable": true },
+                        "emails": {
+                            "type": "array",
+                            "description": "The first is the user's primary address.",
+                            "items": schema_ref("Email"),
+                        },
+                        "ssh_keys": {
+                            "type": "array",
+                            "description": "In the OpenSSH format.",
+                            "items": { "type": "string" },
+                        },
+                        "gpg_keys": {
+                            "type": "array",
+                            "description": "ASCII armored.",
+                            "items": { "type": "string" },
+                        },
+                    },
+                },
+                "Email": {
+                    "type": "object",
+                    "required": ["email"],
+                    "properties": {
+                        "email": { "type": "string" },
+                        "verified": { "type": "boolean", "default": false },
+                    },
+                },
+                "Code": {
+                    "type": "object",
+                    "required": ["code"],
+                    "properties": { "code": { "type": "string" } },
+                },
+                "State": {
+                    "type": "object",
+                    "properties": {
+                        "flavor": { "type": "string", "enum": ["github", "github-enterprise", "gitea"] },
+                        "clients": { "type": "array", "items": schema_ref("Client") },
+                        "users": { "type": "array", "items": schema_ref("User") },
+                        "orgs": { "type": "array", "items": { "type": "object" } },
+                        "teams": { "type": "array", "items": { "type": "object" } },
+                        "repos": { "type": "array", "items": { "type": "object" } },
+                        "webhooks": { "type": "array", "items": { "type": "object" } },
+                        "counters": {
+                            "type": "object",
+                            "additionalProperties": { "type": "integer", "format": "int64" },
+                        },
+                    },
+                },
+                "CapturedRequest": {
+                    "type": "object",
+                    "required": ["method", "host", "uri", "status", "at"],
+                    "properties": {
+                        "method": { "type": "string" },
+                        "host": { "type": "string" },
+                        "uri": { "type": "string", "description": "The path and query string." },
+                        "status": { "type": "integer" },
+                        "at": { "type": "string", "format": "date-time" },
+                    },
+                },
+            },
+        },
+    }))
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn json_body(schema: &str) -> Value {
+    json!({
+        "required": true,
+        "content": { "application/json": { "schema": schema_ref(schema) } },
+    })
+}
+
+fn json_response(description: &str, schema: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema_ref(schema) } },
+    })
+}
+
+fn text_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/plain": { "schema": { "type": "string" } } },
+    })
+}
+
+fn user_id_parameter() -> Value {
+    json!({
+        "name": "userId",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "integer", "format": "int64" },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    use crate::{
+        fakehub::{Fakehub, FakehubConfig},
+        testing::{CLIENT_ID, CLIENT_SECRET, USER, USER_ID},
+        GithubClient,
+    };
+
+    #[tokio::test]
+    async fn fakehub_admin_api() {
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let admin_url = fakehub.admin_url();
+        let admin = reqwest::Client::new();
+
+        let openapi = admin
+            .get(format!("{}/openapi.json", admin_url))
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        assert_eq!("3.0.3", openapi["openapi"]);
+        assert!(openapi["paths"]["/users/{userId}/code"]["post"].is_object());
+
+        let response = admin
+            .post(format!("{}/clients", admin_url))
+            .json(&json!({ "id": CLIENT_ID, "secret": CLIENT_SECRET }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let response = admin
+            .post(format!("{}/users", admin_url))
+            .json(&json!({
+                "id": USER_ID,
+                "login": USER,
+                "emails": [{ "email": "user@example.com", "verified": true }],
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, response.status());
+        let response = admin
+            .post(format!("{}/users", admin_url))
+            .json(&json!({ "id": USER_ID, "login": USER }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+
+        // a user with a bad key isn't added at all
+        let response = admin
+            .post(format!("{}/users", admin_url))
+            .json(&json!({ "id": 2, "login": "other", "ssh_keys": ["ssh-ed25519 nonsense"] }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, response.status());
+
+        let code = admin
+            .post(format!("{}/users/{}/code", admin_url, USER_ID))
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        let github_client =
+            GithubClient::new_with_provider(CLIENT_ID, CLIENT_SECRET, fakehub.provider()).unwrap();
+        let token = github_client
+            .get_access_token(code["code"].as_str().unwrap())
+            .await
+            .unwrap();
+        let user_detail = github_client
+            .get_user_detail(&token.access_token)
+            .await
+            .unwrap();
+        assert_eq!(USER, user_detail.login);
+
+        // the log has the app's requests, but not the admin API's
+        let requests = admin
+            .get(format!("{}/requests", admin_url))
+            .send()
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        let requests = requests
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|request| {
+                format!(
+                    "{} {} {}",
+                    request["method"], request["uri"], request["status"]
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                r#""POST" "/login/oauth/access_token" 200"#,
+                r#""GET" "/user" 200"#,
+            ],
+            requests
+        );
+        assert_eq!(requests.len(), fakehub.requests().await.len());
+
+        // the state loads back into a Fakehub as it is
+        let state = admin
+            .get(format!("{}/state", admin_url))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let config = FakehubConfig::from_json(&state).unwrap();
+        assert_eq!(CLIENT_SECRET, config.fixtures.clients[0].secret);
+        assert_eq!("user@example.com", config.fixtures.users[0].emails[0].email);
+        assert_eq!(
+            1,
+            serde_json::from_str::<serde_json::Value>(&state).unwrap()["counters"]["tokens_minted"]
+        );
+
+        let response = admin
+            .delete(format!("{}/users/{}", admin_url, USER_ID))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, response.status());
+        assert!(github_client
+            .get_user_detail(&token.access_token)
+            .await
+            .is_err());
+        let response = admin
+            .post(format!("{}/users/{}/code", admin_url, USER_ID))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+
+        let response = admin
+            .post(format!("{}/reset", admin_url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, response.status());
+        assert!(fakehub.requests().await.is_empty());
+        assert!(github_client.get_access_token("anything").await.is_err());
+
+        fakehub.shutdown().await;
+    }
+}
@@ -1,9 +1,33 @@
+use std::{
+    net::TcpListener,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
 use axum::extract::Path;
-use axum::{extract::State, http::HeaderMap, routing::get, Json, Router};
+use axum::{
+    extract::{OriginalUri, Query, State},
+    http::{
+        header::{HOST, LINK},
+        HeaderMap, HeaderValue, Request, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use url::Url;
 
 use crate::{
-    fakehub::{error::Error, state::FakehubStateRef},
-    UserDetailResponse,
+    fakehub::{
+        error::Error,
+        state::{FakehubState, FakehubStateRef, Flavor, InstallationId, UserId},
+    },
+    graphql::{ViewerData, ViewerIdentity, VIEWER_IDENTITY_QUERY, VIEWER_LOGIN_QUERY},
+    keys::{GpgKey, SshKey, SshSigningKey},
+    Affiliation, Email, Installation, InstallationAccessToken, InstallationTokenRequest,
+    Organization, Permission, Repository, Team, TokenRepository, UserDetailResponse,
 };
 
 use super::{error::Result, temp_server::TempServer};
@@ -17,15 +41,14 @@ pub struct ApiDotGithubDotCom {
 }
 
 impl ApiDotGithubDotCom {
-    /// Create and start a new fake api.github.com.
-    pub fn new(starting_port: u16, fakehub_state: FakehubStateRef) -> Result<Self> {
-        let app = Router::new()
-            .route("/user", get(get_user_detail))
-            .route("/user/:login", get(get_user_detail_public))
-            .with_state(fakehub_state);
-
+    /// Create and start a new fake api.github.com on a bound listener.
+    pub fn new(
+        listener: TcpListener,
+        fakehub_state: FakehubStateRef,
+        flavor: Flavor,
+    ) -> Result<Self> {
         Ok(Self {
-            _temp_server: TempServer::new(starting_port, app)?,
+            _temp_server: TempServer::new(listener, app(fakehub_state, flavor))?,
         })
     }
 
@@ -35,37 +58,301 @@ impl ApiDotGithubDotCom {
     }
 }
 
+/// Everything api.github.com serves, whether from a socket or in memory.
+pub(super) fn app(fakehub_state: FakehubStateRef, flavor: Flavor) -> Router {
+    routes(flavor)
+        .layer(middleware::from_fn_with_state(
+            fakehub_state.clone(),
+            super::faults::inject_faults,
+        ))
+        .layer(middleware::from_fn_with_state(
+            fakehub_state.clone(),
+            super::admin::capture_request,
+        ))
+        .layer(middleware::from_fn(super::temp_server::log_request))
+        .with_state(fakehub_state)
+}
+
+/// The API, where the flavor has it. Flavors other than Github serve it
+/// from their web host too.
+pub(super) fn routes(flavor: Flavor) -> Router<FakehubStateRef> {
+    let api = Router::new()
+        .route("/user", get(get_user_detail))
+        .route("/user/orgs", get(list_user_orgs))
+        .route("/user/teams", get(list_user_teams))
+        .route("/user/emails", get(list_user_emails))
+        .route("/users/:login", get(get_user_detail_public))
+        .route("/users/:login/keys", get(list_ssh_keys))
+        .route("/users/:login/ssh_signing_keys", get(list_ssh_signing_keys))
+        .route("/users/:login/gpg_keys", get(list_gpg_keys))
+        .route("/user/repos", get(list_user_repos))
+        .route(
+            "/repos/:owner/:repo/collaborators/:username/permission",
+            get(get_collaborator_permission),
+        )
+        .route("/app/installations", get(list_installations))
+        .route(
+            "/app/installations/:installation_id/access_tokens",
+            post(create_installation_access_token),
+        );
+    let api = match flavor {
+        Flavor::Github => api.route("/graphql", post(graphql)),
+        Flavor::GithubEnterprise => Router::new()
+            .nest("/api/v3", api)
+            .route("/api/graphql", post(graphql)),
+        Flavor::Gitea => Router::new().nest("/api/v1", api),
+    };
+
+    api.layer(middleware::from_fn(github_headers))
+}
+
+/// Counts requests, to hand each a distinct request id.
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+
+/// Add the request id and rate limit headers Github answers every API
+/// request with. Fakehub has no rate limit, so it never runs out.
+async fn github_headers<B>(request: Request<B>, next: Next<B>) -> Response {
+    let request_id = REQUESTS.fetch_add(1, Ordering::Relaxed);
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+
+    headers.insert(
+        "x-github-request-id",
+        HeaderValue::from_str(&format!("FAKE:{:08X}", request_id)).expect("request ids are ascii"),
+    );
+    headers.insert("x-ratelimit-limit", HeaderValue::from_static("5000"));
+    headers.insert("x-ratelimit-remaining", HeaderValue::from_static("5000"));
+
+    response
+}
+
 async fn get_user_detail(
     State(fakehub_state): State<FakehubStateRef>,
     headers: HeaderMap,
-) -> Result<Json<UserDetailResponse>> {
-    let authorization = match headers.get("Authorization") {
-        Some(authorization) => match authorization.to_str() {
-            Ok(authorization) => authorization,
-            Err(_) => return Err(Error::InvalidHeader("Authorization".to_string())),
-        },
-        None => return Err(Error::Unauthorized),
-    };
-    let authorization = match authorization.strip_prefix("token ") {
-        Some(authorization) => authorization.to_string(),
-        None => return Err(Error::Unauthorized),
+) -> Result<Json<Value>> {
+    let fakehub_state = fakehub_state.lock().await;
+    let user_id = authenticated_user(&fakehub_state, &headers)?;
+
+    Ok(Json(fakehub_state.flavored_user_detail(user_id)?))
+}
+
+/// Which page of a list to answer with, and how long pages are.
+#[derive(Debug, Deserialize)]
+struct Pagination {
+    per_page: Option<usize>,
+    page: Option<usize>,
+}
+
+/// Answer with a page of a list, 30 items long unless asked for up to
+/// 100, and a `Link` to the next page if there is one, as Github does.
+fn paginate<T: Serialize>(
+    items: Vec<T>,
+    pagination: &Pagination,
+    OriginalUri(uri): &OriginalUri,
+    headers: &HeaderMap,
+) -> Result<Response> {
+    let per_page = pagination.per_page.unwrap_or(30).clamp(1, 100);
+    let page = pagination.page.unwrap_or(1).max(1);
+    let has_next = items.len() > page * per_page;
+    let items = items
+        .into_iter()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .collect::<Vec<_>>();
+    let mut response = Json(items).into_response();
+
+    if has_next {
+        let host = headers
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .unwrap_or("localhost");
+        let path_and_query = uri
+            .path_and_query()
+            .map(|path_and_query| path_and_query.as_str())
+            .unwrap_or("/");
+        let mut next = Url::parse(&format!("http://{}{}", host, path_and_query))?;
+        let query = next
+            .query_pairs()
+            .filter(|(name, _)| name != "page")
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect::<Vec<_>>();
+
+        next.query_pairs_mut()
+            .clear()
+            .extend_pairs(query)
+            .append_pair("page", &(page + 1).to_string());
+        response.headers_mut().insert(
+            LINK,
+            HeaderValue::from_str(&format!("<{}>; rel=\"next\"", next)).expect("urls are ascii"),
+        );
+    }
+
+    Ok(response)
+}
+
+async fn list_user_orgs(
+    State(fakehub_state): State<FakehubStateRef>,
+    headers: HeaderMap,
+    uri: OriginalUri,
+    Query(pagination): Query<Pagination>,
+) -> Result<Response> {
+    let fakehub_state = fakehub_state.lock().await;
+    let user_id = authenticated_user(&fakehub_state, &headers)?;
+    let orgs: Vec<Organization> = fakehub_state.user_orgs(user_id)?;
+
+    paginate(orgs, &pagination, &uri, &headers)
+}
+
+async fn list_user_teams(
+    State(fakehub_state): State<FakehubStateRef>,
+    headers: HeaderMap,
+    uri: OriginalUri,
+    Query(pagination): Query<Pagination>,
+) -> Result<Response> {
+    let fakehub_state = fakehub_state.lock().await;
+    let user_id = authenticated_user(&fakehub_state, &headers)?;
+    let teams: Vec<Team> = fakehub_state.user_teams(user_id)?;
+
+    paginate(teams, &pagination, &uri, &headers)
+}
+
+async fn list_user_emails(
+    State(fakehub_state): State<FakehubStateRef>,
+    headers: HeaderMap,
+    uri: OriginalUri,
+    Query(pagination): Query<Pagination>,
+) -> Result<Response> {
+    let fakehub_state = fakehub_state.lock().await;
+    let user_id = authenticated_user(&fakehub_state, &headers)?;
+    let emails: Vec<Email> = fakehub_state.user_emails(user_id);
+
+    paginate(emails, &pagination, &uri, &headers)
+}
+
+#[derive(Debug, Deserialize)]
+struct ListUserReposQueryParams {
+    affiliation: Option<String>,
+}
+
+// GET /user/repos?affiliation=:affiliation
+async fn list_user_repos(
+    State(fakehub_state): State<FakehubStateRef>,
+    headers: HeaderMap,
+    uri: OriginalUri,
+    Query(pagination): Query<Pagination>,
+    Query(ListUserReposQueryParams { affiliation }): Query<ListUserReposQueryParams>,
+) -> Result<Response> {
+    let fakehub_state = fakehub_state.lock().await;
+    let user_id = authenticated_user(&fakehub_state, &headers)?;
+    let affiliations = match affiliation {
+        Some(affiliation) => affiliation
+            .split(',')
+            .map(|affiliation| match affiliation.trim() {
+                "owner" => Ok(Affiliation::Owner),
+                "collaborator" => Ok(Affiliation::Collaborator),
+                "organization_member" => Ok(Affiliation::OrganizationMember),
+                other => Err(Error::Unprocessable(format!(
+                    "{} is not a valid affiliation",
+                    other
+                ))),
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => vec![
+            Affiliation::Owner,
+            Affiliation::Collaborator,
+            Affiliation::OrganizationMember,
+        ],
     };
+
+    let repos: Vec<Repository> = fakehub_state.user_repos(user_id, &affiliations)?;
+
+    paginate(repos, &pagination, &uri, &headers)
+}
+
+// GET /repos/:owner/:repo/collaborators/:username/permission
+async fn get_collaborator_permission(
+    State(fakehub_state): State<FakehubStateRef>,
+    headers: HeaderMap,
+    Path((owner, repo, username)): Path<(String, String, String)>,
+) -> Result<Json<Value>> {
     let fakehub_state = fakehub_state.lock().await;
-    let user_id = match fakehub_state.tokens.get(&authorization) {
-        Some(user_id) => user_id,
-        None => return Err(Error::Unauthorized),
+    let user_id = authenticated_user(&fakehub_state, &headers)?;
+    let (collaborator_id, permission) =
+        fakehub_state.collaborator_permission(&owner, &repo, user_id, &username)?;
+
+    Ok(Json(json!({
+        // the legacy levels, which fold maintain into write and triage
+        // into read
+        "permission": match permission {
+            Permission::Admin => Permission::Admin,
+            Permission::Maintain | Permission::Write => Permission::Write,
+            Permission::Triage | Permission::Read => Permission::Read,
+            Permission::None => Permission::None,
+        },
+        "role_name": permission,
+        "user": fakehub_state.user_detail(collaborator_id)?,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQLRequest {
+    query: String,
+    #[serde(default)]
+    variables: Value,
+}
+
+// POST /graphql
+// Fakehub doesn't really speak GraphQL. It recognizes the viewer login
+// and identity queries, and answers anything else with an error. Like
+// Github, it lists every team of the viewer's orgs unless the query
+// narrows them with `userLogins`.
+async fn graphql(
+    State(fakehub_state): State<FakehubStateRef>,
+    headers: HeaderMap,
+    Json(GraphQLRequest { query, variables }): Json<GraphQLRequest>,
+) -> Result<Json<Value>> {
+    let fakehub_state = fakehub_state.lock().await;
+    let user_id = authenticated_user(&fakehub_state, &headers)?;
+
+    if query.trim() == VIEWER_LOGIN_QUERY {
+        return Ok(Json(json!({
+            "data": { "viewer": { "login": fakehub_state.user_detail(user_id)?.login } },
+        })));
+    }
+
+    if query.trim() != VIEWER_IDENTITY_QUERY {
+        return Ok(Json(json!({
+            "errors": [{
+                "type": "FAKEHUB_UNSUPPORTED",
+                "message": "Fakehub only answers the viewer identity query",
+            }],
+        })));
+    }
+
+    let first = match variables["first"].as_u64() {
+        Some(first) if (1..=100).contains(&first) => first as usize,
+        _ => {
+            return Ok(Json(json!({
+                "errors": [{
+                    "type": "INVALID_ARGUMENT",
+                    "message": "first must be between 1 and 100",
+                    "path": ["viewer", "organizations"],
+                }],
+            })))
+        }
     };
-    let user = match fakehub_state.users.get(user_id) {
-        Some(user) => user,
-        None => return Err(Error::NoSuchUserId(*user_id)),
+
+    let login = variables["login"].as_str();
+    let user_logins = query
+        .contains("userLogins: [$login]")
+        .then(|| login.into_iter().collect::<Vec<_>>());
+    let identity = ViewerIdentity {
+        user: fakehub_state.user_detail(user_id)?,
+        organizations: fakehub_state.user_orgs(user_id)?,
+        teams: fakehub_state.org_teams(user_id, user_logins.as_deref())?,
     };
 
-    Ok(Json(UserDetailResponse {
-        id: *user_id,
-        login: user.login.clone(),
-        html_url: user.html_url.clone(),
-        avatar_url: user.avatar_url.clone(),
-    }))
+    Ok(Json(json!({ "data": ViewerData::new(identity, first) })))
 }
 
 async fn get_user_detail_public(
@@ -85,3 +372,140 @@ async fn get_user_detail_public(
         avatar_url: user.1.avatar_url.clone(),
     }))
 }
+
+// GET /users/:login/keys
+async fn list_ssh_keys(
+    State(fakehub_state): State<FakehubStateRef>,
+    Path(login): Path<String>,
+) -> Result<Json<Vec<SshKey>>> {
+    Ok(Json(fakehub_state.lock().await.user_ssh_keys(&login)?))
+}
+
+// GET /users/:login/ssh_signing_keys
+async fn list_ssh_signing_keys(
+    State(fakehub_state): State<FakehubStateRef>,
+    Path(login): Path<String>,
+) -> Result<Json<Vec<SshSigningKey>>> {
+    Ok(Json(
+        fakehub_state.lock().await.user_ssh_signing_keys(&login)?,
+    ))
+}
+
+// GET /users/:login/gpg_keys
+async fn list_gpg_keys(
+    State(fakehub_state): State<FakehubStateRef>,
+    Path(login): Path<String>,
+) -> Result<Json<Vec<GpgKey>>> {
+    Ok(Json(fakehub_state.lock().await.user_gpg_keys(&login)?))
+}
+
+async fn list_installations(
+    State(fakehub_state): State<FakehubStateRef>,
+    headers: HeaderMap,
+    uri: OriginalUri,
+    Query(pagination): Query<Pagination>,
+) -> Result<Response> {
+    let jwt = bearer_token(&headers)?;
+    let fakehub_state = fakehub_state.lock().await;
+    let app_id = fakehub_state.authenticate_app(&jwt)?;
+    let mut installations = Vec::new();
+
+    for (installation_id, installation) in fakehub_state.installations.iter() {
+        if installation.app_id != app_id {
+            continue;
+        }
+
+        installations.push(Installation {
+            id: *installation_id,
+            app_id,
+            account: fakehub_state.installation_account(installation.account_id)?,
+            repository_selection: match installation.repositories {
+                Some(_) => "selected".to_owned(),
+                None => "all".to_owned(),
+            },
+            permissions: installation.permissions.clone(),
+        });
+    }
+
+    installations.sort_by_key(|installation| installation.id);
+
+    paginate(installations, &pagination, &uri, &headers)
+}
+
+async fn create_installation_access_token(
+    State(fakehub_state): State<FakehubStateRef>,
+    Path(installation_id): Path<InstallationId>,
+    headers: HeaderMap,
+    request: Option<Json<InstallationTokenRequest>>,
+) -> Result<(StatusCode, Json<InstallationAccessToken>)> {
+    let jwt = bearer_token(&headers)?;
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+    let mut fakehub_state = fakehub_state.lock().await;
+    let app_id = fakehub_state.authenticate_app(&jwt)?;
+    let grant = fakehub_state.push_installation_token(app_id, installation_id, &request)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(InstallationAccessToken {
+            token: grant.token,
+            expires_at: fakehub_state.installation_token_expiry(),
+            permissions: grant.permissions,
+            repository_selection: Some(
+                match grant.repositories {
+                    Some(_) => "selected",
+                    None => "all",
+                }
+                .to_owned(),
+            ),
+            repositories: grant.repositories.map(|repositories| {
+                repositories
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, name)| TokenRepository {
+                        id: i as i64 + 1,
+                        full_name: format!("{}/{}", grant.account.login, name),
+                        name,
+                    })
+                    .collect()
+            }),
+        }),
+    ))
+}
+
+fn bearer_token(headers: &HeaderMap) -> Result<String> {
+    let authorization = match headers.get("Authorization") {
+        Some(authorization) => match authorization.to_str() {
+            Ok(authorization) => authorization,
+            Err(_) => return Err(Error::InvalidHeader("Authorization".to_string())),
+        },
+        None => return Err(Error::Unauthorized),
+    };
+
+    match authorization.strip_prefix("Bearer ") {
+        Some(token) => Ok(token.to_string()),
+        None => Err(Error::Unauthorized),
+    }
+}
+
+/// The user whose OAuth token, in Github's `token` scheme, a request
+/// carries.
+fn authenticated_user(fakehub_state: &FakehubState, headers: &HeaderMap) -> Result<UserId> {
+    let authorization = match headers.get("Authorization") {
+        Some(authorization) => match authorization.to_str() {
+            Ok(authorization) => authorization,
+            Err(_) => return Err(Error::InvalidHeader("Authorization".to_string())),
+        },
+        None => return Err(Error::Unauthorized),
+    };
+    // the REST API takes either scheme, and GraphQL docs use bearer
+    let authorization = match authorization
+        .strip_prefix("token ")
+        .or_else(|| authorization.strip_prefix("bearer "))
+        .or_else(|| authorization.strip_prefix("Bearer "))
+    {
+        Some(authorization) => authorization,
+        None => return Err(Error::Unauthorized),
+    };
+
+    fakehub_state.token_user(authorization)
+}
@@ -0,0 +1,360 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+    sync::Arc,
+    time::Duration,
+};
+
+use url::Url;
+
+use super::{
+    clock::{Clock, SystemClock},
+    config::{ClientFixture, Fixtures, UserFixture},
+    error::Result,
+    faults::InjectedError,
+    service::Fakehub,
+    state::{FakehubState, Flavor, TokenFormat, User, UserId, DEFAULT_REDIRECT_URL},
+    temp_server::bind,
+};
+
+/// Everything about a Fakehub that [`Fakehub::new`] leaves at its
+/// defaults, set before its servers start.
+///
+/// ```no_run
+/// # async fn example() -> ghoauth::fakehub::Result<()> {
+/// use std::time::Duration;
+///
+/// use ghoauth::fakehub::{Fakehub, InjectedError, ManualClock, TokenFormat, User};
+///
+/// let clock = ManualClock::default();
+/// let fakehub = Fakehub::builder()
+///     .web_port(3050)
+///     .api_port(3051)
+///     .client_with_redirect_url("1234", "SECRET", "http://localhost:3000/callback")
+///     .user(
+///         1,
+///         User {
+///             login: "octocat".to_owned(),
+///             avatar_url: "https://github.com/octocat.png".to_owned(),
+///             html_url: "https://github.com/octocat".to_owned(),
+///         },
+///     )
+///     .token_format(TokenFormat::Random)
+///     .clock(clock.clone())
+///     .latency(Duration::from_millis(50))
+///     .inject_error(InjectedError::new("/user/emails", 502).times(1))
+///     .start()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FakehubBuilder {
+    host: IpAddr,
+    web_port: u16,
+    api_port: u16,
+    listeners: Option<(TcpListener, TcpListener)>,
+    flavor: Flavor,
+    default_redirect_url: String,
+    code_format: TokenFormat,
+    token_format: TokenFormat,
+    clock: Arc<dyn Clock>,
+    latency: Option<Duration>,
+    injected_errors: Vec<InjectedError>,
+    fixtures: Fixtures,
+}
+
+impl FakehubBuilder {
+    /// A builder for what [`Fakehub::new`] starts: a Github on free
+    /// ports of the loopback interface, with nobody in it.
+    pub fn new() -> Self {
+        Self {
+            host: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            web_port: 0,
+            api_port: 0,
+            listeners: None,
+            flavor: Flavor::default(),
+            default_redirect_url: DEFAULT_REDIRECT_URL.to_owned(),
+            code_format: TokenFormat::default(),
+            token_format: TokenFormat::default(),
+            clock: Arc::new(SystemClock),
+            latency: None,
+            injected_errors: Vec::new(),
+            fixtures: Fixtures::default(),
+        }
+    }
+
+    /// The address both servers bind to.
+    pub fn host(self, host: IpAddr) -> Self {
+        Self { host, ..self }
+    }
+
+    /// The port of the web host, github.com. Port 0, the default, picks
+    /// a free one.
+    pub fn web_port(self, web_port: u16) -> Self {
+        Self { web_port, ..self }
+    }
+
+    /// The port of the API host, api.github.com. Port 0, the default,
+    /// picks a free one.
+    pub fn api_port(self, api_port: u16) -> Self {
+        Self { api_port, ..self }
+    }
+
+    /// Serve from listeners the caller has already bound, instead of
+    /// binding the host and ports.
+    pub fn listeners(self, web_listener: TcpListener, api_listener: TcpListener) -> Self {
+        Self {
+            listeners: Some((web_listener, api_listener)),
+            ..self
+        }
+    }
+
+    pub fn flavor(self, flavor: Flavor) -> Self {
+        Self { flavor, ..self }
+    }
+
+    /// The callback URL clients are registered with unless they're given
+    /// one of their own. Defaults to `http://127.0.0.1`, which allows
+    /// any port and path.
+    pub fn default_redirect_url(self, default_redirect_url: &str) -> Self {
+        Self {
+            default_redirect_url: default_redirect_url.to_owned(),
+            ..self
+        }
+    }
+
+    pub fn code_format(self, code_format: TokenFormat) -> Self {
+        Self {
+            code_format,
+            ..self
+        }
+    }
+
+    pub fn token_format(self, token_format: TokenFormat) -> Self {
+        Self {
+            token_format,
+            ..self
+        }
+    }
+
+    /// Where Fakehub gets the time from. Keep a clone of a
+    /// [`ManualClock`](super::ManualClock) to move it along.
+    pub fn clock(self, clock: impl Clock + 'static) -> Self {
+        Self {
+            clock: Arc::new(clock),
+            ..self
+        }
+    }
+
+    /// Delay every request by `latency`, like a far away Github would.
+    pub fn latency(self, latency: Duration) -> Self {
+        Self {
+            latency: Some(latency),
+            ..self
+        }
+    }
+
+    /// Answer requests with an error, until it has been returned as many
+    /// times as it's good for. Errors are checked in the order they're
+    /// given.
+    pub fn inject_error(mut self, injected: InjectedError) -> Self {
+        self.injected_errors.push(injected);
+        self
+    }
+
+    /// Start out with a client, whose redirects must fall under the
+    /// default redirect URL.
+    pub fn client(mut self, client_id: &str, client_secret: &str) -> Self {
+        self.fixtures.clients.push(ClientFixture {
+            id: client_id.to_owned(),
+            secret: client_secret.to_owned(),
+            redirect_url: None,
+        });
+        self
+    }
+
+    /// Start out with a client, whose redirects must fall under the
+    /// given callback URL.
+    pub fn client_with_redirect_url(
+        mut self,
+        client_id: &str,
+        client_secret: &str,
+        redirect_url: &str,
+    ) -> Self {
+        self.fixtures.clients.push(ClientFixture {
+            id: client_id.to_owned(),
+            secret: client_secret.to_owned(),
+            redirect_url: Some(redirect_url.to_owned()),
+        });
+        self
+    }
+
+    /// Start out with a user.
+    pub fn user(mut self, user_id: UserId, user: User) -> Self {
+        self.fixtures.users.push(UserFixture {
+            id: user_id,
+            login: user.login,
+            avatar_url: Some(user.avatar_url),
+            html_url: Some(user.html_url),
+            emails: Vec::new(),
+            ssh_keys: Vec::new(),
+            gpg_keys: Vec::new(),
+        });
+        self
+    }
+
+    /// Start out with fixtures, as [`Fakehub::load`] loads them, after
+    /// any clients and users given so far.
+    pub fn fixtures(mut self, fixtures: Fixtures) -> Self {
+        self.fixtures.clients.extend(fixtures.clients);
+        self.fixtures.users.extend(fixtures.users);
+        self.fixtures.orgs.extend(fixtures.orgs);
+        self.fixtures.teams.extend(fixtures.teams);
+        self.fixtures.repos.extend(fixtures.repos);
+        self.fixtures.webhooks.extend(fixtures.webhooks);
+        self
+    }
+
+    /// Start the servers and load the clients, users and other fixtures.
+    pub async fn start(mut self) -> Result<Fakehub> {
+        let listeners = match self.listeners.take() {
+            Some(listeners) => listeners,
+            None => (
+                bind(SocketAddr::new(self.host, self.web_port))?,
+                bind(SocketAddr::new(self.host, self.api_port))?,
+            ),
+        };
+
+        self.launch(Some(listeners)).await
+    }
+
+    /// Start a Fakehub like [`Fakehub::new_in_process`] does, binding no
+    /// sockets whatever the host, ports and listeners, and load the
+    /// clients, users and other fixtures.
+    pub async fn start_in_process(self) -> Result<Fakehub> {
+        self.launch(None).await
+    }
+
+    async fn launch(self, listeners: Option<(TcpListener, TcpListener)>) -> Result<Fakehub> {
+        let mut state = FakehubState::new_with_flavor(self.flavor);
+
+        state.default_redirect_url = Url::parse(&self.default_redirect_url)?;
+        state.code_format = self.code_format;
+        state.token_format = self.token_format;
+        state.clock = self.clock;
+        state.latency = self.latency;
+        state.injected_errors = self.injected_errors;
+
+        let fakehub = Fakehub::from_state(state, listeners)?;
+
+        fakehub.load(&self.fixtures).await?;
+
+        Ok(fakehub)
+    }
+}
+
+impl Default for FakehubBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        fakehub::{Clock, Fakehub, InjectedError, ManualClock, TokenFormat},
+        testing::{add_test_user, user_named, CLIENT_ID, CLIENT_SECRET, USER, USER_ID},
+        GithubClient,
+    };
+
+    #[tokio::test]
+    async fn fakehub_builder() {
+        let clock = ManualClock::default();
+        let fakehub = Fakehub::builder()
+            .client_with_redirect_url(CLIENT_ID, CLIENT_SECRET, "http://localhost:3000/callback")
+            .user(USER_ID, user_named(USER))
+            .code_format(TokenFormat::Random)
+            .token_format(TokenFormat::Random)
+            .clock(clock.clone())
+            .latency(Duration::from_millis(20))
+            .inject_error(InjectedError::new("/user", 502).times(1))
+            .start()
+            .await
+            .expect("cannot start local fakehub server");
+        let github_client =
+            GithubClient::new_with_provider(CLIENT_ID, CLIENT_SECRET, fakehub.provider()).unwrap();
+
+        let dump = reqwest::get(format!("{}/state", fakehub.admin_url()))
+            .await
+            .unwrap()
+            .json::<serde_json::Value>()
+            .await
+            .unwrap();
+        assert_eq!(
+            "http://localhost:3000/callback",
+            dump["clients"][0]["redirect_url"]
+        );
+
+        let code = fakehub.get_code(USER_ID).await.unwrap();
+        assert_eq!(20, code.len());
+        assert_ne!(code, fakehub.get_code(USER_ID).await.unwrap());
+
+        let token = github_client.get_access_token(&code).await.unwrap();
+        assert!(token.access_token.starts_with("gho_"));
+        assert_eq!(40, token.access_token.len());
+
+        // the first request to /user fails, and every request is slowed
+        let started = std::time::Instant::now();
+        assert!(github_client
+            .get_user_detail(&token.access_token)
+            .await
+            .is_err());
+        let user_detail = github_client
+            .get_user_detail(&token.access_token)
+            .await
+            .unwrap();
+        assert_eq!(USER, user_detail.login);
+        assert!(started.elapsed() >= Duration::from_millis(40));
+
+        // expiring tokens expire by the clock Fakehub was built with
+        fakehub.expire_user_tokens(chrono::Duration::hours(1)).await;
+        let code = fakehub.get_code(USER_ID).await.unwrap();
+        let token = github_client.get_access_token(&code).await.unwrap();
+        assert!(github_client
+            .get_user_detail(&token.access_token)
+            .await
+            .is_ok());
+        clock.advance(chrono::Duration::hours(2));
+        assert!(github_client
+            .get_user_detail(&token.access_token)
+            .await
+            .is_err());
+
+        // so are the OIDC tokens it mints
+        let oidc_token = fakehub
+            .mint_oidc_token("https://example.com", serde_json::json!({}))
+            .await
+            .unwrap();
+        let mut unverified = jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::RS256);
+        unverified.insecure_disable_signature_validation();
+        unverified.validate_exp = false;
+        unverified.validate_aud = false;
+        let oidc_claims = jsonwebtoken::decode::<serde_json::Value>(
+            &oidc_token,
+            &jsonwebtoken::DecodingKey::from_secret(&[]),
+            &unverified,
+        )
+        .unwrap()
+        .claims;
+        assert_eq!(clock.now().timestamp(), oidc_claims["iat"]);
+
+        // resetting forgets the users, but not how Fakehub was built
+        fakehub.reset().await;
+        add_test_user(&fakehub).await;
+        assert_eq!(20, fakehub.get_code(USER_ID).await.unwrap().len());
+
+        fakehub.shutdown().await;
+    }
+}
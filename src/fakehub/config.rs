@@ -0,0 +1,348 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Permission;
+
+use super::{
+    error::{Error, Result},
+    state::{Flavor, OrgId, RepoId, TeamId, UserId},
+};
+
+/// Where a standalone Fakehub listens, and what it starts out with, as
+/// read from a TOML, JSON or YAML file. Every field may be left out.
+///
+/// ```toml
+/// host = "127.0.0.1"
+/// web_port = 3050
+/// api_port = 3051
+/// flavor = "github"
+///
+/// [[clients]]
+/// id = "1234567890"
+/// secret = "SECRET_SQUIRREL_STUFF"
+/// redirect_url = "http://localhost:3000/callback"
+///
+/// [[users]]
+/// id = 1
+/// login = "octocat"
+/// emails = [{ email = "octocat@example.com", verified = true }]
+///
+/// [[orgs]]
+/// id = 100
+/// login = "acme"
+/// members = [1]
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct FakehubConfig {
+    /// The address both servers bind to.
+    #[serde(default = "default_host")]
+    pub host: IpAddr,
+    /// The port of the web host, github.com.
+    #[serde(default = "default_web_port")]
+    pub web_port: u16,
+    /// The port of the API host, api.github.com. Flavors other than
+    /// Github serve their API from the web host as well.
+    #[serde(default = "default_api_port")]
+    pub api_port: u16,
+    #[serde(default)]
+    pub flavor: Flavor,
+    #[serde(flatten)]
+    pub fixtures: Fixtures,
+}
+
+impl FakehubConfig {
+    /// Read a config file, in the format its extension names: `.toml`,
+    /// `.json`, or with the `fakehub-bin` feature, `.yaml` or `.yml`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|error| Error::Config(format!("cannot read {}: {}", path.display(), error)))?;
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => Self::from_toml(&contents),
+            Some("json") => Self::from_json(&contents),
+            #[cfg(feature = "fakehub-bin")]
+            Some("yaml" | "yml") => Self::from_yaml(&contents),
+            _ => Err(Error::Config(format!(
+                "cannot tell the format of {} from its extension",
+                path.display()
+            ))),
+        }
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self> {
+        toml::from_str(contents).map_err(|error| Error::Config(error.to_string()))
+    }
+
+    pub fn from_json(contents: &str) -> Result<Self> {
+        serde_json::from_str(contents).map_err(|error| Error::Config(error.to_string()))
+    }
+
+    #[cfg(feature = "fakehub-bin")]
+    pub fn from_yaml(contents: &str) -> Result<Self> {
+        serde_yaml::from_str(contents).map_err(|error| Error::Config(error.to_string()))
+    }
+}
+
+impl Default for FakehubConfig {
+    fn default() -> Self {
+        Self {
+            host: default_host(),
+            web_port: default_web_port(),
+            api_port: default_api_port(),
+            flavor: Flavor::default(),
+            fixtures: Fixtures::default(),
+        }
+    }
+}
+
+fn default_host() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::LOCALHOST)
+}
+
+fn default_web_port() -> u16 {
+    3050
+}
+
+fn default_api_port() -> u16 {
+    3051
+}
+
+/// What a Fakehub is seeded with, see [`crate::fakehub::Fakehub::load`].
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Fixtures {
+    #[serde(default)]
+    pub clients: Vec<ClientFixture>,
+    #[serde(default)]
+    pub users: Vec<UserFixture>,
+    #[serde(default)]
+    pub orgs: Vec<OrgFixture>,
+    #[serde(default)]
+    pub teams: Vec<TeamFixture>,
+    #[serde(default)]
+    pub repos: Vec<RepoFixture>,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookFixture>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientFixture {
+    pub id: String,
+    pub secret: String,
+    /// The app's callback URL, which redirects must fall under.
+    /// Defaults to `http://127.0.0.1`, which allows any port and path.
+    #[serde(default)]
+    pub redirect_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserFixture {
+    pub id: UserId,
+    pub login: String,
+    /// Defaults to the user's Github avatar.
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+    /// Defaults to the user's Github profile.
+    #[serde(default)]
+    pub html_url: Option<String>,
+    /// The first is the user's primary address.
+    #[serde(default)]
+    pub emails: Vec<EmailFixture>,
+    /// In the OpenSSH format.
+    #[serde(default)]
+    pub ssh_keys: Vec<String>,
+    /// ASCII armored.
+    #[serde(default)]
+    pub gpg_keys: Vec<String>,
+}
+
+impl UserFixture {
+    pub(crate) fn avatar_url(&self) -> String {
+        self.avatar_url
+            .clone()
+            .unwrap_or_else(|| format!("https://github.com/{}.png", self.login))
+    }
+
+    pub(crate) fn html_url(&self) -> String {
+        self.html_url
+            .clone()
+            .unwrap_or_else(|| format!("https://github.com/{}", self.login))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailFixture {
+    pub email: String,
+    #[serde(default)]
+    pub verified: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OrgFixture {
+    pub id: OrgId,
+    pub login: String,
+    #[serde(default)]
+    pub members: Vec<UserId>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TeamFixture {
+    pub id: TeamId,
+    pub org_id: OrgId,
+    pub name: String,
+    /// Defaults to the name, lowercased and hyphenated.
+    #[serde(default)]
+    pub slug: Option<String>,
+    #[serde(default)]
+    pub members: Vec<UserId>,
+}
+
+impl TeamFixture {
+    pub(crate) fn slug(&self) -> String {
+        self.slug
+            .clone()
+            .unwrap_or_else(|| self.name.to_lowercase().replace(' ', "-"))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepoFixture {
+    pub id: RepoId,
+    /// The id of the user or org the repository belongs to.
+    pub owner_id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub private: bool,
+    #[serde(default)]
+    pub collaborators: Vec<CollaboratorFixture>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CollaboratorFixture {
+    pub user_id: UserId,
+    pub permission: Permission,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookFixture {
+    pub url: String,
+    pub secret: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        fakehub::{Fakehub, Flavor},
+        testing::{CLIENT_ID, CLIENT_SECRET, REPO_ID, USER, USER_ID},
+        GithubClient, Permission,
+    };
+
+    use super::FakehubConfig;
+
+    #[tokio::test]
+    async fn fakehub_config() {
+        let config = FakehubConfig::from_path("testdata/fakehub.toml").unwrap();
+        assert_eq!(3050, config.web_port);
+        assert_eq!(Flavor::Github, config.flavor);
+        assert_eq!("core-team", config.fixtures.teams[0].slug());
+
+        // the same fixtures, as JSON
+        let json = FakehubConfig::from_json(
+            &json!({
+                "clients": [{ "id": CLIENT_ID, "secret": CLIENT_SECRET }],
+                "users": [{ "id": USER_ID, "login": USER }],
+                "repos": [{
+                    "id": REPO_ID,
+                    "owner_id": USER_ID,
+                    "name": "dotfiles",
+                    "collaborators": [{ "user_id": USER_ID, "permission": "admin" }],
+                }],
+            })
+            .to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            Permission::Admin,
+            json.fixtures.repos[0].collaborators[0].permission
+        );
+        assert!(FakehubConfig::from_toml("flavor = \"sourceforge\"").is_err());
+        #[cfg(feature = "fakehub-bin")]
+        assert_eq!(
+            Flavor::Gitea,
+            FakehubConfig::from_yaml("flavor: gitea\nusers: []\n")
+                .unwrap()
+                .flavor
+        );
+
+        let fakehub = Fakehub::from_config(&FakehubConfig {
+            web_port: 0,
+            api_port: 0,
+            ..config.clone()
+        })
+        .await
+        .unwrap();
+        let github_client = GithubClient::new_with_provider(
+            "1234567890",
+            "SECRET_SQUIRREL_STUFF",
+            fakehub.provider(),
+        )
+        .unwrap();
+
+        let code = fakehub.get_code(1).await.unwrap();
+        let token = github_client.get_access_token(&code).await.unwrap();
+        let orgs = github_client
+            .list_user_orgs(&token.access_token)
+            .await
+            .unwrap();
+        assert_eq!("acme", orgs[0].login);
+        let keys = github_client.list_ssh_keys("octocat").await.unwrap();
+        assert_eq!(1, keys.len());
+        let permission = github_client
+            .get_repo_permission(&token.access_token, "acme", "widgets", "hubot")
+            .await
+            .unwrap();
+        assert_eq!(Permission::Triage, permission);
+
+        // reloading starts over from the fixtures
+        fakehub.reset().await;
+        assert!(github_client.list_ssh_keys("octocat").await.is_err());
+        fakehub.load(&json.fixtures).await.unwrap();
+        let github_client =
+            GithubClient::new_with_provider(CLIENT_ID, CLIENT_SECRET, fakehub.provider()).unwrap();
+        let code = fakehub.get_code(USER_ID).await.unwrap();
+        let token = github_client.get_access_token(&code).await.unwrap();
+        let user_detail = github_client
+            .get_user_detail(&token.access_token)
+            .await
+            .unwrap();
+        assert_eq!(USER, user_detail.login);
+        assert_eq!("https://github.com/user", user_detail.html_url);
+
+        // an explicitly configured port that's taken is an error
+        let taken = FakehubConfig {
+            web_port: fakehub.github_dot_com_socket().port(),
+            api_port: 0,
+            ..FakehubConfig::default()
+        };
+        assert!(matches!(
+            Fakehub::from_config(&taken).await,
+            Err(crate::fakehub::Error::Bind(..))
+        ));
+
+        fakehub.shutdown().await;
+    }
+}
@@ -1,17 +1,27 @@
+use std::net::SocketAddr;
+
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use thiserror::Error;
 
-use crate::fakehub::state::UserId;
+use crate::fakehub::state::{OrgId, TeamId, UserId};
 
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("No free port available, tried from {0} upward.")]
-    NoAvailablePorts(u16),
+    #[error("Cannot bind {0}: {1}")]
+    Bind(SocketAddr, String),
+    #[error("Invalid config: {0}")]
+    Config(String),
     #[error("No user with id {0} exists.")]
     NoSuchUserId(UserId),
     #[error("No user with login {0} exists.")]
     NoSuchUserLogin(String),
+    #[error("No org with id {0} exists.")]
+    NoSuchOrg(OrgId),
+    #[error("No team with id {0} exists.")]
+    NoSuchTeam(TeamId),
+    #[error("No repository {0} exists.")]
+    NoSuchRepo(String),
     #[error("Failed to parse URL {0}")]
     UrlParse(String),
     #[error("Authentication URL is missing a client id")]
@@ -24,7 +34,7 @@ pub enum Error {
     HostlessBase(String),
     #[error("Host {1} does not match base of {0}")]
     InvalidHost(String, String),
-    #[error("Header {0} contains an non-ASCII value")]
+    #[error("Header {0} contains a non-ASCII value")]
     InvalidHeader(String),
     #[error("Path {1} does not match base of {0}")]
     InvalidBasePath(String, String),
@@ -36,6 +46,16 @@ pub enum Error {
     Decode(String),
     #[error("{0}")]
     OtherHttp(String),
+    #[error("{0}")]
+    Encode(String),
+    #[error("Invalid JWT: {0}")]
+    InvalidJwt(String),
+    #[error("No app with id {0} exists.")]
+    NoSuchApp(i64),
+    #[error("No installation with id {0} exists.")]
+    NoSuchInstallation(i64),
+    #[error("{0}")]
+    Unprocessable(String),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -53,19 +73,55 @@ impl From<crate::Error> for Error {
             crate::Error::Http(code, reason) => Self::Http(code, reason),
             crate::Error::Decode(reason) => Self::Decode(reason),
             crate::Error::OtherHttp(reason) => Self::OtherHttp(reason),
+            crate::Error::Jwt(reason) => Self::InvalidJwt(reason),
+            crate::Error::Interceptor(reason) => Self::OtherHttp(reason),
+            crate::Error::OAuth(error, _) => Self::OtherHttp(error),
+            crate::Error::Callback(reason) => Self::UrlParse(reason),
+            crate::Error::TokenStore(reason) => Self::OtherHttp(reason),
+            crate::Error::IdentityRepository(reason) => Self::OtherHttp(reason),
+            crate::Error::MalformedKey(reason) => Self::Unprocessable(reason),
+            error @ crate::Error::GraphQL(_) => Self::OtherHttp(error.to_string()),
+            crate::Error::Unsupported(reason) => Self::OtherHttp(reason),
+            crate::Error::Timeout(reason) => Self::OtherHttp(reason),
+            crate::Error::Incomplete(reason) => Self::OtherHttp(reason),
+            error @ crate::Error::InvalidName(_) => Self::Unprocessable(error.to_string()),
+            crate::Error::TokenExpired => Self::Unauthorized,
         }
     }
 }
 
+impl From<reqwest::Error> for Error {
+    fn from(value: reqwest::Error) -> Self {
+        crate::Error::from(value).into()
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Encode(format!("{}", value))
+    }
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
         match self {
-            Self::NoAvailablePorts(_) => {
-                (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", self)).into_response()
-            }
             Self::NoSuchUserId(_) => {
                 (StatusCode::UNAUTHORIZED, format!("{}", self)).into_response()
             }
+            Self::NoSuchOrg(_) => (StatusCode::NOT_FOUND, format!("{}", self)).into_response(),
+            Self::NoSuchTeam(_) => (StatusCode::NOT_FOUND, format!("{}", self)).into_response(),
+            Self::NoSuchRepo(_) => (StatusCode::NOT_FOUND, format!("{}", self)).into_response(),
+            Self::NoSuchUserLogin(_) => {
+                (StatusCode::NOT_FOUND, format!("{}", self)).into_response()
+            }
+            Self::InvalidJwt(_) => (StatusCode::UNAUTHORIZED, format!("{}", self)).into_response(),
+            Self::NoSuchApp(_) => (StatusCode::UNAUTHORIZED, format!("{}", self)).into_response(),
+            Self::NoSuchInstallation(_) => {
+                (StatusCode::NOT_FOUND, format!("{}", self)).into_response()
+            }
+            Self::Unprocessable(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, format!("{}", self)).into_response()
+            }
             Self::UrlParse(_) => {
                 (StatusCode::UNPROCESSABLE_ENTITY, format!("{}", self)).into_response()
             }
@@ -0,0 +1,102 @@
+use axum::{
+    extract::State,
+    http::{Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+use super::{admin::ADMIN_PREFIX, state::FakehubStateRef};
+
+/// An error Fakehub answers requests with instead of doing what they
+/// ask, for testing how an app copes with Github having a bad day.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InjectedError {
+    path: String,
+    status: u16,
+    times: Option<u32>,
+}
+
+impl InjectedError {
+    /// Answer every request whose path starts with `path` with `status`,
+    /// on whichever host serves it.
+    pub fn new(path: &str, status: u16) -> Self {
+        Self {
+            path: path.to_owned(),
+            status,
+            times: None,
+        }
+    }
+
+    /// Only fail the next `times` matching requests, then answer as
+    /// usual.
+    pub fn times(self, times: u32) -> Self {
+        Self {
+            times: Some(times),
+            ..self
+        }
+    }
+
+    /// Whether this error applies to a request for the path, counting
+    /// the request against its remaining times if so.
+    fn take(&mut self, path: &str) -> bool {
+        if !path.starts_with(&self.path) {
+            return false;
+        }
+
+        match &mut self.times {
+            Some(0) => false,
+            Some(times) => {
+                *times -= 1;
+                true
+            }
+            None => true,
+        }
+    }
+}
+
+/// Delay every request by the configured latency, and answer those an
+/// [`InjectedError`] matches with its status. The admin API is left
+/// alone, so tests can always reach it.
+pub(super) async fn inject_faults<B>(
+    State(fakehub_state): State<FakehubStateRef>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let path = request.uri().path();
+
+    if path.starts_with(ADMIN_PREFIX) {
+        return next.run(request).await;
+    }
+
+    let (latency, status) = {
+        let mut fakehub_state = fakehub_state.lock().await;
+        let status = fakehub_state
+            .injected_errors
+            .iter_mut()
+            .find_map(|injected| injected.take(path).then_some(injected.status));
+
+        (fakehub_state.latency, status)
+    };
+
+    if let Some(latency) = latency {
+        tokio::time::sleep(latency).await;
+    }
+
+    match status {
+        Some(status) => {
+            let status = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+
+            (
+                status,
+                Json(json!({
+                    "message": status.canonical_reason().unwrap_or("Injected error"),
+                    "documentation_url": "https://docs.github.com/rest",
+                })),
+            )
+                .into_response()
+        }
+        None => next.run(request).await,
+    }
+}
@@ -1,14 +1,26 @@
+use std::net::TcpListener;
+
 use axum::{
-    extract::{Query, State},
+    extract::{Host, Path, Query, RawQuery, State},
     http::StatusCode,
-    response::{IntoResponse, Response},
+    middleware,
+    response::{Html, IntoResponse, Response},
     routing::{get, post},
     Form, Json, Router,
 };
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
+use serde_json::{json, Value};
 use url::Url;
 
-use super::{error::Result, state::FakehubStateRef, temp_server::TempServer};
+use crate::GetAccessTokenResponse;
+
+use super::{
+    admin::{self, ADMIN_PREFIX},
+    error::Result,
+    faults,
+    state::{CodeRefusal, FakehubStateRef, Flavor, UserToken},
+    temp_server::{self, TempServer},
+};
 
 /// A fake implementation of github.com, complete enough to stand in for
 /// the real thing in an integration tested OAuth flow. Which isn't very
@@ -19,16 +31,14 @@ pub struct GithubDotCom {
 }
 
 impl GithubDotCom {
-    /// Create and start a new fake github.com.
-    pub fn new(starting_port: u16, fakehub_state: FakehubStateRef) -> Result<Self> {
-        let app = Router::new()
-            .route("/", get(root))
-            .route("/login/oauth/authorize", get(login_page).post(issue_code))
-            .route("/login/oauth/access_token", post(exchange_code_for_token))
-            .with_state(fakehub_state);
-
+    /// Create and start a new fake github.com on a bound listener.
+    pub fn new(
+        listener: TcpListener,
+        fakehub_state: FakehubStateRef,
+        flavor: Flavor,
+    ) -> Result<Self> {
         Ok(Self {
-            _temp_server: TempServer::new(starting_port, app)?,
+            _temp_server: TempServer::new(listener, app(fakehub_state, flavor))?,
         })
     }
 
@@ -38,30 +48,92 @@ impl GithubDotCom {
     }
 }
 
+/// Everything github.com serves, whether from a socket or in memory.
+pub(super) fn app(fakehub_state: FakehubStateRef, flavor: Flavor) -> Router {
+    let app = Router::new()
+        .route("/", get(root))
+        .route("/login/oauth/authorize", get(login_page).post(issue_code))
+        .route("/login/oauth/authorize/cancel", post(deny_authorization))
+        .route("/login/oauth/access_token", post(exchange_code_for_token))
+        .route(
+            "/_services/token/.well-known/openid-configuration",
+            get(openid_configuration),
+        )
+        .route("/_services/token/.well-known/jwks", get(jwks))
+        .route("/:keys", get(user_keys));
+    let app = match flavor.api_prefix() {
+        Some(_) => app.merge(super::api_gh::routes(flavor)),
+        None => app,
+    };
+
+    app.nest(ADMIN_PREFIX, admin::routes())
+        .layer(middleware::from_fn_with_state(
+            fakehub_state.clone(),
+            faults::inject_faults,
+        ))
+        .layer(middleware::from_fn_with_state(
+            fakehub_state.clone(),
+            admin::capture_request,
+        ))
+        .layer(middleware::from_fn(temp_server::log_request))
+        .with_state(fakehub_state)
+}
+
 // GET /
 async fn root() -> &'static str {
     "Hello, world!"
 }
 
+// GET /:login.keys
+// GET /:login.gpg
+// A user's SSH keys, one per line, or their armored GPG keys, as plain
+// text.
+async fn user_keys(
+    State(fakehub_state): State<FakehubStateRef>,
+    Path(file): Path<String>,
+) -> Result<Response> {
+    let fakehub_state = fakehub_state.lock().await;
+
+    if let Some(login) = file.strip_suffix(".keys") {
+        return Ok(fakehub_state
+            .user_ssh_keys(login)?
+            .into_iter()
+            .map(|key| format!("{}\n", key.key))
+            .collect::<String>()
+            .into_response());
+    }
+
+    if let Some(login) = file.strip_suffix(".gpg") {
+        return Ok(fakehub_state
+            .user_gpg_keys(login)?
+            .into_iter()
+            .filter_map(|key| key.raw_key)
+            .collect::<Vec<_>>()
+            .join("\n")
+            .into_response());
+    }
+
+    Ok(StatusCode::NOT_FOUND.into_response())
+}
+
 // GET /login/oauth/authorize?client_id=:client_id
 async fn login_page(
     State(fakehub_state): State<FakehubStateRef>,
-    Query(LoginPageQueryParams { client_id }): Query<LoginPageQueryParams>,
-) -> String {
-    let fakehub_state = fakehub_state.lock().await;
+    RawQuery(query): RawQuery,
+) -> Html<String> {
+    let mut fakehub_state = fakehub_state.lock().await;
 
-    super::login_page::render(
-        &client_id,
+    fakehub_state.counters.login_page_views += 1;
+
+    // the login forms post back every parameter the authorization was
+    // started with, plus the chosen user
+    Html(super::login_page::render(
+        query.as_deref().unwrap_or_default(),
         fakehub_state
             .users
             .iter()
             .map(|(k, v)| (*k, v.login.as_str())),
-    )
-}
-
-#[derive(Debug, Deserialize)]
-struct LoginPageQueryParams {
-    client_id: String,
+    ))
 }
 
 // POST /login/oauth/authorize?client_id=:client_id&user_id=:user_id
@@ -74,6 +146,8 @@ async fn issue_code(
         client_id,
         user_id,
         redirect_uri,
+        state,
+        code_challenge,
     }): Query<IssueCodeQueryParams>,
 ) -> Result<Response> {
     let redirect_uri = match redirect_uri {
@@ -86,80 +160,193 @@ async fn issue_code(
         Some(redirect_uri) => client.check_redirect_url(&redirect_uri)?,
         None => client.redirect_url.clone(),
     };
-    let code = fakehub_state.get_code(user_id)?;
+    let code = fakehub_state.get_code_with_challenge(user_id, code_challenge)?;
 
     // check_redirect also picks the request-supplied redirect if it is
-    // more specific, so all that remains is to add the issued code to
-    // the uri's query parameters
+    // more specific, so all that remains is to add the issued code and
+    // state to the uri's query parameters
     redirect_uri.query_pairs_mut().append_pair("code", &code);
+    if let Some(state) = state {
+        redirect_uri.query_pairs_mut().append_pair("state", &state);
+    }
+
+    Ok((StatusCode::FOUND, [("Location", redirect_uri.as_str())]).into_response())
+}
+
+// POST /login/oauth/authorize/cancel?client_id=:client_id
+// Expect redirect to application redirect url with an access_denied
+// error, as when a user turns the app down on Github
+async fn deny_authorization(
+    State(fakehub_state): State<FakehubStateRef>,
+    Query(DenyAuthorizationQueryParams {
+        client_id,
+        redirect_uri,
+        state,
+    }): Query<DenyAuthorizationQueryParams>,
+) -> Result<Response> {
+    let redirect_uri = match redirect_uri {
+        Some(redirect_uri) => Some(Url::parse(&redirect_uri)?),
+        None => None,
+    };
+    let fakehub_state = fakehub_state.lock().await;
+    let client = fakehub_state.get_client(&client_id)?;
+    let mut redirect_uri = match redirect_uri {
+        Some(redirect_uri) => client.check_redirect_url(&redirect_uri)?,
+        None => client.redirect_url.clone(),
+    };
+
+    redirect_uri
+        .query_pairs_mut()
+        .append_pair("error", "access_denied")
+        .append_pair(
+            "error_description",
+            "The user has denied your application access.",
+        )
+        .append_pair(
+            "error_uri",
+            "https://docs.github.com/apps/managing-oauth-apps/troubleshooting-authorization-request-errors/#access-denied",
+        );
+    if let Some(state) = state {
+        redirect_uri.query_pairs_mut().append_pair("state", &state);
+    }
 
     Ok((StatusCode::FOUND, [("Location", redirect_uri.as_str())]).into_response())
 }
 
+#[derive(Debug, Deserialize)]
+struct DenyAuthorizationQueryParams {
+    client_id: String,
+    redirect_uri: Option<String>,
+    state: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 struct IssueCodeQueryParams {
     client_id: String,
     user_id: i64,
     redirect_uri: Option<String>,
+    state: Option<String>,
+    code_challenge: Option<String>,
 }
 
 // POST /login/oauth/access_token
+// Exchanges either a login code or, with grant_type=refresh_token, a
+// refresh token
 async fn exchange_code_for_token(
     State(fakehub_state): State<FakehubStateRef>,
     Form(ExchangeCodeForTokenFormParams {
         client_id,
         client_secret,
+        grant_type,
         code,
+        code_verifier,
+        refresh_token,
     }): Form<ExchangeCodeForTokenFormParams>,
 ) -> ExchangeCodeForTokenResponse {
     let mut fakehub_state = fakehub_state.lock().await;
-
-    if !fakehub_state.client_matches(&client_id, &client_secret) {
-        return ExchangeCodeForTokenResponse::BadClient;
-    }
-
-    let user_id = match fakehub_state.pop_code(&code) {
-        Some(code) => code,
-        None => {
-            return ExchangeCodeForTokenResponse::BadCode;
+    let flavor = fakehub_state.flavor;
+    let token = match (flavor, grant_type.as_deref()) {
+        (_, Some("refresh_token")) => fakehub_state.refresh_token(
+            &client_id,
+            &client_secret,
+            refresh_token.as_deref().unwrap_or_default(),
+        ),
+        // Gitea insists on being told which grant it is
+        (Flavor::Gitea, grant_type) if grant_type != Some("authorization_code") => {
+            return ExchangeCodeForTokenResponse::UnsupportedGrantType
         }
+        _ => fakehub_state.exchange_code(
+            &client_id,
+            &client_secret,
+            code.as_deref().unwrap_or_default(),
+            code_verifier.as_deref(),
+        ),
     };
 
-    let token = fakehub_state.push_token(user_id);
-
-    ExchangeCodeForTokenResponse::Token(Json(TokenResponse {
-        access_token: token.to_owned(),
-        scope: "".to_owned(),
-        token_type: "bearer".to_owned(),
-    }))
+    match (flavor, token) {
+        (Flavor::Gitea, Ok(token)) => ExchangeCodeForTokenResponse::GiteaToken(token),
+        (_, Ok(token)) => ExchangeCodeForTokenResponse::Token(Json(token.into())),
+        (Flavor::Gitea, Err(refusal)) => ExchangeCodeForTokenResponse::GiteaRefused(refusal),
+        (_, Err(refusal)) => ExchangeCodeForTokenResponse::Refused(refusal),
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct ExchangeCodeForTokenFormParams {
     client_id: String,
     client_secret: String,
-    code: String,
+    grant_type: Option<String>,
+    code: Option<String>,
+    code_verifier: Option<String>,
+    refresh_token: Option<String>,
 }
 
 enum ExchangeCodeForTokenResponse {
-    Token(Json<TokenResponse>),
-    BadClient,
-    BadCode,
+    Token(Json<GetAccessTokenResponse>),
+    Refused(CodeRefusal),
+    GiteaToken(UserToken),
+    GiteaRefused(CodeRefusal),
+    UnsupportedGrantType,
 }
 
 impl IntoResponse for ExchangeCodeForTokenResponse {
     fn into_response(self) -> Response {
         match self {
             Self::Token(t) => t.into_response(),
-            Self::BadClient => (StatusCode::FORBIDDEN, "Forbidden - Bad Client").into_response(),
-            Self::BadCode => (StatusCode::FORBIDDEN, "Forbidden - Bad Code").into_response(),
+            // Github refuses with a 200 and an OAuth error in the body
+            Self::Refused(refusal) => Json(json!({
+                "error": refusal.error(),
+                "error_description": refusal.description(),
+                "error_uri": "https://docs.github.com/apps/managing-oauth-apps/troubleshooting-oauth-app-access-token-request-errors",
+            }))
+            .into_response(),
+            // Gitea leaves out the scopes
+            Self::GiteaToken(token) => Json(json!({
+                "access_token": token.access_token,
+                "token_type": "bearer",
+                "expires_in": token.expires_in,
+                "refresh_token": token.refresh_token,
+            }))
+            .into_response(),
+            // and refuses with a 400, as the spec has it
+            Self::GiteaRefused(refusal) => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": refusal.spec_error(),
+                    "error_description": refusal.description(),
+                })),
+            )
+                .into_response(),
+            Self::UnsupportedGrantType => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({
+                    "error": "unsupported_grant_type",
+                    "error_description": "Only refresh_token or authorization_code grant type is supported",
+                })),
+            )
+                .into_response(),
         }
     }
 }
 
-#[derive(Debug, Serialize)]
-struct TokenResponse {
-    access_token: String,
-    scope: String,
-    token_type: String,
+// GET /_services/token/.well-known/openid-configuration
+// Github Enterprise Server publishes its Actions OIDC issuer under this
+// path, which saves Fakehub from running yet another server.
+async fn openid_configuration(Host(host): Host) -> Json<Value> {
+    let issuer = format!("http://{}/_services/token", host);
+
+    Json(json!({
+        "issuer": issuer,
+        "jwks_uri": format!("{}/.well-known/jwks", issuer),
+        "response_types_supported": ["id_token"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256"],
+    }))
+}
+
+// GET /_services/token/.well-known/jwks
+async fn jwks(State(fakehub_state): State<FakehubStateRef>) -> Json<Value> {
+    let fakehub_state = fakehub_state.lock().await;
+
+    Json(fakehub_state.oidc_issuer.jwks())
 }
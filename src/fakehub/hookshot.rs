@@ -0,0 +1,56 @@
+use reqwest::Client as ReqwestClient;
+
+use crate::webhooks::{
+    DeliveryId, Event, Signature, DELIVERY_HEADER, EVENT_HEADER, SIGNATURE_256_HEADER,
+    SIGNATURE_HEADER,
+};
+
+use super::{error::Result, state::Webhook};
+
+/// Delivers signed webhook events, much like Github's own Hookshot.
+#[derive(Clone, Debug)]
+pub struct Hookshot {
+    http_client: ReqwestClient,
+}
+
+impl Hookshot {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            http_client: reqwest::ClientBuilder::new()
+                .user_agent("GitHub-Hookshot/fakehub")
+                .build()?,
+        })
+    }
+
+    /// Post a payload to a webhook, signed with both the current and
+    /// legacy signature schemes.
+    pub async fn deliver(
+        &self,
+        webhook: &Webhook,
+        delivery: &DeliveryId,
+        event: &Event,
+        payload: &[u8],
+    ) -> Result<()> {
+        let secret = webhook.secret.as_bytes();
+
+        self.http_client
+            .post(webhook.url.clone())
+            .header("Content-Type", "application/json")
+            .header(EVENT_HEADER, event.as_str())
+            .header(DELIVERY_HEADER, delivery.as_str())
+            .header(
+                SIGNATURE_256_HEADER,
+                Signature::sha256(secret, payload).to_string(),
+            )
+            .header(
+                SIGNATURE_HEADER,
+                Signature::sha1(secret, payload).to_string(),
+            )
+            .body(payload.to_owned())
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
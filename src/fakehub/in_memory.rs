@@ -0,0 +1,336 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::response::IntoResponse;
+use tokio::sync::Mutex;
+use url::Url;
+
+use crate::{
+    client::authorization_url_with,
+    graphql::ViewerIdentity,
+    keys::{GpgKey, SshKey, SshSigningKey},
+    shapes::{
+        Affiliation, AuthorizationRequest, Email, GetAccessTokenResponse, Organization, Permission,
+        Repository, Team,
+    },
+    GithubApi, UserDetailResponse,
+};
+
+use super::{
+    state::{Client, CodeRefusal, FakehubState, FakehubStateRef, UserId},
+    Error, Result, User,
+};
+
+/// A [`GithubApi`] answered straight from Fakehub's state, without
+/// starting any servers or making any HTTP requests.
+///
+/// Make one on its own with [`InMemoryGithub::new`], or share the state
+/// of a running Fakehub with
+/// [`crate::fakehub::Fakehub::in_memory_client`].
+#[derive(Clone)]
+pub struct InMemoryGithub {
+    state: FakehubStateRef,
+    client_id: String,
+    client_secret: String,
+    /// The base url that authorization urls are based on.
+    base_url: String,
+    authorize_url: Url,
+}
+
+impl InMemoryGithub {
+    /// Create an in-memory Github with a client and nothing else.
+    pub fn new(client_id: &str, client_secret: &str) -> Self {
+        let mut state = FakehubState::new();
+        let redirect_url = state.default_redirect_url.clone();
+
+        state.clients.insert(
+            client_id.to_owned(),
+            Client {
+                secret: client_secret.to_owned(),
+                redirect_url,
+            },
+        );
+
+        let state = Arc::new(Mutex::new(state));
+
+        Self::with_state(state, client_id, client_secret, "https://github.com")
+    }
+
+    pub(crate) fn with_state(
+        state: FakehubStateRef,
+        client_id: &str,
+        client_secret: &str,
+        base_url: &str,
+    ) -> Self {
+        Self {
+            state,
+            client_id: client_id.to_owned(),
+            client_secret: client_secret.to_owned(),
+            base_url: base_url.to_owned(),
+            authorize_url: Url::parse(&format!("{}/login/oauth/authorize", base_url))
+                .expect("Fakehub's urls are valid"),
+        }
+    }
+
+    /// Add a User.
+    pub async fn add_user(&self, user_id: UserId, user: User) {
+        self.state.lock().await.users.insert(user_id, user);
+    }
+
+    /// Get a login code for a given user id, as though they had logged
+    /// in.
+    pub async fn get_code(&self, user_id: UserId) -> Result<String> {
+        self.state.lock().await.get_code(user_id)
+    }
+
+    async fn exchange_code(
+        &self,
+        code: &str,
+        code_verifier: Option<&str>,
+    ) -> Result<GetAccessTokenResponse, crate::Error> {
+        let token = self
+            .state
+            .lock()
+            .await
+            .exchange_code(&self.client_id, &self.client_secret, code, code_verifier)
+            .map_err(oauth_error)?;
+
+        Ok(token.into())
+    }
+
+    async fn with_user<T>(
+        &self,
+        access_token: &str,
+        f: impl FnOnce(&FakehubState, UserId) -> Result<T>,
+    ) -> Result<T, crate::Error> {
+        let state = self.state.lock().await;
+        let user_id = state.token_user(access_token).map_err(http_error)?;
+
+        f(&state, user_id).map_err(http_error)
+    }
+}
+
+/// Report Fakehub's errors the way [`crate::GithubClient`] would see
+/// them, with the status the HTTP endpoints answer with.
+fn http_error(error: Error) -> crate::Error {
+    let message = error.to_string();
+
+    crate::Error::Http(Some(error.into_response().status().as_u16()), message)
+}
+
+/// Report the token endpoint's refusals the way [`crate::GithubClient`]
+/// would see them.
+fn oauth_error(refusal: CodeRefusal) -> crate::Error {
+    crate::Error::OAuth(
+        refusal.error().to_owned(),
+        Some(refusal.description().to_owned()),
+    )
+}
+
+#[async_trait]
+impl GithubApi for InMemoryGithub {
+    fn authorization_url(&self) -> String {
+        format!(
+            "{}/login/oauth/authorize?client_id={}",
+            self.base_url, self.client_id
+        )
+    }
+
+    fn authorization_url_with(&self, request: &AuthorizationRequest) -> String {
+        authorization_url_with(&self.authorize_url, &self.client_id, request)
+    }
+
+    async fn get_access_token(&self, code: &str) -> Result<GetAccessTokenResponse, crate::Error> {
+        self.exchange_code(code, None).await
+    }
+
+    async fn get_access_token_with_verifier(
+        &self,
+        code: &str,
+        code_verifier: &str,
+    ) -> Result<GetAccessTokenResponse, crate::Error> {
+        self.exchange_code(code, Some(code_verifier)).await
+    }
+
+    async fn refresh_access_token(
+        &self,
+        refresh_token: &str,
+    ) -> Result<GetAccessTokenResponse, crate::Error> {
+        let token = self
+            .state
+            .lock()
+            .await
+            .refresh_token(&self.client_id, &self.client_secret, refresh_token)
+            .map_err(oauth_error)?;
+
+        Ok(token.into())
+    }
+
+    async fn get_user_detail(
+        &self,
+        access_token: &str,
+    ) -> Result<UserDetailResponse, crate::Error> {
+        self.with_user(access_token, |state, user_id| state.user_detail(user_id))
+            .await
+    }
+
+    async fn list_user_orgs(&self, access_token: &str) -> Result<Vec<Organization>, crate::Error> {
+        self.with_user(access_token, |state, user_id| state.user_orgs(user_id))
+            .await
+    }
+
+    async fn list_user_teams(&self, access_token: &str) -> Result<Vec<Team>, crate::Error> {
+        self.with_user(access_token, |state, user_id| state.user_teams(user_id))
+            .await
+    }
+
+    async fn list_user_emails(&self, access_token: &str) -> Result<Vec<Email>, crate::Error> {
+        self.with_user(
+            access_token,
+            |state, user_id| Ok(state.user_emails(user_id)),
+        )
+        .await
+    }
+
+    async fn get_repo_permission(
+        &self,
+        access_token: &str,
+        owner: &str,
+        repo: &str,
+        username: &str,
+    ) -> Result<Permission, crate::Error> {
+        self.with_user(access_token, |state, user_id| {
+            state
+                .collaborator_permission(owner, repo, user_id, username)
+                .map(|(_, permission)| permission)
+        })
+        .await
+    }
+
+    async fn list_user_repos(
+        &self,
+        access_token: &str,
+        affiliations: &[Affiliation],
+    ) -> Result<Vec<Repository>, crate::Error> {
+        let affiliations = match affiliations.is_empty() {
+            true => &[
+                Affiliation::Owner,
+                Affiliation::Collaborator,
+                Affiliation::OrganizationMember,
+            ][..],
+            false => affiliations,
+        };
+
+        self.with_user(access_token, |state, user_id| {
+            state.user_repos(user_id, affiliations)
+        })
+        .await
+    }
+
+    async fn viewer_identity(&self, access_token: &str) -> Result<ViewerIdentity, crate::Error> {
+        self.with_user(access_token, |state, user_id| {
+            state.viewer_identity(user_id)
+        })
+        .await
+    }
+
+    async fn get_user_detail_public(
+        &self,
+        username: &str,
+    ) -> Result<UserDetailResponse, crate::Error> {
+        let state = self.state.lock().await;
+
+        match state.get_user_by_login(username) {
+            Some((user_id, _)) => state.user_detail(*user_id).map_err(http_error),
+            None => Err(http_error(Error::NoSuchUserLogin(username.to_owned()))),
+        }
+    }
+
+    async fn list_ssh_keys(&self, username: &str) -> Result<Vec<SshKey>, crate::Error> {
+        self.state
+            .lock()
+            .await
+            .user_ssh_keys(username)
+            .map_err(http_error)
+    }
+
+    async fn list_ssh_signing_keys(
+        &self,
+        username: &str,
+    ) -> Result<Vec<SshSigningKey>, crate::Error> {
+        self.state
+            .lock()
+            .await
+            .user_ssh_signing_keys(username)
+            .map_err(http_error)
+    }
+
+    async fn list_gpg_keys(&self, username: &str) -> Result<Vec<GpgKey>, crate::Error> {
+        self.state
+            .lock()
+            .await
+            .user_gpg_keys(username)
+            .map_err(http_error)
+    }
+}
+
+impl std::fmt::Debug for InMemoryGithub {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "InMemoryGithub {{ client_id: {}, client_secret: REDACTED, base_url: {} }}",
+            self.client_id, self.base_url,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fakehub::Fakehub,
+        testing::{add_test_user, user_named, CLIENT_ID, CLIENT_SECRET, USER, USER_ID},
+        GithubApi,
+    };
+
+    use super::InMemoryGithub;
+
+    #[tokio::test]
+    async fn in_memory_github() {
+        let in_memory = InMemoryGithub::new(CLIENT_ID, CLIENT_SECRET);
+        in_memory.add_user(USER_ID, user_named(USER)).await;
+        let code = in_memory.get_code(USER_ID).await.unwrap();
+        let github: Box<dyn GithubApi> = Box::new(in_memory);
+
+        let token = github.get_access_token(&code).await.unwrap();
+        let user_detail = github.get_user_detail(&token.access_token).await.unwrap();
+        assert_eq!(USER, user_detail.login);
+
+        // codes are good for one exchange, and unknown tokens are refused
+        // with the same status the HTTP endpoints answer with
+        assert!(matches!(
+            github.get_access_token(&code).await,
+            Err(crate::Error::OAuth(error, _)) if error == "bad_verification_code"
+        ));
+        assert!(matches!(
+            github.get_user_detail("not a token").await,
+            Err(crate::Error::Http(Some(401), _))
+        ));
+
+        // an in-memory client of a running Fakehub sees the same users
+        // and tokens as the HTTP client does
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let http: Box<dyn GithubApi> =
+            Box::new(fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap());
+        let in_memory: Box<dyn GithubApi> =
+            Box::new(fakehub.in_memory_client(CLIENT_ID, CLIENT_SECRET).await);
+        add_test_user(&fakehub).await;
+
+        let code = fakehub.get_code(USER_ID).await.unwrap();
+        let token = in_memory.get_access_token(&code).await.unwrap();
+        let user_detail = http.get_user_detail(&token.access_token).await.unwrap();
+        assert_eq!(USER, user_detail.login);
+        assert_eq!(http.authorization_url(), in_memory.authorization_url());
+
+        fakehub.shutdown().await;
+    }
+}
@@ -1,13 +1,33 @@
+mod admin;
 mod api_gh;
+mod builder;
+mod clock;
+mod config;
 mod error;
+mod faults;
 mod gh;
+mod hookshot;
+mod in_memory;
 mod login_page;
+mod oidc;
 mod service;
 mod state;
 mod temp_server;
+mod transport;
 
 pub use self::{
+    builder::FakehubBuilder,
+    clock::{Clock, ManualClock, SystemClock},
+    config::{
+        ClientFixture, CollaboratorFixture, EmailFixture, FakehubConfig, Fixtures, OrgFixture,
+        RepoFixture, TeamFixture, UserFixture, WebhookFixture,
+    },
     error::{Error, Result},
+    faults::InjectedError,
+    in_memory::InMemoryGithub,
     service::Fakehub,
-    state::User,
+    state::{
+        App, CapturedRequest, Counters, Flavor, Installation, Org, Repo, Team, TokenFormat, User,
+    },
+    transport::FakehubTransport,
 };
@@ -0,0 +1,126 @@
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde_json::{json, Value};
+
+use super::error::{Error, Result};
+
+/// How long minted tokens are good for.
+const TOKEN_LIFETIME: Duration = Duration::minutes(5);
+
+/// A key Fakehub signs OIDC tokens with. These are throwaway keys for
+/// testing, which is why it's fine for them to be in the source.
+struct SigningKey {
+    kid: &'static str,
+    private_key_pem: &'static str,
+    /// The base64url encoded modulus of the key, for its JWK.
+    modulus: &'static str,
+}
+
+const SIGNING_KEYS: [SigningKey; 2] = [
+    SigningKey {
+        kid: "fakehub-oidc-1",
+        private_key_pem: include_str!("keys/oidc_1.pem"),
+        modulus: "jysDbSjEk9QFgWjNrVo-x9OaE2MrVy0y0ITCdbRi33ok24WdEoYaQrq-O29Dsz5P60P-EJbhY1D2Hp_NfpLJUDV9Nk7rQ0izHAKsDmlaolFc4lO2spbWF7oatpno03SENBdhkGW8Le7xH0nvg7L9ebTGTTFOX0-JDxU51tTOIJlhdt41-j_JtPFtwCNcF9y1X8_QMuSpFhoBoapqMIr8T7PkztoqzdyZ6hsu-XCQ_FatgvQjF8wwSeVz8blDcoGO-1GHLPI0wO52tbE91hhCK8CoVjSsNwisuR1XWTdI-cTZEWz-RQoFRKAUymccjN9k9uXqaCKeSjmu7MkUVcpQVQ",
+    },
+    SigningKey {
+        kid: "fakehub-oidc-2",
+        private_key_pem: include_str!("keys/oidc_2.pem"),
+        modulus: "2tm7XbTvBeczBqiv1mePUslSrjjJBrP56VOiLSRXRtofbmDzGTsiqSABbSiDPOq8kOlP-db6b8I3uow9kuTwZq0ei2D0UmzuiHUniM7sWHS7snSNTRf0TNVNK1g0znHu71BDokUJZGrnMpmqRQwxr1uti72cervzfXUpHJqPQZj-Ra-Btx4FzyjqfCJQmQu2ttZwesTvojltL2IgGBnA03tYAyCNlmo8N-Qs24638nZVlAKUWPQFu0KJfO_HOSoFWQsexEJ81XloG_zgeduu-qCPj-UvT_mjDBX2czs2e0NLIabcd4kipGwEJH5dc1S6w_qXDh24jiCTnCrM8x8AcQ",
+    },
+];
+
+/// A stand-in for token.actions.githubusercontent.com, which signs
+/// tokens for workflow runs.
+#[derive(Debug)]
+pub struct OidcIssuer {
+    /// The index of the key new tokens are signed with.
+    active_key: usize,
+    /// How many keys have been rotated in, and so are published.
+    published_keys: usize,
+}
+
+impl OidcIssuer {
+    pub fn new() -> Self {
+        Self {
+            active_key: 0,
+            published_keys: 1,
+        }
+    }
+
+    /// Start signing with the next key. The old key stays published so
+    /// tokens it signed keep verifying.
+    pub fn rotate(&mut self) {
+        self.active_key = (self.active_key + 1) % SIGNING_KEYS.len();
+        self.published_keys = self.published_keys.max(self.active_key + 1);
+    }
+
+    /// The published keys, as a JWKS document.
+    pub fn jwks(&self) -> Value {
+        let keys = SIGNING_KEYS[..self.published_keys]
+            .iter()
+            .map(|key| {
+                json!({
+                    "kty": "RSA",
+                    "alg": "RS256",
+                    "use": "sig",
+                    "kid": key.kid,
+                    "n": key.modulus,
+                    "e": "AQAB",
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({ "keys": keys })
+    }
+
+    /// Sign a token with the active key, filling in the registered
+    /// claims the caller left out as of `now`.
+    pub fn mint(
+        &self,
+        issuer: &str,
+        audience: &str,
+        claims: Value,
+        now: DateTime<Utc>,
+    ) -> Result<String> {
+        let mut claims = match claims {
+            Value::Object(claims) => claims,
+            _ => return Err(Error::Unprocessable("claims must be an object".to_owned())),
+        };
+        let subject = format!(
+            "repo:{}:ref:{}",
+            claims
+                .get("repository")
+                .and_then(Value::as_str)
+                .unwrap_or_default(),
+            claims
+                .get("ref")
+                .and_then(Value::as_str)
+                .unwrap_or_default(),
+        );
+
+        claims.entry("iss").or_insert_with(|| json!(issuer));
+        claims.entry("aud").or_insert_with(|| json!(audience));
+        claims.entry("sub").or_insert_with(|| json!(subject));
+        claims
+            .entry("iat")
+            .or_insert_with(|| json!(now.timestamp()));
+        claims
+            .entry("nbf")
+            .or_insert_with(|| json!(now.timestamp()));
+        claims
+            .entry("exp")
+            .or_insert_with(|| json!((now + TOKEN_LIFETIME).timestamp()));
+
+        let key = &SIGNING_KEYS[self.active_key];
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(key.kid.to_owned());
+
+        jsonwebtoken::encode(
+            &header,
+            &claims,
+            &EncodingKey::from_rsa_pem(key.private_key_pem.as_bytes())
+                .map_err(|e| Error::InvalidJwt(e.to_string()))?,
+        )
+        .map_err(|e| Error::InvalidJwt(e.to_string()))
+    }
+}
@@ -1,63 +1,368 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener},
+    sync::Arc,
+    time::Duration,
+};
 
+use axum::Router;
+use serde::Serialize;
+use serde_json::Value;
 use tokio::sync::Mutex;
 use url::Url;
 
-use crate::fakehub::state::UserId;
-use crate::GithubClient;
+use crate::fakehub::state::{AppId, InstallationId, OrgId, RepoId, TeamId, UserId};
+use crate::webhooks::{
+    events::{
+        self, Account, GithubAppAuthorizationAction, GithubAppAuthorizationEvent,
+        InstallationAction, InstallationEvent, Invitation, MembershipAction, MembershipEvent,
+        OrgMembership, OrganizationAction, OrganizationEvent, TeamAction, TeamEvent,
+    },
+    DeliveryId, Event,
+};
+use crate::{
+    keys::{SshPublicKey, SshSigningKey},
+    GithubClient, Permission, Provider,
+};
 
 use super::{
-    api_gh::ApiDotGithubDotCom,
-    error::Result,
-    gh::GithubDotCom,
-    state::{Client, FakehubState, FakehubStateRef, User},
+    admin::ADMIN_PREFIX,
+    api_gh::{self, ApiDotGithubDotCom},
+    builder::FakehubBuilder,
+    config::{FakehubConfig, Fixtures},
+    error::{Error, Result},
+    faults::InjectedError,
+    gh::{self, GithubDotCom},
+    hookshot::Hookshot,
+    in_memory::InMemoryGithub,
+    state::{
+        App, CapturedRequest, Client, Counters, FakehubState, FakehubStateRef, Flavor,
+        Installation, Org, Repo, Team, User, Webhook,
+    },
+    temp_server::bind,
+    transport::FakehubTransport,
 };
 
+/// Where an in-process Fakehub claims github.com to be. Nothing
+/// resolves `.invalid`, so requests that miss the transport go nowhere.
+const IN_PROCESS_WEB_URL: &str = "http://github.invalid";
+/// Where an in-process Fakehub claims api.github.com to be.
+const IN_PROCESS_API_URL: &str = "http://api.github.invalid";
+
 /// A fake implementation of github.com and api.github.com, complete
 /// enough for use in an integration test.
 #[derive(Debug)]
 pub struct Fakehub {
     state: FakehubStateRef,
+    flavor: Flavor,
+    /// The servers on sockets, or `None` for an in-process Fakehub.
+    servers: Option<Servers>,
+    hookshot: Hookshot,
+}
+
+#[derive(Debug)]
+struct Servers {
     root_server: GithubDotCom,
     api_server: ApiDotGithubDotCom,
 }
 
 impl Fakehub {
-    /// Create a new Fakehub.
+    /// Create a new Fakehub, on free ports of the loopback interface.
+    /// See [`Fakehub::builder`] for anything else.
     pub fn new() -> Result<Self> {
-        let state = Arc::new(Mutex::new(FakehubState::new()));
+        Self::new_with_flavor(Flavor::Github)
+    }
 
-        let root_server = GithubDotCom::new(3050, state.clone())?;
-        let api_server = ApiDotGithubDotCom::new(3051, state.clone())?;
+    /// Create a new Fakehub that binds no sockets at all. Clients from
+    /// [`Fakehub::add_client`] reach it through a [`FakehubTransport`],
+    /// which calls its routers in memory.
+    pub fn new_in_process() -> Result<Self> {
+        Self::from_state(FakehubState::new_with_flavor(Flavor::Github), None)
+    }
+
+    /// Configure a Fakehub's hosts, ports, flavor, clock, faults and
+    /// starting clients and users, before starting it.
+    pub fn builder() -> FakehubBuilder {
+        FakehubBuilder::new()
+    }
+
+    /// Create a new Fakehub that imitates a Github Enterprise Server or a
+    /// Gitea instance rather than github.com.
+    pub fn new_with_flavor(flavor: Flavor) -> Result<Self> {
+        // port 0 has the OS pick free ports, so Fakehubs in parallel
+        // tests never race each other for them
+        let loopback = SocketAddr::from(([127, 0, 0, 1], 0));
+
+        Self::from_listeners(bind(loopback)?, bind(loopback)?, flavor)
+    }
+
+    /// Create a new Fakehub serving github.com and api.github.com on
+    /// listeners the caller has already bound.
+    pub fn from_listeners(
+        web_listener: TcpListener,
+        api_listener: TcpListener,
+        flavor: Flavor,
+    ) -> Result<Self> {
+        Self::from_state(
+            FakehubState::new_with_flavor(flavor),
+            Some((web_listener, api_listener)),
+        )
+    }
+
+    /// Start a Fakehub on its state, serving from the listeners if there
+    /// are any, or in process otherwise.
+    pub(crate) fn from_state(
+        state: FakehubState,
+        listeners: Option<(TcpListener, TcpListener)>,
+    ) -> Result<Self> {
+        let flavor = state.flavor;
+        let state = Arc::new(Mutex::new(state));
+
+        let servers = match listeners {
+            Some((web_listener, api_listener)) => Some(Servers {
+                root_server: GithubDotCom::new(web_listener, state.clone(), flavor)?,
+                api_server: ApiDotGithubDotCom::new(api_listener, state.clone(), flavor)?,
+            }),
+            None => None,
+        };
 
         Ok(Self {
-            root_server,
-            api_server,
+            servers,
             state,
+            flavor,
+            hookshot: Hookshot::new()?,
         })
     }
 
+    /// Create a new Fakehub on the host and ports a config gives, seeded
+    /// with its fixtures.
+    pub async fn from_config(config: &FakehubConfig) -> Result<Self> {
+        Self::builder()
+            .host(config.host)
+            .web_port(config.web_port)
+            .api_port(config.api_port)
+            .flavor(config.flavor)
+            .fixtures(config.fixtures.clone())
+            .start()
+            .await
+    }
+
+    /// Add fixtures to this Fakehub. Memberships are added before
+    /// webhooks, so loading delivers no events.
+    pub async fn load(&self, fixtures: &Fixtures) -> Result<()> {
+        for client in &fixtures.clients {
+            match &client.redirect_url {
+                Some(redirect_url) => {
+                    self.add_client_with_redirect_url(&client.id, &client.secret, redirect_url)
+                        .await?
+                }
+                None => self.add_client(&client.id, &client.secret).await?,
+            };
+        }
+
+        for user in &fixtures.users {
+            self.add_user(
+                user.id,
+                User {
+                    login: user.login.clone(),
+                    avatar_url: user.avatar_url(),
+                    html_url: user.html_url(),
+                },
+            )
+            .await;
+
+            for email in &user.emails {
+                self.add_email(user.id, &email.email, email.verified)
+                    .await?;
+            }
+            for key in &user.ssh_keys {
+                self.add_ssh_key(user.id, key).await?;
+            }
+            for key in &user.gpg_keys {
+                self.add_gpg_key(user.id, key).await?;
+            }
+        }
+
+        for org in &fixtures.orgs {
+            self.add_org(
+                org.id,
+                Org {
+                    login: org.login.clone(),
+                },
+            )
+            .await;
+
+            for member in &org.members {
+                self.add_org_member(org.id, *member).await?;
+            }
+        }
+
+        for team in &fixtures.teams {
+            self.add_team(
+                team.id,
+                Team {
+                    org_id: team.org_id,
+                    name: team.name.clone(),
+                    slug: team.slug(),
+                },
+            )
+            .await?;
+
+            for member in &team.members {
+                self.add_team_member(team.id, *member).await?;
+            }
+        }
+
+        for repo in &fixtures.repos {
+            self.add_repo(
+                repo.id,
+                Repo {
+                    owner_id: repo.owner_id,
+                    name: repo.name.clone(),
+                    private: repo.private,
+                },
+            )
+            .await?;
+
+            for collaborator in &repo.collaborators {
+                self.add_collaborator(repo.id, collaborator.user_id, collaborator.permission)
+                    .await?;
+            }
+        }
+
+        for webhook in &fixtures.webhooks {
+            self.add_webhook(&webhook.url, &webhook.secret).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Forget everything: clients, users, codes, tokens and the rest,
+    /// as if this Fakehub had just started.
+    pub async fn reset(&self) {
+        let mut state = self.state.lock().await;
+
+        state.reset();
+    }
+
+    /// Remove a user, with their tokens, keys, memberships and
+    /// repositories, as if they had deleted their account.
+    pub async fn remove_user(&self, user_id: UserId) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        state.remove_user(user_id)
+    }
+
+    /// The most recent requests Fakehub's servers answered, oldest
+    /// first, leaving out the admin API's own.
+    pub async fn requests(&self) -> Vec<CapturedRequest> {
+        let state = self.state.lock().await;
+
+        state.requests.iter().cloned().collect()
+    }
+
     /// Add a Client to this Fakehub instance and return a GithubClient configured to use it.
     pub async fn add_client(
         &self,
         client_id: &str,
         client_secret: &str,
     ) -> Result<GithubClient, crate::Error> {
+        let redirect_url = self.state.lock().await.default_redirect_url.clone();
+
+        self.add_client_with_redirect_url(client_id, client_secret, redirect_url.as_str())
+            .await
+    }
+
+    /// Add a Client, like [`Fakehub::add_client`], whose redirects must
+    /// fall under the given callback URL rather than the default one.
+    pub async fn add_client_with_redirect_url(
+        &self,
+        client_id: &str,
+        client_secret: &str,
+        redirect_url: &str,
+    ) -> Result<GithubClient, crate::Error> {
+        let redirect_url = Url::parse(redirect_url)
+            .map_err(|error| crate::Error::ClientCreation(error.to_string()))?;
         let mut state = self.state.lock().await;
 
         state.clients.insert(
             client_id.to_owned(),
             Client {
                 secret: client_secret.to_owned(),
-                redirect_url: Url::parse("http://127.0.0.1").unwrap(),
+                redirect_url,
             },
         );
 
-        GithubClient::new_with_urls(
+        let github_client =
+            GithubClient::new_with_provider(client_id, client_secret, self.provider())?;
+
+        Ok(match self.servers {
+            Some(_) => github_client,
+            None => github_client.with_transport(self.transport()),
+        })
+    }
+
+    /// What github.com serves, to call as a `tower::Service` without
+    /// going through a socket. It shares this Fakehub's state.
+    pub fn github_dot_com_router(&self) -> Router {
+        gh::app(self.state.clone(), self.flavor)
+    }
+
+    /// What api.github.com serves, to call as a `tower::Service` without
+    /// going through a socket. It shares this Fakehub's state.
+    pub fn api_dot_github_dot_com_router(&self) -> Router {
+        api_gh::app(self.state.clone(), self.flavor)
+    }
+
+    /// A transport for [`GithubClient::with_transport`] that calls this
+    /// Fakehub's routers in memory, whether or not it's also serving
+    /// them from sockets.
+    pub fn transport(&self) -> FakehubTransport {
+        FakehubTransport::new(
+            &self.github_dot_com_url(),
+            self.github_dot_com_router(),
+            &self.api_dot_github_dot_com_url(),
+            self.api_dot_github_dot_com_router(),
+        )
+    }
+
+    /// The provider this Fakehub imitates, at its urls. Flavors other
+    /// than Github serve everything from the web host, as the real ones
+    /// do.
+    pub fn provider(&self) -> Provider {
+        match self.flavor {
+            Flavor::Github => Provider::custom(
+                &self.github_dot_com_url(),
+                &self.api_dot_github_dot_com_url(),
+            ),
+            Flavor::GithubEnterprise => Provider::github_enterprise(&self.github_dot_com_url()),
+            Flavor::Gitea => Provider::gitea(&self.github_dot_com_url()),
+        }
+    }
+
+    pub fn flavor(&self) -> Flavor {
+        self.flavor
+    }
+
+    /// Add a client, like [`Fakehub::add_client`], but talk to it
+    /// through a [`GithubApi`](crate::GithubApi) that reads and writes
+    /// this Fakehub's state directly instead of over HTTP.
+    pub async fn in_memory_client(&self, client_id: &str, client_secret: &str) -> InMemoryGithub {
+        let mut state = self.state.lock().await;
+        let redirect_url = state.default_redirect_url.clone();
+
+        state.clients.insert(
+            client_id.to_owned(),
+            Client {
+                secret: client_secret.to_owned(),
+                redirect_url,
+            },
+        );
+
+        InMemoryGithub::with_state(
+            self.state.clone(),
             client_id,
             client_secret,
-            self.github_dot_com_url().leak(),
-            self.api_dot_github_dot_com_url().leak(),
+            &self.github_dot_com_url(),
         )
     }
 
@@ -68,6 +373,464 @@ impl Fakehub {
         state.users.insert(user_id, user);
     }
 
+    /// Simulate a user changing their login on Github. Their id stays
+    /// the same, and their old login is free for someone else to take.
+    pub async fn rename_user(&self, user_id: UserId, login: &str) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        if let Some((holder_id, _)) = state.get_user_by_login(login) {
+            if *holder_id != user_id {
+                return Err(Error::Unprocessable(format!("Login {} is taken", login)));
+            }
+        }
+
+        let user = state
+            .users
+            .get_mut(&user_id)
+            .ok_or(Error::NoSuchUserId(user_id))?;
+        let previous_login = std::mem::replace(&mut user.login, login.to_owned());
+
+        if let Some(base) = user.html_url.strip_suffix(&previous_login) {
+            user.html_url = format!("{}{}", base, login);
+        }
+
+        Ok(())
+    }
+
+    /// Give a user an email address. The first one a user is given is
+    /// their primary address.
+    pub async fn add_email(&self, user_id: UserId, email: &str, verified: bool) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        state.add_email(user_id, email, verified)
+    }
+
+    /// Give a user an SSH key to authenticate with, in the OpenSSH
+    /// format. Like Github, Fakehub drops the comment. Returns the id of
+    /// the key.
+    pub async fn add_ssh_key(&self, user_id: UserId, key: &str) -> Result<i64> {
+        let mut state = self.state.lock().await;
+
+        state.add_ssh_key(user_id, key)
+    }
+
+    /// Give a user an SSH key to sign commits with. Returns the id of
+    /// the key.
+    pub async fn add_ssh_signing_key(
+        &self,
+        user_id: UserId,
+        title: &str,
+        key: &str,
+    ) -> Result<i64> {
+        let key = key.parse::<SshPublicKey>()?.with_comment("");
+        let mut state = self.state.lock().await;
+
+        if !state.users.contains_key(&user_id) {
+            return Err(Error::NoSuchUserId(user_id));
+        }
+
+        let id = state.next_key_id();
+        let created_at = state.now();
+
+        state
+            .ssh_signing_keys
+            .entry(user_id)
+            .or_default()
+            .push(SshSigningKey {
+                id,
+                key,
+                title: title.to_owned(),
+                created_at,
+            });
+
+        Ok(id)
+    }
+
+    /// Give a user a GPG key, ASCII armored as `gpg --armor --export`
+    /// writes it. Fakehub reads the keys and user ids from it, but not
+    /// the signatures, so the capabilities are whatever each key's
+    /// algorithm allows and the key never expires. Returns the id of
+    /// the primary key.
+    pub async fn add_gpg_key(&self, user_id: UserId, armored: &str) -> Result<i64> {
+        let mut state = self.state.lock().await;
+
+        state.add_gpg_key(user_id, armored)
+    }
+
+    /// Add a repository, owned by a user or an org.
+    pub async fn add_repo(&self, repo_id: RepoId, repo: Repo) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        state.installation_account(repo.owner_id)?;
+        state.repos.insert(repo_id, repo);
+
+        Ok(())
+    }
+
+    /// Make a user a collaborator on a repository, or change what they
+    /// may do in it.
+    pub async fn add_collaborator(
+        &self,
+        repo_id: RepoId,
+        user_id: UserId,
+        permission: Permission,
+    ) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        if !state.repos.contains_key(&repo_id) {
+            return Err(Error::NoSuchRepo(repo_id.to_string()));
+        }
+        if !state.users.contains_key(&user_id) {
+            return Err(Error::NoSuchUserId(user_id));
+        }
+
+        state
+            .collaborators
+            .entry(repo_id)
+            .or_default()
+            .insert(user_id, permission);
+
+        Ok(())
+    }
+
+    /// Take a user's collaborator access to a repository away.
+    pub async fn remove_collaborator(&self, repo_id: RepoId, user_id: UserId) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        match state.collaborators.get_mut(&repo_id) {
+            Some(collaborators) => {
+                collaborators.remove(&user_id);
+                Ok(())
+            }
+            None => Err(Error::NoSuchRepo(repo_id.to_string())),
+        }
+    }
+
+    /// Add an Org to this Fakehub instance.
+    pub async fn add_org(&self, org_id: OrgId, org: Org) {
+        let mut state = self.state.lock().await;
+
+        state.orgs.insert(org_id, org);
+    }
+
+    /// Invite a user to an org, delivering an `organization` event.
+    pub async fn invite_org_member(&self, org_id: OrgId, user_id: UserId) -> Result<()> {
+        let payload = {
+            let mut state = self.state.lock().await;
+            let organization = state.organization(org_id)?;
+            let user = state.account(user_id)?;
+
+            state
+                .org_invitations
+                .entry(org_id)
+                .or_default()
+                .insert(user_id);
+
+            OrganizationEvent {
+                action: OrganizationAction::MemberInvited,
+                membership: None,
+                invitation: Some(Invitation {
+                    id: user_id,
+                    login: Some(user.login.clone()),
+                    email: None,
+                    role: "direct_member".to_owned(),
+                }),
+                user: Some(user),
+                organization,
+                sender: fakehub_bot(),
+            }
+        };
+
+        self.deliver_webhook(&Event::Organization, &payload).await?;
+
+        Ok(())
+    }
+
+    /// Add a user to an org, delivering an `organization` event.
+    pub async fn add_org_member(&self, org_id: OrgId, user_id: UserId) -> Result<()> {
+        let payload = {
+            let mut state = self.state.lock().await;
+            let organization = state.organization(org_id)?;
+            let user = state.account(user_id)?;
+
+            if let Some(invitations) = state.org_invitations.get_mut(&org_id) {
+                invitations.remove(&user_id);
+            }
+            state.org_members.entry(org_id).or_default().insert(user_id);
+
+            OrganizationEvent {
+                action: OrganizationAction::MemberAdded,
+                membership: Some(OrgMembership {
+                    user,
+                    role: "member".to_owned(),
+                    state: "active".to_owned(),
+                }),
+                invitation: None,
+                user: None,
+                organization,
+                sender: fakehub_bot(),
+            }
+        };
+
+        self.deliver_webhook(&Event::Organization, &payload).await?;
+
+        Ok(())
+    }
+
+    /// Remove a user from an org and all of its teams, delivering an
+    /// `organization` event.
+    pub async fn remove_org_member(&self, org_id: OrgId, user_id: UserId) -> Result<()> {
+        let payload = {
+            let mut state = self.state.lock().await;
+            let organization = state.organization(org_id)?;
+            let user = state.account(user_id)?;
+            let org_team_ids = state
+                .teams
+                .iter()
+                .filter(|(_, team)| team.org_id == org_id)
+                .map(|(team_id, _)| *team_id)
+                .collect::<Vec<_>>();
+
+            if let Some(members) = state.org_members.get_mut(&org_id) {
+                members.remove(&user_id);
+            }
+            for team_id in org_team_ids {
+                if let Some(members) = state.team_members.get_mut(&team_id) {
+                    members.remove(&user_id);
+                }
+            }
+
+            OrganizationEvent {
+                action: OrganizationAction::MemberRemoved,
+                membership: Some(OrgMembership {
+                    user,
+                    role: "member".to_owned(),
+                    state: "active".to_owned(),
+                }),
+                invitation: None,
+                user: None,
+                organization,
+                sender: fakehub_bot(),
+            }
+        };
+
+        self.deliver_webhook(&Event::Organization, &payload).await?;
+
+        Ok(())
+    }
+
+    /// Add a Team to an org in this Fakehub instance, delivering a
+    /// `team` event.
+    pub async fn add_team(&self, team_id: TeamId, team: Team) -> Result<()> {
+        let payload = {
+            let mut state = self.state.lock().await;
+            let organization = state.organization(team.org_id)?;
+
+            state.teams.insert(team_id, team);
+
+            TeamEvent {
+                action: TeamAction::Created,
+                team: state.team(team_id)?,
+                organization,
+                sender: fakehub_bot(),
+            }
+        };
+
+        self.deliver_webhook(&Event::Team, &payload).await?;
+
+        Ok(())
+    }
+
+    /// Remove a Team, delivering a `team` event.
+    pub async fn remove_team(&self, team_id: TeamId) -> Result<()> {
+        let payload = {
+            let mut state = self.state.lock().await;
+            let team = state.team(team_id)?;
+            let organization = state.organization(state.teams[&team_id].org_id)?;
+
+            state.teams.remove(&team_id);
+            state.team_members.remove(&team_id);
+
+            TeamEvent {
+                action: TeamAction::Deleted,
+                team,
+                organization,
+                sender: fakehub_bot(),
+            }
+        };
+
+        self.deliver_webhook(&Event::Team, &payload).await?;
+
+        Ok(())
+    }
+
+    /// Add a user to a team, delivering a `membership` event.
+    pub async fn add_team_member(&self, team_id: TeamId, user_id: UserId) -> Result<()> {
+        self.change_team_membership(team_id, user_id, MembershipAction::Added)
+            .await
+    }
+
+    /// Remove a user from a team, delivering a `membership` event.
+    pub async fn remove_team_member(&self, team_id: TeamId, user_id: UserId) -> Result<()> {
+        self.change_team_membership(team_id, user_id, MembershipAction::Removed)
+            .await
+    }
+
+    async fn change_team_membership(
+        &self,
+        team_id: TeamId,
+        user_id: UserId,
+        action: MembershipAction,
+    ) -> Result<()> {
+        let payload = {
+            let mut state = self.state.lock().await;
+            let team = state.team(team_id)?;
+            let organization = state.organization(state.teams[&team_id].org_id)?;
+            let member = state.account(user_id)?;
+            let members = state.team_members.entry(team_id).or_default();
+
+            match action {
+                MembershipAction::Added => members.insert(user_id),
+                MembershipAction::Removed => members.remove(&user_id),
+            };
+
+            MembershipEvent {
+                action,
+                scope: "team".to_owned(),
+                member,
+                team,
+                organization,
+                sender: fakehub_bot(),
+            }
+        };
+
+        self.deliver_webhook(&Event::Membership, &payload).await?;
+
+        Ok(())
+    }
+
+    /// Register a Github App with the public half of its key. Use
+    /// [`crate::AppClient::new_with_url`] with the private half and
+    /// [`Fakehub::api_dot_github_dot_com_url`] to act as the app.
+    pub async fn add_app(&self, app_id: AppId, app: App) {
+        let mut state = self.state.lock().await;
+
+        state.apps.insert(app_id, app);
+    }
+
+    /// Install an app on a user or org account, delivering an
+    /// `installation` event.
+    pub async fn add_installation(
+        &self,
+        installation_id: InstallationId,
+        installation: Installation,
+    ) -> Result<()> {
+        let payload = {
+            let mut state = self.state.lock().await;
+
+            if !state.apps.contains_key(&installation.app_id) {
+                return Err(Error::NoSuchApp(installation.app_id));
+            }
+
+            let account = state.installation_account(installation.account_id)?;
+            let sender = state
+                .account(installation.account_id)
+                .unwrap_or_else(|_| fakehub_bot());
+            let app_id = installation.app_id;
+
+            state.installations.insert(installation_id, installation);
+
+            InstallationEvent {
+                action: InstallationAction::Created,
+                installation: events::Installation {
+                    id: installation_id,
+                    app_id,
+                    account: Account {
+                        id: account.id,
+                        login: account.login,
+                        avatar_url: None,
+                        html_url: None,
+                    },
+                },
+                sender,
+            }
+        };
+
+        self.deliver_webhook(&Event::Installation, &payload).await?;
+
+        Ok(())
+    }
+
+    /// Mint a Github Actions OIDC token for the given audience. Any of
+    /// the registered claims (`iss`, `sub`, `iat`, `exp` and so on) left
+    /// out of `claims` are filled in.
+    pub async fn mint_oidc_token(&self, audience: &str, claims: Value) -> Result<String> {
+        let state = self.state.lock().await;
+
+        state
+            .oidc_issuer
+            .mint(&self.oidc_issuer_url(), audience, claims, state.now())
+    }
+
+    /// Start signing OIDC tokens with a new key, as Github does from
+    /// time to time.
+    pub async fn rotate_oidc_key(&self) {
+        let mut state = self.state.lock().await;
+
+        state.oidc_issuer.rotate();
+    }
+
+    /// Simulate a user revoking their authorization of your application.
+    /// Every token issued to them stops working, and a
+    /// `github_app_authorization` event is delivered.
+    pub async fn revoke_authorization(&self, user_id: UserId) -> Result<()> {
+        let payload = {
+            let mut state = self.state.lock().await;
+            let sender = state.account(user_id)?;
+
+            state.revoke_tokens(user_id);
+
+            GithubAppAuthorizationEvent {
+                action: GithubAppAuthorizationAction::Revoked,
+                sender,
+            }
+        };
+
+        self.deliver_webhook(&Event::GithubAppAuthorization, &payload)
+            .await?;
+
+        Ok(())
+    }
+
+    /// What this Fakehub has done so far.
+    pub async fn counters(&self) -> Counters {
+        self.state.lock().await.counters.clone()
+    }
+
+    /// Delay every request by `latency`, or stop delaying them with
+    /// `None`. The admin API is never delayed.
+    pub async fn set_latency(&self, latency: Option<Duration>) {
+        self.state.lock().await.latency = latency;
+    }
+
+    /// Answer requests with an error from now on, until it has been
+    /// returned as many times as it's good for.
+    pub async fn inject_error(&self, injected: InjectedError) {
+        self.state.lock().await.injected_errors.push(injected);
+    }
+
+    /// Stop answering requests with injected errors.
+    pub async fn clear_injected_errors(&self) {
+        self.state.lock().await.injected_errors.clear();
+    }
+
+    /// Issue user tokens that expire after the given lifetime, along with
+    /// refresh tokens to renew them, as Github does for apps that opt in
+    /// to expiring user tokens.
+    pub async fn expire_user_tokens(&self, lifetime: chrono::Duration) {
+        self.state.lock().await.user_token_lifetime = Some(lifetime);
+    }
+
     /// Simulate a user going to your authorization URL to log in, which
     /// returns a simple code. The code must not be confused with an API
     /// token, which the backend service (not the user) exchanges the code
@@ -79,33 +842,131 @@ impl Fakehub {
         state.get_code(user_id)
     }
 
+    /// Add a webhook, to which Fakehub will deliver events signed with
+    /// the given secret.
+    pub async fn add_webhook(&self, url: &str, secret: &str) -> Result<()> {
+        let mut state = self.state.lock().await;
+
+        state.webhooks.push(Webhook {
+            url: Url::parse(url)?,
+            secret: secret.to_owned(),
+        });
+
+        Ok(())
+    }
+
+    /// Deliver an event to every configured webhook, returning the id of
+    /// each delivery. Fails if any webhook does not accept the delivery.
+    pub async fn deliver_webhook<T: Serialize>(
+        &self,
+        event: &Event,
+        payload: &T,
+    ) -> Result<Vec<DeliveryId>> {
+        let payload = serde_json::to_vec(payload)?;
+        let deliveries = {
+            let mut state = self.state.lock().await;
+
+            state
+                .webhooks
+                .clone()
+                .into_iter()
+                .map(|webhook| (webhook, state.next_delivery_id()))
+                .collect::<Vec<_>>()
+        };
+
+        for (webhook, delivery) in deliveries.iter() {
+            self.hookshot
+                .deliver(webhook, delivery, event, &payload)
+                .await?;
+        }
+
+        Ok(deliveries
+            .into_iter()
+            .map(|(_, delivery)| delivery)
+            .collect())
+    }
+
     /// Shutdown this fakehub.
     pub async fn shutdown(self) {
-        self.root_server.shutdown().await;
-        self.api_server.shutdown().await;
+        if let Some(servers) = self.servers {
+            servers.root_server.shutdown().await;
+            servers.api_server.shutdown().await;
+        }
     }
 
     pub fn github_dot_com_url(&self) -> String {
-        format!(
-            "http://{}:{}",
-            self.github_dot_com_socket().ip(),
-            self.github_dot_com_socket().port()
-        )
+        match &self.servers {
+            Some(servers) => url_for(&servers.root_server._temp_server.socket_addr),
+            None => IN_PROCESS_WEB_URL.to_owned(),
+        }
+    }
+
+    /// Where the admin API is, for driving this Fakehub from outside
+    /// the process. `GET {admin_url}/openapi.json` describes it.
+    pub fn admin_url(&self) -> String {
+        format!("{}{}", self.github_dot_com_url(), ADMIN_PREFIX)
+    }
+
+    /// Whether this Fakehub was made by [`Fakehub::new_in_process`],
+    /// and so has no sockets.
+    pub fn is_in_process(&self) -> bool {
+        self.servers.is_none()
     }
 
+    /// # Panics
+    ///
+    /// If this Fakehub is in process, and has no sockets. See
+    /// [`Fakehub::is_in_process`].
     pub fn github_dot_com_socket(&self) -> &SocketAddr {
-        &self.root_server._temp_server.socket_addr
+        &self.servers().root_server._temp_server.socket_addr
+    }
+
+    /// The issuer of Fakehub's OIDC tokens. Use it with
+    /// [`crate::oidc::OidcVerifier::new_with_issuer`].
+    pub fn oidc_issuer_url(&self) -> String {
+        format!("{}/_services/token", self.github_dot_com_url())
     }
 
     pub fn api_dot_github_dot_com_url(&self) -> String {
-        format!(
-            "http://{}:{}",
-            self.api_dot_github_dot_com_socket().ip(),
-            self.api_dot_github_dot_com_socket().port()
-        )
+        match &self.servers {
+            Some(servers) => url_for(&servers.api_server._temp_server.socket_addr),
+            None => IN_PROCESS_API_URL.to_owned(),
+        }
     }
 
+    /// # Panics
+    ///
+    /// If this Fakehub is in process, and has no sockets. See
+    /// [`Fakehub::is_in_process`].
     pub fn api_dot_github_dot_com_socket(&self) -> &SocketAddr {
-        &self.api_server._temp_server.socket_addr
+        &self.servers().api_server._temp_server.socket_addr
+    }
+
+    fn servers(&self) -> &Servers {
+        self.servers
+            .as_ref()
+            .expect("an in-process Fakehub has no sockets")
+    }
+}
+
+/// Where a server on the given address is reached. One bound to every
+/// interface is reached on the loopback interface as well as any.
+fn url_for(socket_addr: &SocketAddr) -> String {
+    let ip = match socket_addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+
+    format!("http://{}", SocketAddr::new(ip, socket_addr.port()))
+}
+
+/// The account Fakehub attributes administrative changes to.
+fn fakehub_bot() -> Account {
+    Account {
+        id: 0,
+        login: "fakehub[bot]".to_owned(),
+        avatar_url: None,
+        html_url: None,
     }
 }
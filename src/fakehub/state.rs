@@ -1,15 +1,108 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
 
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 use url::Url;
 
-use super::{Error, Result};
+use crate::{
+    graphql::ViewerIdentity,
+    keys::{GpgKey, GpgKeyEmail, OpenPgpCertificate, SshKey, SshPublicKey, SshSigningKey},
+    shapes::{
+        Affiliation, Email, GetAccessTokenResponse, InstallationAccount, InstallationTokenRequest,
+        Organization, Permission, Repository, RepositoryOwner, Team as UserTeam,
+    },
+    webhooks::{events, DeliveryId},
+    AppClaims, Pkce, UserDetailResponse,
+};
+
+use super::{
+    clock::{Clock, SystemClock},
+    config::{
+        ClientFixture, CollaboratorFixture, EmailFixture, Fixtures, OrgFixture, RepoFixture,
+        TeamFixture, UserFixture, WebhookFixture,
+    },
+    faults::InjectedError,
+    oidc::OidcIssuer,
+    Error, Result,
+};
 
 pub(crate) type FakehubStateRef = Arc<Mutex<FakehubState>>;
 pub(crate) type ClientId = String;
 pub(crate) type Code = String;
 pub(crate) type UserId = i64;
 pub(crate) type Token = String;
+pub(crate) type OrgId = i64;
+pub(crate) type TeamId = i64;
+pub(crate) type AppId = i64;
+pub(crate) type InstallationId = i64;
+pub(crate) type RepoId = i64;
+
+/// How long refresh tokens for expiring user tokens are good for.
+const REFRESH_TOKEN_LIFETIME: Duration = Duration::days(184);
+/// How long Gitea's user tokens are good for.
+const GITEA_TOKEN_LIFETIME: Duration = Duration::hours(1);
+/// How long installation access tokens are good for.
+const INSTALLATION_TOKEN_LIFETIME: Duration = Duration::hours(1);
+/// How far into the future Github accepts app JWTs expiring, plus a
+/// little leeway for clock drift.
+const MAX_APP_JWT_LIFETIME_SECONDS: i64 = 600 + 60;
+/// The callback URL clients are registered with unless told otherwise.
+/// Redirects to loopback addresses may pick any port, as on Github, so
+/// this allows any local app.
+pub(crate) const DEFAULT_REDIRECT_URL: &str = "http://127.0.0.1";
+/// How many requests the request log keeps before dropping the oldest.
+const MAX_CAPTURED_REQUESTS: usize = 1000;
+const HEX_DIGITS: &[u8] = b"0123456789abcdef";
+const ALPHANUMERICS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Which forge a Fakehub imitates. They share Github's OAuth routes,
+/// but differ in where the API lives, what it has and how the token
+/// endpoint behaves.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Flavor {
+    /// github.com, with the API on a host of its own.
+    #[default]
+    Github,
+    /// Github Enterprise Server, with the API under `/api/v3` and
+    /// GraphQL at `/api/graphql`.
+    GithubEnterprise,
+    /// Gitea or Forgejo, with the API under `/api/v1`, no GraphQL, and
+    /// tokens that expire within the hour.
+    Gitea,
+}
+
+/// How Fakehub spells the login codes and user tokens it hands out.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TokenFormat {
+    /// `token_{user id}`, so a test can tell whose code or token it has
+    /// at a glance. Every code and token of a user is the same.
+    #[default]
+    Predictable,
+    /// Random, and as long as Github's: 20 hex digits for codes, and a
+    /// `gho_` prefix on 36 letters and digits for tokens.
+    Random,
+}
+
+impl Flavor {
+    /// Where the API lives on the web host, for flavors that serve it
+    /// there.
+    pub(crate) fn api_prefix(&self) -> Option<&'static str> {
+        match self {
+            Self::Github => None,
+            Self::GithubEnterprise => Some("/api/v3"),
+            Self::Gitea => Some("/api/v1"),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Client {
@@ -24,24 +117,456 @@ pub struct User {
     pub html_url: String,
 }
 
+#[derive(Debug)]
+pub struct Org {
+    pub login: String,
+}
+
+/// A repository, owned by a user or an org.
+#[derive(Debug)]
+pub struct Repo {
+    /// The id of the user or org the repository belongs to.
+    pub owner_id: i64,
+    pub name: String,
+    pub private: bool,
+}
+
+#[derive(Debug)]
+pub struct Team {
+    pub org_id: OrgId,
+    pub name: String,
+    pub slug: String,
+}
+
+/// Running totals of what Fakehub has done, for asserting on a login
+/// funnel in tests.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Counters {
+    /// Times the login page was shown.
+    pub login_page_views: u64,
+    /// Login codes handed out, whether by the login page or by
+    /// [`crate::fakehub::Fakehub::get_code`].
+    pub codes_issued: u64,
+    /// OAuth tokens minted in exchange for codes.
+    pub tokens_minted: u64,
+    /// Code exchanges refused, for a bad client, code or verifier.
+    pub code_exchange_failures: u64,
+    /// Installation tokens minted for Github Apps.
+    pub installation_tokens_minted: u64,
+}
+
+/// A request one of Fakehub's servers answered, as the admin API's
+/// request log shows it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct CapturedRequest {
+    pub method: String,
+    /// The host the request was made to, eg. `127.0.0.1:3050`.
+    pub host: String,
+    /// The path and query string.
+    pub uri: String,
+    pub status: u16,
+    pub at: DateTime<Utc>,
+}
+
+/// A login code waiting to be exchanged for a token.
+#[derive(Debug)]
+pub struct IssuedCode {
+    pub user_id: UserId,
+    /// The PKCE challenge the authorization was started with, which the
+    /// exchange must present the verifier for.
+    pub code_challenge: Option<String>,
+}
+
+/// A user token as the token endpoint hands it out. Only expiring
+/// tokens come with a refresh token.
+#[derive(Debug)]
+pub struct UserToken {
+    pub access_token: Token,
+    pub expires_in: Option<i64>,
+    pub refresh_token: Option<Token>,
+    pub refresh_token_expires_in: Option<i64>,
+}
+
+impl From<UserToken> for GetAccessTokenResponse {
+    fn from(value: UserToken) -> Self {
+        Self {
+            access_token: value.access_token,
+            token_type: "bearer".to_owned(),
+            scope: "".to_owned(),
+            expires_in: value.expires_in,
+            refresh_token: value.refresh_token,
+            refresh_token_expires_in: value.refresh_token_expires_in,
+        }
+    }
+}
+
+/// A refresh token waiting to be exchanged for a new user token.
+#[derive(Debug)]
+pub struct IssuedRefreshToken {
+    pub user_id: UserId,
+    pub client_id: ClientId,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Why the token endpoint refused to exchange a code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeRefusal {
+    UnknownClient,
+    UnknownCode,
+    WrongVerifier,
+    UnknownRefreshToken,
+}
+
+impl CodeRefusal {
+    /// The OAuth error code the spec has for the refusal, which is what
+    /// Gitea answers with.
+    pub fn spec_error(&self) -> &'static str {
+        match self {
+            Self::UnknownClient => "invalid_client",
+            Self::UnknownCode | Self::WrongVerifier | Self::UnknownRefreshToken => "invalid_grant",
+        }
+    }
+
+    /// The OAuth error code Github answers with.
+    pub fn error(&self) -> &'static str {
+        match self {
+            Self::UnknownClient => "incorrect_client_credentials",
+            Self::UnknownCode | Self::WrongVerifier => "bad_verification_code",
+            Self::UnknownRefreshToken => "bad_refresh_token",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::UnknownClient => "The client_id and/or client_secret passed are incorrect.",
+            Self::UnknownCode => "The code passed is incorrect or expired.",
+            Self::WrongVerifier => "The code_verifier does not match the code_challenge.",
+            Self::UnknownRefreshToken => "The refresh token passed is incorrect or expired.",
+        }
+    }
+}
+
+/// A Github App, which authenticates with JWTs signed by the private
+/// half of its key.
+#[derive(Debug)]
+pub struct App {
+    pub name: String,
+    pub public_key_pem: String,
+}
+
+/// An installation of a Github App on a user or org account.
+#[derive(Debug)]
+pub struct Installation {
+    pub app_id: AppId,
+    /// The id of the user or org the app is installed on.
+    pub account_id: i64,
+    pub permissions: BTreeMap<String, String>,
+    /// The repositories the installation may access, or `None` for all
+    /// of them.
+    pub repositories: Option<Vec<String>>,
+}
+
+/// What an installation access token grants, after any narrowing.
+#[derive(Debug)]
+pub struct InstallationGrant {
+    pub token: Token,
+    pub account: InstallationAccount,
+    pub permissions: BTreeMap<String, String>,
+    pub repositories: Option<Vec<String>>,
+}
+
+/// A place Fakehub delivers webhook events to.
+#[derive(Clone, Debug)]
+pub struct Webhook {
+    pub url: Url,
+    pub secret: String,
+}
+
 #[derive(Debug)]
 pub struct FakehubState {
     pub users: HashMap<UserId, User>,
     pub clients: HashMap<ClientId, Client>,
-    pub issued_codes: HashMap<Code, UserId>,
+    pub issued_codes: HashMap<Code, IssuedCode>,
     pub tokens: HashMap<Token, UserId>,
+    /// When expiring user tokens stop working.
+    pub token_expiries: HashMap<Token, DateTime<Utc>>,
+    pub refresh_tokens: HashMap<Token, IssuedRefreshToken>,
+    /// How long user tokens are good for, or `None` if they never expire.
+    pub user_token_lifetime: Option<Duration>,
+    pub orgs: HashMap<OrgId, Org>,
+    pub org_members: HashMap<OrgId, HashSet<UserId>>,
+    pub org_invitations: HashMap<OrgId, HashSet<UserId>>,
+    pub teams: HashMap<TeamId, Team>,
+    pub team_members: HashMap<TeamId, HashSet<UserId>>,
+    pub emails: HashMap<UserId, Vec<Email>>,
+    pub ssh_keys: HashMap<UserId, Vec<SshKey>>,
+    pub ssh_signing_keys: HashMap<UserId, Vec<SshSigningKey>>,
+    pub gpg_keys: HashMap<UserId, Vec<GpgKey>>,
+    /// Counts keys of every kind, to hand each a distinct id.
+    pub keys_added: i64,
+    pub repos: HashMap<RepoId, Repo>,
+    pub collaborators: HashMap<RepoId, HashMap<UserId, Permission>>,
+    pub apps: HashMap<AppId, App>,
+    pub installations: HashMap<InstallationId, Installation>,
+    pub installation_tokens: HashMap<Token, InstallationId>,
+    pub oidc_issuer: OidcIssuer,
+    pub webhooks: Vec<Webhook>,
+    pub deliveries: u64,
+    pub counters: Counters,
+    /// The most recent requests, oldest first.
+    pub requests: VecDeque<CapturedRequest>,
+    pub flavor: Flavor,
+    pub clock: Arc<dyn Clock>,
+    pub code_format: TokenFormat,
+    pub token_format: TokenFormat,
+    /// The callback URL clients are registered with unless told
+    /// otherwise.
+    pub default_redirect_url: Url,
+    /// How long every request waits before it's answered.
+    pub latency: Option<std::time::Duration>,
+    pub injected_errors: Vec<InjectedError>,
 }
 
 impl FakehubState {
     pub fn new() -> Self {
+        Self::new_with_flavor(Flavor::Github)
+    }
+
+    pub fn new_with_flavor(flavor: Flavor) -> Self {
         Self {
             users: HashMap::new(),
             clients: HashMap::new(),
             issued_codes: HashMap::new(),
             tokens: HashMap::new(),
+            token_expiries: HashMap::new(),
+            refresh_tokens: HashMap::new(),
+            // Gitea only hands out expiring tokens
+            user_token_lifetime: match flavor {
+                Flavor::Gitea => Some(GITEA_TOKEN_LIFETIME),
+                _ => None,
+            },
+            orgs: HashMap::new(),
+            org_members: HashMap::new(),
+            org_invitations: HashMap::new(),
+            teams: HashMap::new(),
+            team_members: HashMap::new(),
+            emails: HashMap::new(),
+            ssh_keys: HashMap::new(),
+            ssh_signing_keys: HashMap::new(),
+            gpg_keys: HashMap::new(),
+            keys_added: 0,
+            repos: HashMap::new(),
+            collaborators: HashMap::new(),
+            apps: HashMap::new(),
+            installations: HashMap::new(),
+            installation_tokens: HashMap::new(),
+            oidc_issuer: OidcIssuer::new(),
+            webhooks: Vec::new(),
+            deliveries: 0,
+            counters: Counters::default(),
+            requests: VecDeque::new(),
+            flavor,
+            clock: Arc::new(SystemClock),
+            code_format: TokenFormat::default(),
+            token_format: TokenFormat::default(),
+            default_redirect_url: Url::parse(DEFAULT_REDIRECT_URL).expect("a valid url"),
+            latency: None,
+            injected_errors: Vec::new(),
         }
     }
 
+    /// Forget everything but how this Fakehub was built: its flavor,
+    /// clock, formats, default redirect URL and faults.
+    pub fn reset(&mut self) {
+        *self = Self {
+            clock: self.clock.clone(),
+            code_format: self.code_format,
+            token_format: self.token_format,
+            default_redirect_url: self.default_redirect_url.clone(),
+            latency: self.latency,
+            injected_errors: std::mem::take(&mut self.injected_errors),
+            ..Self::new_with_flavor(self.flavor)
+        };
+    }
+
+    /// The time by this Fakehub's clock.
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    /// Remove a user, with their tokens, keys, memberships and
+    /// repositories, as if they had deleted their account.
+    pub fn remove_user(&mut self, user_id: UserId) -> Result<()> {
+        if self.users.remove(&user_id).is_none() {
+            return Err(Error::NoSuchUserId(user_id));
+        }
+
+        self.revoke_tokens(user_id);
+        self.issued_codes
+            .retain(|_, issued| issued.user_id != user_id);
+        self.emails.remove(&user_id);
+        self.ssh_keys.remove(&user_id);
+        self.ssh_signing_keys.remove(&user_id);
+        self.gpg_keys.remove(&user_id);
+
+        for members in self
+            .org_members
+            .values_mut()
+            .chain(self.org_invitations.values_mut())
+            .chain(self.team_members.values_mut())
+        {
+            members.remove(&user_id);
+        }
+
+        let repos = self
+            .repos
+            .iter()
+            .filter(|(_, repo)| repo.owner_id == user_id)
+            .map(|(repo_id, _)| *repo_id)
+            .collect::<Vec<_>>();
+
+        for repo_id in repos {
+            self.repos.remove(&repo_id);
+            self.collaborators.remove(&repo_id);
+        }
+        for collaborators in self.collaborators.values_mut() {
+            collaborators.remove(&user_id);
+        }
+
+        self.installations
+            .retain(|_, installation| installation.account_id != user_id);
+        self.installation_tokens
+            .retain(|_, installation_id| self.installations.contains_key(installation_id));
+
+        Ok(())
+    }
+
+    /// Add a request to the request log.
+    pub fn capture_request(&mut self, request: CapturedRequest) {
+        if self.requests.len() == MAX_CAPTURED_REQUESTS {
+            self.requests.pop_front();
+        }
+
+        self.requests.push_back(request);
+    }
+
+    /// Everything that can be loaded from fixtures, as fixtures, ordered
+    /// by id.
+    pub fn fixtures(&self) -> Fixtures {
+        let sorted = |mut ids: Vec<i64>| {
+            ids.sort_unstable();
+            ids
+        };
+        let members = |members: Option<&HashSet<UserId>>| {
+            sorted(members.into_iter().flatten().copied().collect())
+        };
+        let mut fixtures = Fixtures {
+            clients: self
+                .clients
+                .iter()
+                .map(|(id, client)| ClientFixture {
+                    id: id.clone(),
+                    secret: client.secret.clone(),
+                    redirect_url: Some(client.redirect_url.to_string()),
+                })
+                .collect(),
+            users: self
+                .users
+                .iter()
+                .map(|(id, user)| UserFixture {
+                    id: *id,
+                    login: user.login.clone(),
+                    avatar_url: Some(user.avatar_url.clone()),
+                    html_url: Some(user.html_url.clone()),
+                    emails: self
+                        .user_emails(*id)
+                        .into_iter()
+                        .map(|email| EmailFixture {
+                            email: email.email,
+                            verified: email.verified,
+                        })
+                        .collect(),
+                    ssh_keys: self
+                        .ssh_keys
+                        .get(id)
+                        .into_iter()
+                        .flatten()
+                        .map(|key| key.key.to_string())
+                        .collect(),
+                    gpg_keys: self
+                        .gpg_keys
+                        .get(id)
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|key| key.raw_key.clone())
+                        .collect(),
+                })
+                .collect(),
+            orgs: self
+                .orgs
+                .iter()
+                .map(|(id, org)| OrgFixture {
+                    id: *id,
+                    login: org.login.clone(),
+                    members: members(self.org_members.get(id)),
+                })
+                .collect(),
+            teams: self
+                .teams
+                .iter()
+                .map(|(id, team)| TeamFixture {
+                    id: *id,
+                    org_id: team.org_id,
+                    name: team.name.clone(),
+                    slug: Some(team.slug.clone()),
+                    members: members(self.team_members.get(id)),
+                })
+                .collect(),
+            repos: self
+                .repos
+                .iter()
+                .map(|(id, repo)| {
+                    let mut collaborators = self
+                        .collaborators
+                        .get(id)
+                        .into_iter()
+                        .flatten()
+                        .map(|(user_id, permission)| CollaboratorFixture {
+                            user_id: *user_id,
+                            permission: *permission,
+                        })
+                        .collect::<Vec<_>>();
+
+                    collaborators.sort_unstable_by_key(|collaborator| collaborator.user_id);
+
+                    RepoFixture {
+                        id: *id,
+                        owner_id: repo.owner_id,
+                        name: repo.name.clone(),
+                        private: repo.private,
+                        collaborators,
+                    }
+                })
+                .collect(),
+            webhooks: self
+                .webhooks
+                .iter()
+                .map(|webhook| WebhookFixture {
+                    url: webhook.url.to_string(),
+                    secret: webhook.secret.clone(),
+                })
+                .collect(),
+        };
+
+        fixtures.clients.sort_unstable_by(|a, b| a.id.cmp(&b.id));
+        fixtures.users.sort_unstable_by_key(|user| user.id);
+        fixtures.orgs.sort_unstable_by_key(|org| org.id);
+        fixtures.teams.sort_unstable_by_key(|team| team.id);
+        fixtures.repos.sort_unstable_by_key(|repo| repo.id);
+
+        fixtures
+    }
+
     pub fn get_client(&self, client_id: &str) -> Result<&Client> {
         match self.clients.get(client_id) {
             Some(client) => Ok(client),
@@ -51,15 +576,35 @@ impl FakehubState {
 
     /// Get a login code for a given user id.
     pub fn get_code(&mut self, user_id: UserId) -> Result<String> {
+        self.get_code_with_challenge(user_id, None)
+    }
+
+    /// Get a login code for a given user id, which can only be exchanged
+    /// by presenting the verifier for the PKCE challenge.
+    pub fn get_code_with_challenge(
+        &mut self,
+        user_id: UserId,
+        code_challenge: Option<String>,
+    ) -> Result<String> {
         let user_id = user_id.to_owned();
 
         if !self.users.contains_key(&user_id) {
             return Err(Error::NoSuchUserId(user_id));
         }
 
-        let issued_code = format!("token_{}", user_id);
+        let issued_code = match self.code_format {
+            TokenFormat::Predictable => format!("token_{}", user_id),
+            TokenFormat::Random => random_chars(HEX_DIGITS, 20),
+        };
 
-        self.issued_codes.insert(issued_code.clone(), user_id);
+        self.counters.codes_issued += 1;
+        self.issued_codes.insert(
+            issued_code.clone(),
+            IssuedCode {
+                user_id,
+                code_challenge,
+            },
+        );
 
         Ok(issued_code)
     }
@@ -74,21 +619,763 @@ impl FakehubState {
 
     /// Gets a code out of the store, removing it. Prepares for calling
     /// push_token.
-    pub fn pop_code(&mut self, code: &str) -> Option<UserId> {
+    pub fn pop_code(&mut self, code: &str) -> Option<IssuedCode> {
         self.issued_codes.remove(code)
     }
 
+    /// Exchange a login code for a token, the way the token endpoint
+    /// does.
+    pub fn exchange_code(
+        &mut self,
+        client_id: &str,
+        client_secret: &str,
+        code: &str,
+        code_verifier: Option<&str>,
+    ) -> Result<UserToken, CodeRefusal> {
+        let token = self.try_exchange_code(client_id, client_secret, code, code_verifier);
+
+        if token.is_err() {
+            self.counters.code_exchange_failures += 1;
+        }
+
+        token
+    }
+
+    fn try_exchange_code(
+        &mut self,
+        client_id: &str,
+        client_secret: &str,
+        code: &str,
+        code_verifier: Option<&str>,
+    ) -> Result<UserToken, CodeRefusal> {
+        if !self.client_matches(client_id, client_secret) {
+            return Err(CodeRefusal::UnknownClient);
+        }
+
+        let issued_code = self.pop_code(code).ok_or(CodeRefusal::UnknownCode)?;
+
+        if let Some(code_challenge) = issued_code.code_challenge {
+            match code_verifier {
+                Some(code_verifier) if Pkce::challenge_for(code_verifier) == code_challenge => (),
+                _ => return Err(CodeRefusal::WrongVerifier),
+            }
+        }
+
+        Ok(self.push_user_token(client_id, issued_code.user_id))
+    }
+
+    /// Exchange a refresh token for a new user token, the way the token
+    /// endpoint does. Each refresh token can only be used once.
+    pub fn refresh_token(
+        &mut self,
+        client_id: &str,
+        client_secret: &str,
+        refresh_token: &str,
+    ) -> Result<UserToken, CodeRefusal> {
+        if !self.client_matches(client_id, client_secret) {
+            return Err(CodeRefusal::UnknownClient);
+        }
+
+        let issued = self
+            .refresh_tokens
+            .remove(refresh_token)
+            .filter(|issued| issued.client_id == client_id && issued.expires_at > self.now())
+            .ok_or(CodeRefusal::UnknownRefreshToken)?;
+
+        Ok(self.push_user_token(client_id, issued.user_id))
+    }
+
     pub fn push_token(&mut self, user_id: UserId) -> Token {
-        let issued_token = format!("token_{}", user_id);
+        let issued_token = match self.token_format {
+            TokenFormat::Predictable => format!("token_{}", user_id),
+            TokenFormat::Random => format!("gho_{}", random_chars(ALPHANUMERICS, 36)),
+        };
 
         self.tokens.insert(issued_token.to_owned(), user_id);
+        self.counters.tokens_minted += 1;
 
         issued_token
     }
 
+    /// Mint a user token for a client, which expires along with a
+    /// refresh token if expiring user tokens are turned on.
+    pub fn push_user_token(&mut self, client_id: &str, user_id: UserId) -> UserToken {
+        let lifetime = match self.user_token_lifetime {
+            Some(lifetime) => lifetime,
+            None => {
+                return UserToken {
+                    access_token: self.push_token(user_id),
+                    expires_in: None,
+                    refresh_token: None,
+                    refresh_token_expires_in: None,
+                }
+            }
+        };
+
+        // expiring tokens are unique, so a refreshed token can be told
+        // apart from the one it replaces
+        self.counters.tokens_minted += 1;
+        let (access_token, refresh_token) = match self.token_format {
+            TokenFormat::Predictable => (
+                format!("ghu_{:036}", self.counters.tokens_minted),
+                format!("ghr_{:076}", self.counters.tokens_minted),
+            ),
+            TokenFormat::Random => (
+                format!("ghu_{}", random_chars(ALPHANUMERICS, 36)),
+                format!("ghr_{}", random_chars(ALPHANUMERICS, 76)),
+            ),
+        };
+
+        self.tokens.insert(access_token.clone(), user_id);
+        self.token_expiries
+            .insert(access_token.clone(), self.now() + lifetime);
+        self.refresh_tokens.insert(
+            refresh_token.clone(),
+            IssuedRefreshToken {
+                user_id,
+                client_id: client_id.to_owned(),
+                expires_at: self.now() + REFRESH_TOKEN_LIFETIME,
+            },
+        );
+
+        UserToken {
+            access_token,
+            expires_in: Some(lifetime.num_seconds()),
+            refresh_token: Some(refresh_token),
+            refresh_token_expires_in: Some(REFRESH_TOKEN_LIFETIME.num_seconds()),
+        }
+    }
+
+    /// Mint a new delivery id, shaped like the GUIDs Github uses.
+    pub fn next_delivery_id(&mut self) -> DeliveryId {
+        self.deliveries += 1;
+
+        DeliveryId(format!("00000000-0000-4000-8000-{:012x}", self.deliveries))
+    }
+
     pub fn get_user_by_login(&self, login: &str) -> Option<(&UserId, &User)> {
         self.users.iter().find(|u| u.1.login == login)
     }
+
+    /// The user an OAuth token was issued to.
+    pub fn token_user(&self, token: &str) -> Result<UserId> {
+        let expired = self
+            .token_expiries
+            .get(token)
+            .map(|expires_at| *expires_at <= self.now())
+            .unwrap_or(false);
+
+        match self.tokens.get(token) {
+            Some(user_id) if !expired => Ok(*user_id),
+            _ => Err(Error::Unauthorized),
+        }
+    }
+
+    /// Describe a user the way the `/user` endpoint does.
+    pub fn user_detail(&self, user_id: UserId) -> Result<UserDetailResponse> {
+        match self.users.get(&user_id) {
+            Some(user) => Ok(UserDetailResponse {
+                id: user_id,
+                login: user.login.clone(),
+                html_url: user.html_url.clone(),
+                avatar_url: user.avatar_url.clone(),
+            }),
+            None => Err(Error::NoSuchUserId(user_id)),
+        }
+    }
+
+    /// Describe a user the way the flavor's `/user` endpoint does.
+    /// Gitea's users have a few more fields, and name them differently.
+    pub fn flavored_user_detail(&self, user_id: UserId) -> Result<serde_json::Value> {
+        let user = self.user_detail(user_id)?;
+
+        Ok(match self.flavor {
+            Flavor::Github | Flavor::GithubEnterprise => serde_json::to_value(user)?,
+            Flavor::Gitea => serde_json::json!({
+                "id": user.id,
+                "login": user.login,
+                "login_name": "",
+                "full_name": "",
+                "email": self
+                    .user_emails(user_id)
+                    .into_iter()
+                    .find(|email| email.primary)
+                    .map(|email| email.email)
+                    .unwrap_or_default(),
+                "avatar_url": user.avatar_url,
+                "html_url": user.html_url,
+                "is_admin": false,
+                "username": user.login,
+            }),
+        })
+    }
+
+    /// The orgs a user is a member of, ordered by id.
+    pub fn user_orgs(&self, user_id: UserId) -> Result<Vec<Organization>> {
+        let mut orgs = Vec::new();
+
+        for (org_id, members) in self.org_members.iter() {
+            if members.contains(&user_id) {
+                let organization = self.organization(*org_id)?;
+
+                orgs.push(Organization {
+                    id: organization.id,
+                    login: organization.login,
+                });
+            }
+        }
+
+        orgs.sort_by_key(|org| org.id);
+
+        Ok(orgs)
+    }
+
+    /// Find a repository by its owner's login and its name.
+    pub fn repo_by_name(&self, owner: &str, name: &str) -> Result<RepoId> {
+        self.repos
+            .iter()
+            .find(|(_, repo)| {
+                repo.name == name
+                    && self
+                        .installation_account(repo.owner_id)
+                        .map(|account| account.login == owner)
+                        .unwrap_or(false)
+            })
+            .map(|(repo_id, _)| *repo_id)
+            .ok_or_else(|| Error::NoSuchRepo(format!("{}/{}", owner, name)))
+    }
+
+    /// How much a user may do in a repository: everything in their own,
+    /// read in those of their orgs and in public ones, and whatever
+    /// they were given as a collaborator.
+    pub fn repo_permission(&self, repo_id: RepoId, user_id: UserId) -> Permission {
+        let repo = match self.repos.get(&repo_id) {
+            Some(repo) => repo,
+            None => return Permission::None,
+        };
+
+        if repo.owner_id == user_id {
+            return Permission::Admin;
+        }
+
+        let org_member = self
+            .org_members
+            .get(&repo.owner_id)
+            .map(|members| members.contains(&user_id))
+            .unwrap_or(false);
+        let base = match org_member || !repo.private {
+            true => Permission::Read,
+            false => Permission::None,
+        };
+        let collaborator = self
+            .collaborators
+            .get(&repo_id)
+            .and_then(|collaborators| collaborators.get(&user_id))
+            .copied()
+            .unwrap_or(Permission::None);
+
+        base.max(collaborator)
+    }
+
+    /// How much a user may do in a repository, as asked by another user,
+    /// who must be able to see it.
+    pub fn collaborator_permission(
+        &self,
+        owner: &str,
+        repo: &str,
+        viewer_id: UserId,
+        username: &str,
+    ) -> Result<(UserId, Permission)> {
+        let repo_id = self.repo_by_name(owner, repo)?;
+
+        // repositories the viewer can't see don't exist, as far as they
+        // know
+        if self.repo_permission(repo_id, viewer_id) == Permission::None {
+            return Err(Error::NoSuchRepo(format!("{}/{}", owner, repo)));
+        }
+
+        match self.get_user_by_login(username) {
+            Some((user_id, _)) => Ok((*user_id, self.repo_permission(repo_id, *user_id))),
+            None => Err(Error::NoSuchUserLogin(username.to_owned())),
+        }
+    }
+
+    /// Describe a repository the way the API lists it for a user.
+    pub fn repository(&self, repo_id: RepoId, user_id: UserId) -> Result<Repository> {
+        let repo = self
+            .repos
+            .get(&repo_id)
+            .ok_or_else(|| Error::NoSuchRepo(repo_id.to_string()))?;
+        let owner = self.installation_account(repo.owner_id)?;
+        let full_name = format!("{}/{}", owner.login, repo.name);
+
+        Ok(Repository {
+            id: repo_id,
+            name: repo.name.clone(),
+            html_url: format!("https://github.com/{}", full_name),
+            full_name,
+            owner: RepositoryOwner {
+                id: owner.id,
+                login: owner.login,
+            },
+            private: repo.private,
+            permissions: Some(self.repo_permission(repo_id, user_id).into()),
+        })
+    }
+
+    /// The repositories a user is affiliated with in any of the given
+    /// ways, ordered by id.
+    pub fn user_repos(
+        &self,
+        user_id: UserId,
+        affiliations: &[Affiliation],
+    ) -> Result<Vec<Repository>> {
+        let mut repos = Vec::new();
+
+        for (repo_id, repo) in self.repos.iter() {
+            let affiliated = affiliations.iter().any(|affiliation| match affiliation {
+                Affiliation::Owner => repo.owner_id == user_id,
+                Affiliation::Collaborator => self
+                    .collaborators
+                    .get(repo_id)
+                    .map(|collaborators| collaborators.contains_key(&user_id))
+                    .unwrap_or(false),
+                Affiliation::OrganizationMember => self
+                    .org_members
+                    .get(&repo.owner_id)
+                    .map(|members| members.contains(&user_id))
+                    .unwrap_or(false),
+            });
+
+            if affiliated {
+                repos.push(self.repository(*repo_id, user_id)?);
+            }
+        }
+
+        repos.sort_by_key(|repo| repo.id);
+
+        Ok(repos)
+    }
+
+    /// Who a user is and where they belong, the way the GraphQL viewer
+    /// identity query finds out.
+    pub fn viewer_identity(&self, user_id: UserId) -> Result<ViewerIdentity> {
+        Ok(ViewerIdentity {
+            user: self.user_detail(user_id)?,
+            organizations: self.user_orgs(user_id)?,
+            teams: self.user_teams(user_id)?,
+        })
+    }
+
+    /// The teams a user is a member of, ordered by id.
+    pub fn user_teams(&self, user_id: UserId) -> Result<Vec<UserTeam>> {
+        let mut teams = Vec::new();
+
+        for (team_id, members) in self.team_members.iter() {
+            if members.contains(&user_id) {
+                let team = self.team(*team_id)?;
+                let organization = self.organization(self.teams[team_id].org_id)?;
+
+                teams.push(UserTeam {
+                    id: team.id,
+                    name: team.name,
+                    slug: team.slug,
+                    organization: Organization {
+                        id: organization.id,
+                        login: organization.login,
+                    },
+                });
+            }
+        }
+
+        teams.sort_by_key(|team| team.id);
+
+        Ok(teams)
+    }
+
+    /// The teams of the orgs a user belongs to, the way GraphQL's
+    /// `teams(userLogins:)` lists them: only those with a member of one
+    /// of the logins, if any are given, and otherwise all of them.
+    /// Ordered by id.
+    pub fn org_teams(
+        &self,
+        user_id: UserId,
+        user_logins: Option<&[&str]>,
+    ) -> Result<Vec<UserTeam>> {
+        let mut teams = Vec::new();
+
+        for (team_id, team) in self.teams.iter() {
+            let in_org = self
+                .org_members
+                .get(&team.org_id)
+                .is_some_and(|members| members.contains(&user_id));
+            let has_member = |logins: &[&str]| {
+                self.team_members.get(team_id).is_some_and(|members| {
+                    members.iter().any(|member| {
+                        self.users
+                            .get(member)
+                            .is_some_and(|user| logins.contains(&user.login.as_str()))
+                    })
+                })
+            };
+
+            if in_org && user_logins.is_none_or(has_member) {
+                let organization = self.organization(team.org_id)?;
+
+                teams.push(UserTeam {
+                    id: *team_id,
+                    name: team.name.clone(),
+                    slug: team.slug.clone(),
+                    organization: Organization {
+                        id: organization.id,
+                        login: organization.login,
+                    },
+                });
+            }
+        }
+
+        teams.sort_by_key(|team| team.id);
+
+        Ok(teams)
+    }
+
+    pub fn user_emails(&self, user_id: UserId) -> Vec<Email> {
+        self.emails.get(&user_id).cloned().unwrap_or_default()
+    }
+
+    /// The id of the user with a login, for the endpoints that name
+    /// users by login.
+    pub fn login_user(&self, login: &str) -> Result<UserId> {
+        match self.get_user_by_login(login) {
+            Some((user_id, _)) => Ok(*user_id),
+            None => Err(Error::NoSuchUserLogin(login.to_owned())),
+        }
+    }
+
+    pub fn user_ssh_keys(&self, login: &str) -> Result<Vec<SshKey>> {
+        let user_id = self.login_user(login)?;
+
+        Ok(self.ssh_keys.get(&user_id).cloned().unwrap_or_default())
+    }
+
+    pub fn user_ssh_signing_keys(&self, login: &str) -> Result<Vec<SshSigningKey>> {
+        let user_id = self.login_user(login)?;
+
+        Ok(self
+            .ssh_signing_keys
+            .get(&user_id)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    pub fn user_gpg_keys(&self, login: &str) -> Result<Vec<GpgKey>> {
+        let user_id = self.login_user(login)?;
+
+        Ok(self.gpg_keys.get(&user_id).cloned().unwrap_or_default())
+    }
+
+    pub fn next_key_id(&mut self) -> i64 {
+        self.keys_added += 1;
+
+        self.keys_added
+    }
+
+    /// Give a user an email address. The first one a user is given is
+    /// their primary address.
+    pub fn add_email(&mut self, user_id: UserId, email: &str, verified: bool) -> Result<()> {
+        if !self.users.contains_key(&user_id) {
+            return Err(Error::NoSuchUserId(user_id));
+        }
+
+        let emails = self.emails.entry(user_id).or_default();
+        let primary = emails.is_empty();
+
+        emails.push(Email {
+            email: email.to_owned(),
+            primary,
+            verified,
+            visibility: primary.then(|| "private".to_owned()),
+        });
+
+        Ok(())
+    }
+
+    /// Give a user an SSH key to authenticate with, in the OpenSSH
+    /// format. Like Github, Fakehub drops the comment. Returns the id of
+    /// the key.
+    pub fn add_ssh_key(&mut self, user_id: UserId, key: &str) -> Result<i64> {
+        let key = key.parse::<SshPublicKey>()?.with_comment("");
+
+        if !self.users.contains_key(&user_id) {
+            return Err(Error::NoSuchUserId(user_id));
+        }
+
+        let id = self.next_key_id();
+
+        self.ssh_keys
+            .entry(user_id)
+            .or_default()
+            .push(SshKey { id, key });
+
+        Ok(id)
+    }
+
+    /// Give a user a GPG key, ASCII armored as `gpg --armor --export`
+    /// writes it. Fakehub reads the keys and user ids from it, but not
+    /// the signatures, so the capabilities are whatever each key's
+    /// algorithm allows and the key never expires. Returns the id of
+    /// the primary key.
+    pub fn add_gpg_key(&mut self, user_id: UserId, armored: &str) -> Result<i64> {
+        let certificate = OpenPgpCertificate::from_armored(armored)?;
+
+        if !self.users.contains_key(&user_id) {
+            return Err(Error::NoSuchUserId(user_id));
+        }
+
+        // the addresses in the user ids, eg. `Name <name@example.com>`,
+        // verified if the user has verified them on their account
+        let emails = certificate
+            .user_ids
+            .iter()
+            .filter_map(|identity| identity.rsplit_once('<')?.1.strip_suffix('>'))
+            .map(|email| GpgKeyEmail {
+                email: email.to_owned(),
+                verified: self
+                    .user_emails(user_id)
+                    .iter()
+                    .any(|known| known.email == email && known.verified),
+            })
+            .collect();
+        let mut keys = Vec::new();
+
+        for key in &certificate.keys {
+            let id = self.next_key_id();
+            // RSA signs and encrypts, DSA, ECDSA and EdDSA sign, and
+            // Elgamal, ECDH and X25519 encrypt
+            let (can_sign, can_encrypt) = match key.algorithm {
+                1 => (true, true),
+                17 | 19 | 22 | 27 | 28 => (true, false),
+                _ => (false, true),
+            };
+
+            keys.push(GpgKey {
+                id,
+                name: None,
+                primary_key_id: keys.first().map(|primary: &GpgKey| primary.id),
+                key_id: key.key_id().to_owned(),
+                public_key: STANDARD.encode(&key.packet),
+                emails: Vec::new(),
+                subkeys: Vec::new(),
+                can_sign,
+                can_encrypt_comms: can_encrypt,
+                can_encrypt_storage: can_encrypt,
+                can_certify: can_sign && keys.is_empty(),
+                created_at: key.created_at,
+                expires_at: None,
+                revoked: false,
+                raw_key: None,
+            });
+        }
+
+        let mut keys = keys.into_iter();
+        let mut primary = keys.next().expect("certificates have a primary key");
+        let id = primary.id;
+
+        primary.emails = emails;
+        primary.subkeys = keys.collect();
+        primary.raw_key = Some(armored.to_owned());
+        self.gpg_keys.entry(user_id).or_default().push(primary);
+
+        Ok(id)
+    }
+
+    /// Describe a user the way webhook payloads do.
+    pub fn account(&self, user_id: UserId) -> Result<events::Account> {
+        match self.users.get(&user_id) {
+            Some(user) => Ok(events::Account {
+                id: user_id,
+                login: user.login.clone(),
+                avatar_url: Some(user.avatar_url.clone()),
+                html_url: Some(user.html_url.clone()),
+            }),
+            None => Err(Error::NoSuchUserId(user_id)),
+        }
+    }
+
+    /// Describe an org the way webhook payloads do.
+    pub fn organization(&self, org_id: OrgId) -> Result<events::Organization> {
+        match self.orgs.get(&org_id) {
+            Some(org) => Ok(events::Organization {
+                id: org_id,
+                login: org.login.clone(),
+            }),
+            None => Err(Error::NoSuchOrg(org_id)),
+        }
+    }
+
+    /// Describe a team the way webhook payloads do.
+    pub fn team(&self, team_id: TeamId) -> Result<events::Team> {
+        match self.teams.get(&team_id) {
+            Some(team) => Ok(events::Team {
+                id: team_id,
+                name: team.name.clone(),
+                slug: team.slug.clone(),
+            }),
+            None => Err(Error::NoSuchTeam(team_id)),
+        }
+    }
+
+    /// Describe the account an app is installed on, which may be either
+    /// a user or an org.
+    pub fn installation_account(&self, account_id: i64) -> Result<InstallationAccount> {
+        if let Some(user) = self.users.get(&account_id) {
+            return Ok(InstallationAccount {
+                id: account_id,
+                login: user.login.clone(),
+            });
+        }
+
+        match self.orgs.get(&account_id) {
+            Some(org) => Ok(InstallationAccount {
+                id: account_id,
+                login: org.login.clone(),
+            }),
+            None => Err(Error::NoSuchUserId(account_id)),
+        }
+    }
+
+    /// Check a JWT presented by a Github App, returning the app's id if
+    /// it was signed by the app's key and isn't expired or too
+    /// long-lived.
+    pub fn authenticate_app(&self, jwt: &str) -> Result<AppId> {
+        // peek at the issuer to find out whose key to check against
+        let mut unverified = Validation::new(Algorithm::RS256);
+        unverified.insecure_disable_signature_validation();
+        unverified.validate_exp = false;
+        let app_id =
+            jsonwebtoken::decode::<AppClaims>(jwt, &DecodingKey::from_secret(&[]), &unverified)
+                .map_err(|e| Error::InvalidJwt(e.to_string()))?
+                .claims
+                .iss
+                .parse::<AppId>()
+                .map_err(|e| Error::InvalidJwt(e.to_string()))?;
+        let app = self.apps.get(&app_id).ok_or(Error::NoSuchApp(app_id))?;
+        let decoding_key = DecodingKey::from_rsa_pem(app.public_key_pem.as_bytes())
+            .map_err(|e| Error::InvalidJwt(e.to_string()))?;
+        // expiry is checked against Fakehub's clock, not the system's
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.validate_exp = false;
+        let claims = jsonwebtoken::decode::<AppClaims>(jwt, &decoding_key, &validation)
+            .map_err(|e| Error::InvalidJwt(e.to_string()))?
+            .claims;
+        let now = self.now().timestamp();
+
+        if claims.exp + (validation.leeway as i64) < now {
+            return Err(Error::InvalidJwt("exp is in the past".to_owned()));
+        }
+        if claims.exp - now > MAX_APP_JWT_LIFETIME_SECONDS {
+            return Err(Error::InvalidJwt("exp is too far in the future".to_owned()));
+        }
+
+        Ok(app_id)
+    }
+
+    /// Issue a `ghs_` token for an installation, narrowed as requested.
+    pub fn push_installation_token(
+        &mut self,
+        app_id: AppId,
+        installation_id: InstallationId,
+        request: &InstallationTokenRequest,
+    ) -> Result<InstallationGrant> {
+        let installation = match self.installations.get(&installation_id) {
+            Some(installation) if installation.app_id == app_id => installation,
+            _ => return Err(Error::NoSuchInstallation(installation_id)),
+        };
+
+        if request.repository_ids.is_some() {
+            return Err(Error::Unprocessable(
+                "Fakehub does not track repository ids".to_owned(),
+            ));
+        }
+
+        let repositories = match &request.repositories {
+            Some(requested) => {
+                if let Some(granted) = &installation.repositories {
+                    if let Some(repository) = requested.iter().find(|r| !granted.contains(r)) {
+                        return Err(Error::Unprocessable(format!(
+                            "Installation cannot access repository {}",
+                            repository
+                        )));
+                    }
+                }
+
+                Some(requested.clone())
+            }
+            None => installation.repositories.clone(),
+        };
+
+        let permissions = match &request.permissions {
+            Some(requested) => {
+                for (permission, level) in requested.iter() {
+                    let granted = installation
+                        .permissions
+                        .get(permission)
+                        .map(|level| permission_rank(level))
+                        .unwrap_or(0);
+
+                    if permission_rank(level) > granted {
+                        return Err(Error::Unprocessable(format!(
+                            "Installation cannot grant {} access to {}",
+                            level, permission
+                        )));
+                    }
+                }
+
+                requested.clone()
+            }
+            None => installation.permissions.clone(),
+        };
+
+        let account = self.installation_account(installation.account_id)?;
+        // counted rather than numbered by the map's size, which shrinks
+        // when users are removed
+        self.counters.installation_tokens_minted += 1;
+        let token = format!("ghs_{:036}", self.counters.installation_tokens_minted);
+
+        self.installation_tokens
+            .insert(token.clone(), installation_id);
+
+        Ok(InstallationGrant {
+            token,
+            account,
+            permissions,
+            repositories,
+        })
+    }
+
+    /// When installation tokens issued now expire.
+    pub fn installation_token_expiry(&self) -> chrono::DateTime<Utc> {
+        self.now() + INSTALLATION_TOKEN_LIFETIME
+    }
+
+    /// Forget every token issued to a user.
+    pub fn revoke_tokens(&mut self, user_id: UserId) {
+        self.tokens
+            .retain(|_, token_user_id| *token_user_id != user_id);
+        self.token_expiries
+            .retain(|token, _| self.tokens.contains_key(token));
+        self.refresh_tokens
+            .retain(|_, issued| issued.user_id != user_id);
+    }
+}
+
+fn permission_rank(level: &str) -> u8 {
+    match level {
+        "read" => 1,
+        "write" => 2,
+        "admin" => 3,
+        _ => 0,
+    }
 }
 
 impl Client {
@@ -121,3 +1408,12 @@ impl Client {
         Ok(redirect_url.clone())
     }
 }
+
+/// `len` characters picked at random from `charset`.
+fn random_chars(charset: &[u8], len: usize) -> String {
+    let mut rng = rand::thread_rng();
+
+    (0..len)
+        .map(|_| charset[rng.gen_range(0..charset.len())] as char)
+        .collect()
+}
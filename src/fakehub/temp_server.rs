@@ -1,6 +1,9 @@
-use std::net::SocketAddr;
+use std::{
+    net::{SocketAddr, TcpListener},
+    time::Instant,
+};
 
-use axum::{Router, Server};
+use axum::{http::Request, middleware::Next, response::Response, Router, Server};
 use tokio::{
     spawn,
     sync::oneshot::{channel, Sender},
@@ -19,12 +22,14 @@ pub struct TempServer {
 }
 
 impl TempServer {
-    // Start the server.
-    pub fn new(starting_port: u16, app: Router) -> Result<Self> {
-        let port = port_selector::select_from_given_port(starting_port)
-            .ok_or(Error::NoAvailablePorts(starting_port))?;
-        let socket = SocketAddr::from(([127, 0, 0, 1], port));
-        let server = Server::bind(&socket).serve(app.into_make_service());
+    // Start the server on an already bound listener.
+    pub fn new(listener: TcpListener, app: Router) -> Result<Self> {
+        let socket = listener
+            .local_addr()
+            .map_err(|error| Error::OtherHttp(error.to_string()))?;
+        let server = Server::from_tcp(listener)
+            .map_err(|error| Error::Bind(socket, error.to_string()))?
+            .serve(app.into_make_service());
         let (tx, rx) = channel::<()>();
         let graceful = server.with_graceful_shutdown(async {
             rx.await.ok();
@@ -51,3 +56,100 @@ impl TempServer {
         self.completion_handle.await.ok();
     }
 }
+
+/// Bind a listener for a server. Port 0 picks a free port, atomically,
+/// so any number of servers can start at once.
+pub fn bind(socket: SocketAddr) -> Result<TcpListener> {
+    TcpListener::bind(socket).map_err(|error| Error::Bind(socket, error.to_string()))
+}
+
+/// Log every request a Fakehub server answers, for following along with
+/// a standalone Fakehub.
+pub(super) async fn log_request<B>(request: Request<B>, next: Next<B>) -> Response {
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    tracing::info!(
+        target: "fakehub",
+        "{} {} {} {}ms",
+        method,
+        uri,
+        response.status().as_u16(),
+        started.elapsed().as_millis(),
+    );
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use crate::{
+        fakehub::{Fakehub, Flavor},
+        testing::{user_named, CLIENT_ID, CLIENT_SECRET},
+    };
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_fakehubs() {
+        const FAKEHUBS: i64 = 32;
+
+        let mut starting = tokio::task::JoinSet::new();
+
+        for user_id in 0..FAKEHUBS {
+            starting.spawn(async move {
+                let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+                let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+                fakehub
+                    .add_user(user_id, user_named(&format!("user{}", user_id)))
+                    .await;
+
+                let code = fakehub.get_code(user_id).await.unwrap();
+                let token = github_client.get_access_token(&code).await.unwrap();
+                let user_detail = github_client
+                    .get_user_detail(&token.access_token)
+                    .await
+                    .unwrap();
+                assert_eq!(user_id, user_detail.id);
+
+                fakehub
+            });
+        }
+
+        let mut fakehubs = Vec::new();
+        while let Some(fakehub) = starting.join_next().await {
+            fakehubs.push(fakehub.unwrap());
+        }
+
+        // every server got a port of its own
+        let ports = fakehubs
+            .iter()
+            .flat_map(|fakehub| {
+                [
+                    fakehub.github_dot_com_socket().port(),
+                    fakehub.api_dot_github_dot_com_socket().port(),
+                ]
+            })
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(2 * FAKEHUBS as usize, ports.len());
+
+        for fakehub in fakehubs {
+            fakehub.shutdown().await;
+        }
+
+        // and the caller may bind the listeners themselves
+        let web_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let web_addr = web_listener.local_addr().unwrap();
+        let fakehub = Fakehub::from_listeners(
+            web_listener,
+            TcpListener::bind("127.0.0.1:0").unwrap(),
+            Flavor::Github,
+        )
+        .unwrap();
+        assert_eq!(&web_addr, fakehub.github_dot_com_socket());
+        assert!(!fakehub.is_in_process());
+        fakehub.shutdown().await;
+    }
+}
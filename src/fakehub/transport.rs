@@ -0,0 +1,136 @@
+use async_trait::async_trait;
+use axum::{body::Body, Router};
+use reqwest::{Request, Response, Url};
+
+use crate::transport::{ServiceTransport, Transport};
+
+/// Sends a [`GithubClient`](crate::GithubClient)'s requests straight to
+/// a Fakehub's routers, picking github.com's or api.github.com's by the
+/// host and port of the request, without a socket in sight.
+#[derive(Clone, Debug)]
+pub struct FakehubTransport {
+    web_url: Url,
+    web: ServiceTransport<Router, Body>,
+    api_url: Url,
+    api: ServiceTransport<Router, Body>,
+}
+
+impl FakehubTransport {
+    pub(super) fn new(web_url: &str, web: Router, api_url: &str, api: Router) -> Self {
+        Self {
+            web_url: Url::parse(web_url).expect("Fakehub's urls are valid"),
+            web: ServiceTransport::new(web),
+            api_url: Url::parse(api_url).expect("Fakehub's urls are valid"),
+            api: ServiceTransport::new(api),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for FakehubTransport {
+    async fn send(&self, request: Request) -> Result<Response, crate::Error> {
+        if same_origin(request.url(), &self.web_url) {
+            self.web.send(request).await
+        } else if same_origin(request.url(), &self.api_url) {
+            self.api.send(request).await
+        } else {
+            Err(crate::Error::OtherHttp(format!(
+                "{} is not served by this Fakehub",
+                request.url()
+            )))
+        }
+    }
+}
+
+fn same_origin(url: &Url, base: &Url) -> bool {
+    url.host_str() == base.host_str() && url.port_or_known_default() == base.port_or_known_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use tower::ServiceExt;
+
+    use crate::{
+        fakehub::{Fakehub, Flavor},
+        testing::{add_test_user, user_named, CLIENT_ID, CLIENT_SECRET, USER, USER_ID},
+        GithubClient, Provider,
+    };
+
+    #[tokio::test]
+    async fn in_process_fakehub() {
+        let fakehub = Fakehub::builder()
+            .flavor(Flavor::GithubEnterprise)
+            .user(USER_ID, user_named(USER))
+            .start_in_process()
+            .await
+            .expect("cannot start in-process fakehub");
+        let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+
+        assert!(github_client
+            .authorization_url()
+            .starts_with("http://github.invalid/login/oauth/authorize"));
+
+        let code = fakehub.get_code(USER_ID).await.unwrap();
+        let token = github_client.get_access_token(&code).await.unwrap();
+        let user_detail = github_client
+            .get_user_detail(&token.access_token)
+            .await
+            .unwrap();
+        assert_eq!(USER, user_detail.login);
+
+        // requests went through the routers, middleware and all
+        let requests = fakehub.requests().await;
+        assert_eq!(2, requests.len());
+        assert!(requests
+            .iter()
+            .all(|request| request.host == "github.invalid" && request.status == 200));
+
+        // there are no sockets to be had
+        assert!(fakehub.is_in_process());
+
+        // the routers can be called as plain tower services too
+        let response = fakehub
+            .github_dot_com_router()
+            .oneshot(
+                http::Request::get("/")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        // and nothing else is reachable from the transport
+        let elsewhere = GithubClient::new_with_provider(
+            CLIENT_ID,
+            CLIENT_SECRET,
+            Provider::github_enterprise("http://example.invalid"),
+        )
+        .unwrap()
+        .with_transport(fakehub.transport());
+        assert!(elsewhere.get_access_token(&code).await.is_err());
+
+        // a socket Fakehub's transport skips its sockets
+        let socket_fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        add_test_user(&socket_fakehub).await;
+        let in_memory = socket_fakehub
+            .add_client(CLIENT_ID, CLIENT_SECRET)
+            .await
+            .unwrap()
+            .with_transport(socket_fakehub.transport());
+        let code = socket_fakehub.get_code(USER_ID).await.unwrap();
+        let token = in_memory.get_access_token(&code).await.unwrap();
+        assert_eq!(
+            USER,
+            in_memory
+                .get_user_detail(&token.access_token)
+                .await
+                .unwrap()
+                .login
+        );
+
+        socket_fakehub.shutdown().await;
+        fakehub.shutdown().await;
+    }
+}
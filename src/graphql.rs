@@ -0,0 +1,415 @@
+//! Just enough of Github's GraphQL API to find out who a user is, and
+//! where they belong, in two requests.
+//!
+//! [`GithubClient::graphql`](crate::GithubClient::graphql) sends any
+//! query, and
+//! [`GithubClient::viewer_identity`](crate::GithubClient::viewer_identity)
+//! asks for the user's login and then sends [`VIEWER_IDENTITY_QUERY`]
+//! to get the user along with their organizations and teams, which
+//! takes three calls to the REST API.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{error::Error, Organization, Team, UserDetailResponse};
+
+/// The login of the user a token belongs to, which
+/// [`VIEWER_IDENTITY_QUERY`] needs to narrow their organizations' teams
+/// down to their own.
+pub(crate) const VIEWER_LOGIN_QUERY: &str = "query ViewerLogin {
+  viewer {
+    login
+  }
+}";
+
+/// The user a token belongs to, their organizations, and their teams in
+/// those organizations, at most 100 of each. `$login` must be the user's
+/// own, since Github otherwise lists every team it can see. Needs the
+/// `read:org` scope to see private memberships.
+pub const VIEWER_IDENTITY_QUERY: &str = "query ViewerIdentity($first: Int!, $login: String!) {
+  viewer {
+    databaseId
+    login
+    avatarUrl
+    url
+    organizations(first: $first) {
+      pageInfo {
+        hasNextPage
+      }
+      nodes {
+        databaseId
+        login
+        teams(first: $first, userLogins: [$login]) {
+          pageInfo {
+            hasNextPage
+          }
+          nodes {
+            databaseId
+            name
+            slug
+          }
+        }
+      }
+    }
+  }
+}";
+
+/// How many organizations, and teams in each, the viewer identity query
+/// asks for. Github allows no more per page.
+pub(crate) const VIEWER_IDENTITY_PAGE_SIZE: u32 = 100;
+
+/// Who a user is and where they belong, as answered by
+/// [`VIEWER_IDENTITY_QUERY`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ViewerIdentity {
+    pub user: UserDetailResponse,
+    pub organizations: Vec<Organization>,
+    pub teams: Vec<Team>,
+}
+
+/// An error Github answered a query with, eg. for a field that doesn't
+/// exist or a resource the token can't see. Github answers with these
+/// and a 200, possibly alongside partial data.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct GraphQLError {
+    pub message: String,
+    /// What kind of error it is, eg. `NOT_FOUND` or `FORBIDDEN`.
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// Where in the query the error happened.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub path: Vec<Value>,
+}
+
+impl std::fmt::Display for GraphQLError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.kind {
+            Some(kind) => write!(f, "{}: {}", kind, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// The body of a GraphQL request.
+#[derive(Serialize)]
+pub(crate) struct Request<'a, V> {
+    pub query: &'a str,
+    pub variables: &'a V,
+}
+
+/// The body of a GraphQL response.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Response<T> {
+    #[serde(default = "Option::default")]
+    pub data: Option<T>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<GraphQLError>,
+}
+
+impl<T> Response<T> {
+    /// The data, unless Github answered with any errors at all.
+    pub(crate) fn into_result(self) -> Result<T, Error> {
+        match (self.data, self.errors) {
+            (Some(data), errors) if errors.is_empty() => Ok(data),
+            (None, errors) if errors.is_empty() => {
+                Err(Error::Decode("GraphQL response has no data".to_owned()))
+            }
+            (_, errors) => Err(Error::GraphQL(errors)),
+        }
+    }
+}
+
+/// The `data` of a [`VIEWER_LOGIN_QUERY`] response.
+#[derive(Deserialize)]
+pub(crate) struct ViewerLoginData {
+    pub viewer: ViewerLogin,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct ViewerLogin {
+    pub login: String,
+}
+
+/// The `data` of a [`VIEWER_IDENTITY_QUERY`] response.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct ViewerData {
+    viewer: Viewer,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Viewer {
+    database_id: i64,
+    login: String,
+    avatar_url: String,
+    url: String,
+    organizations: Nodes<ViewerOrganization>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ViewerOrganization {
+    database_id: i64,
+    login: String,
+    teams: Nodes<ViewerTeam>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ViewerTeam {
+    database_id: i64,
+    name: String,
+    slug: String,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Nodes<T> {
+    page_info: PageInfo,
+    nodes: Vec<T>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PageInfo {
+    has_next_page: bool,
+}
+
+/// The viewer's identity, unless there's more of it than fits in a
+/// page, in which case it's refused rather than cut short.
+impl TryFrom<ViewerData> for ViewerIdentity {
+    type Error = Error;
+
+    fn try_from(value: ViewerData) -> Result<Self, Error> {
+        let viewer = value.viewer;
+        let mut organizations = Vec::new();
+        let mut teams = Vec::new();
+
+        if viewer.organizations.page_info.has_next_page {
+            return Err(Error::Incomplete(format!(
+                "{} belongs to more organizations than fit in a page",
+                viewer.login
+            )));
+        }
+
+        for organization in viewer.organizations.nodes {
+            if organization.teams.page_info.has_next_page {
+                return Err(Error::Incomplete(format!(
+                    "{} has more teams than fit in a page",
+                    organization.login
+                )));
+            }
+
+            let owner = Organization {
+                id: organization.database_id,
+                login: organization.login,
+            };
+
+            teams.extend(organization.teams.nodes.into_iter().map(|team| Team {
+                id: team.database_id,
+                name: team.name,
+                slug: team.slug,
+                organization: owner.clone(),
+            }));
+            organizations.push(owner);
+        }
+
+        Ok(Self {
+            user: UserDetailResponse {
+                id: viewer.database_id,
+                login: viewer.login,
+                avatar_url: viewer.avatar_url,
+                html_url: viewer.url,
+            },
+            organizations,
+            teams,
+        })
+    }
+}
+
+/// Answer the viewer identity query the way Github would, at most
+/// `first` organizations and teams in each.
+#[cfg(feature = "fakehub")]
+impl ViewerData {
+    pub(crate) fn new(identity: ViewerIdentity, first: usize) -> Self {
+        let ViewerIdentity {
+            user,
+            organizations,
+            teams,
+        } = identity;
+
+        Self {
+            viewer: Viewer {
+                database_id: user.id,
+                login: user.login,
+                avatar_url: user.avatar_url,
+                url: user.html_url,
+                organizations: Nodes::first(
+                    first,
+                    organizations
+                        .into_iter()
+                        .map(|organization| ViewerOrganization {
+                            teams: Nodes::first(
+                                first,
+                                teams
+                                    .iter()
+                                    .filter(|team| team.organization.id == organization.id)
+                                    .map(|team| ViewerTeam {
+                                        database_id: team.id,
+                                        name: team.name.clone(),
+                                        slug: team.slug.clone(),
+                                    })
+                                    .collect(),
+                            ),
+                            database_id: organization.id,
+                            login: organization.login,
+                        })
+                        .collect(),
+                ),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "fakehub")]
+impl<T> Nodes<T> {
+    /// The first page of a connection.
+    fn first(first: usize, mut nodes: Vec<T>) -> Self {
+        let has_next_page = nodes.len() > first;
+
+        nodes.truncate(first);
+
+        Self {
+            page_info: PageInfo { has_next_page },
+            nodes,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "fakehub"))]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        fakehub::{Fakehub, Org, Team},
+        testing::{add_test_user, CLIENT_ID, CLIENT_SECRET, ORG, ORG_ID, TEAM_ID, USER, USER_ID},
+        GithubApi,
+    };
+
+    #[tokio::test]
+    async fn graphql_viewer_identity() {
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+        add_test_user(&fakehub).await;
+        for (org_id, login) in [(ORG_ID, ORG), (ORG_ID + 1, "other-org")] {
+            fakehub
+                .add_org(
+                    org_id,
+                    Org {
+                        login: login.to_string(),
+                    },
+                )
+                .await;
+            fakehub.add_org_member(org_id, USER_ID).await.unwrap();
+        }
+        fakehub
+            .add_team(
+                TEAM_ID,
+                Team {
+                    org_id: ORG_ID,
+                    name: "Admins".to_string(),
+                    slug: "admins".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+        fakehub.add_team_member(TEAM_ID, USER_ID).await.unwrap();
+        // a team in the same org the user isn't on
+        fakehub
+            .add_team(
+                TEAM_ID + 1,
+                Team {
+                    org_id: ORG_ID,
+                    name: "Contractors".to_string(),
+                    slug: "contractors".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+
+        let code = fakehub.get_code(USER_ID).await.unwrap();
+        let token = github_client.get_access_token(&code).await.unwrap();
+        let identity = github_client
+            .viewer_identity(&token.access_token)
+            .await
+            .unwrap();
+
+        // one query answers what takes three REST calls
+        assert_eq!(USER_ID, identity.user.id);
+        assert_eq!(USER, identity.user.login);
+        assert_eq!(
+            github_client
+                .list_user_orgs(&token.access_token)
+                .await
+                .unwrap(),
+            identity.organizations
+        );
+        assert_eq!(
+            github_client
+                .list_user_teams(&token.access_token)
+                .await
+                .unwrap(),
+            identity.teams
+        );
+        assert_eq!(2, identity.organizations.len());
+        assert_eq!(1, identity.teams.len());
+        assert_eq!(ORG, identity.teams[0].organization.login);
+
+        let in_memory = fakehub.in_memory_client(CLIENT_ID, CLIENT_SECRET).await;
+        assert_eq!(
+            identity.teams,
+            in_memory
+                .viewer_identity(&token.access_token)
+                .await
+                .unwrap()
+                .teams
+        );
+
+        match github_client
+            .graphql::<_, serde_json::Value>(
+                &token.access_token,
+                "query { viewer { login } }",
+                &json!({}),
+            )
+            .await
+        {
+            Err(crate::Error::GraphQL(errors)) => {
+                assert_eq!(Some("FAKEHUB_UNSUPPORTED"), errors[0].kind.as_deref())
+            }
+            result => panic!("expected a GraphQL error, got {:?}", result),
+        }
+        assert!(matches!(
+            github_client.viewer_identity("not a token").await,
+            Err(crate::Error::Http(Some(401), _))
+        ));
+
+        // more organizations than fit in a page are refused, not cut short
+        for org_id in ORG_ID + 2..ORG_ID + 101 {
+            fakehub
+                .add_org(
+                    org_id,
+                    Org {
+                        login: format!("org-{}", org_id),
+                    },
+                )
+                .await;
+            fakehub.add_org_member(org_id, USER_ID).await.unwrap();
+        }
+        assert!(matches!(
+            github_client.viewer_identity(&token.access_token).await,
+            Err(crate::Error::Incomplete(_))
+        ));
+
+        fakehub.shutdown().await;
+    }
+}
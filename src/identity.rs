@@ -0,0 +1,268 @@
+//! Linking Github users to your own records by their numeric id, which
+//! never changes, rather than their login, which they can change at
+//! will and someone else can then take.
+//!
+//! Each time a user logs in, [`reconcile`] compares what Github says
+//! about them with what an [`IdentityRepository`] remembers, records the
+//! difference and reports it.
+//!
+//! ```no_run
+//! # use ghoauth::{identity::{self, Link, MemoryIdentityRepository}, UserDetailResponse};
+//! # async fn login(user: UserDetailResponse) -> Result<(), ghoauth::Error> {
+//! let repository = MemoryIdentityRepository::new();
+//! let reconciliation = identity::reconcile(&repository, &user).await?;
+//!
+//! match reconciliation.link {
+//!     Link::New => println!("welcome, {}", user.login),
+//!     Link::Returning => println!("welcome back, {}", user.login),
+//!     Link::Renamed { previous_login } => println!("{} is now {}", previous_login, user.login),
+//!     Link::LoginReassigned { previous_holder } => {
+//!         println!("{} is not user {}", user.login, previous_holder.id)
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{collections::HashMap, sync::Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, UserDetailResponse};
+
+/// What is remembered about a Github user between logins.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Identity {
+    /// The Github user id, which is what identifies them.
+    pub id: i64,
+    /// Their login as of when they were last seen.
+    pub login: String,
+    /// Logins they've been seen with before, oldest first.
+    pub previous_logins: Vec<String>,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+/// How a user who just logged in relates to the identities on record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Link {
+    /// Nobody with this id or login has been seen before.
+    New,
+    /// Seen before, under the same login.
+    Returning,
+    /// Seen before under another login.
+    Renamed { previous_login: String },
+    /// The login was last seen on a different user id. The user that
+    /// held it has renamed themselves, and this is someone else, who
+    /// must not inherit anything that belonged to them.
+    LoginReassigned { previous_holder: Identity },
+}
+
+/// The outcome of [`reconcile`]: the identity as it is now recorded, and
+/// how the user relates to what was recorded before.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reconciliation {
+    pub identity: Identity,
+    pub link: Link,
+}
+
+/// Somewhere to keep identities. Implement this over your own user
+/// table to link Github users to your accounts.
+#[async_trait]
+pub trait IdentityRepository: Send + Sync + 'static {
+    async fn find_by_id(&self, id: i64) -> Result<Option<Identity>, Error>;
+    /// The identity most recently seen with the login, if any. More than
+    /// one identity may have held it over time. Logins are matched
+    /// without regard to case, as Github matches them.
+    async fn find_by_login(&self, login: &str) -> Result<Option<Identity>, Error>;
+    async fn save(&self, identity: &Identity) -> Result<(), Error>;
+}
+
+/// Compare a user fresh from Github against the repository, and record
+/// them as seen now.
+pub async fn reconcile(
+    repository: &dyn IdentityRepository,
+    user: &UserDetailResponse,
+) -> Result<Reconciliation, Error> {
+    let now = Utc::now();
+    let known = repository.find_by_id(user.id).await?;
+    let previous_holder = repository
+        .find_by_login(&user.login)
+        .await?
+        .filter(|holder| holder.id != user.id);
+
+    let (identity, link) = match known {
+        Some(mut identity) => {
+            // a change of case alone is the same login, on Github
+            let link = if identity.login.eq_ignore_ascii_case(&user.login) {
+                identity.login = user.login.clone();
+
+                Link::Returning
+            } else {
+                let previous_login = std::mem::replace(&mut identity.login, user.login.clone());
+
+                identity.previous_logins.push(previous_login.clone());
+
+                Link::Renamed { previous_login }
+            };
+
+            identity.last_seen_at = now;
+
+            (identity, link)
+        }
+        None => (
+            Identity {
+                id: user.id,
+                login: user.login.clone(),
+                previous_logins: Vec::new(),
+                first_seen_at: now,
+                last_seen_at: now,
+            },
+            Link::New,
+        ),
+    };
+
+    // a login changing hands matters more than how this user came by it
+    let link = match previous_holder {
+        Some(previous_holder) => Link::LoginReassigned { previous_holder },
+        None => link,
+    };
+
+    repository.save(&identity).await?;
+
+    Ok(Reconciliation { identity, link })
+}
+
+/// Keeps identities in memory, so they are lost when the process exits.
+#[derive(Debug, Default)]
+pub struct MemoryIdentityRepository {
+    identities: Mutex<HashMap<i64, Identity>>,
+}
+
+impl MemoryIdentityRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl IdentityRepository for MemoryIdentityRepository {
+    async fn find_by_id(&self, id: i64) -> Result<Option<Identity>, Error> {
+        Ok(self.identities.lock().unwrap().get(&id).cloned())
+    }
+
+    async fn find_by_login(&self, login: &str) -> Result<Option<Identity>, Error> {
+        Ok(self
+            .identities
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|identity| identity.login.eq_ignore_ascii_case(login))
+            .max_by_key(|identity| identity.last_seen_at)
+            .cloned())
+    }
+
+    async fn save(&self, identity: &Identity) -> Result<(), Error> {
+        self.identities
+            .lock()
+            .unwrap()
+            .insert(identity.id, identity.clone());
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "fakehub"))]
+mod tests {
+    use crate::{
+        fakehub::{Fakehub, User},
+        testing::{user_named, CLIENT_ID, CLIENT_SECRET, USER, USER_ID},
+    };
+
+    use super::{reconcile, IdentityRepository, Link, MemoryIdentityRepository};
+
+    #[tokio::test]
+    async fn identity_linking() {
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+        let user = || User {
+            html_url: format!("https://github.com/{}", USER),
+            ..user_named(USER)
+        };
+        fakehub.add_user(USER_ID, user()).await;
+        let repository = MemoryIdentityRepository::new();
+        let log_in = |user_id| {
+            let github_client = &github_client;
+            let fakehub = &fakehub;
+            let repository = &repository;
+
+            async move {
+                let code = fakehub.get_code(user_id).await.unwrap();
+                let token = github_client.get_access_token(&code).await.unwrap();
+                let user = github_client
+                    .get_user_detail(&token.access_token)
+                    .await
+                    .unwrap();
+
+                reconcile(repository, &user).await.unwrap()
+            }
+        };
+
+        assert_eq!(Link::New, log_in(USER_ID).await.link);
+        assert_eq!(Link::Returning, log_in(USER_ID).await.link);
+
+        // the user takes a new login, and someone else takes their old one
+        fakehub.rename_user(USER_ID, "user-renamed").await.unwrap();
+        fakehub.add_user(USER_ID + 1, user()).await;
+        assert!(fakehub.rename_user(USER_ID, USER).await.is_err());
+
+        match log_in(USER_ID + 1).await.link {
+            Link::LoginReassigned { previous_holder } => assert_eq!(USER_ID, previous_holder.id),
+            link => panic!("expected the login to be reassigned, got {:?}", link),
+        }
+        assert_eq!(Link::Returning, log_in(USER_ID + 1).await.link);
+
+        // logins differing only in case are the same login
+        fakehub
+            .rename_user(USER_ID + 1, &USER.to_uppercase())
+            .await
+            .unwrap();
+        let reconciliation = log_in(USER_ID + 1).await;
+        assert_eq!(Link::Returning, reconciliation.link);
+        assert_eq!(USER.to_uppercase(), reconciliation.identity.login);
+        assert!(reconciliation.identity.previous_logins.is_empty());
+        assert_eq!(
+            Some(USER_ID + 1),
+            repository
+                .find_by_login(USER)
+                .await
+                .unwrap()
+                .map(|identity| identity.id)
+        );
+
+        let reconciliation = log_in(USER_ID).await;
+        assert_eq!(
+            Link::Renamed {
+                previous_login: USER.to_string()
+            },
+            reconciliation.link
+        );
+        assert_eq!("user-renamed", reconciliation.identity.login);
+        assert_eq!(
+            vec![USER.to_string()],
+            reconciliation.identity.previous_logins
+        );
+        assert_eq!(
+            "https://github.com/user-renamed",
+            github_client
+                .get_user_detail_public("user-renamed")
+                .await
+                .unwrap()
+                .html_url
+        );
+
+        fakehub.shutdown().await;
+    }
+}
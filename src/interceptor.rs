@@ -0,0 +1,488 @@
+//! Hooks around every request [`GithubClient`](crate::GithubClient)
+//! makes, for adding headers, auditing identity lookups, rewriting hosts
+//! for egress proxies and the like.
+//!
+//! Interceptors see each request's method, URL and headers, but never
+//! its body or the values of headers that carry secrets. Nor do they see
+//! the tokens in the answers of token endpoints. They run in the order
+//! they were added, before the request is sent and again once its
+//! response has arrived.
+//!
+//! ```no_run
+//! # use async_trait::async_trait;
+//! # use ghoauth::{interceptor::{InterceptedRequest, Interceptor}, GithubClient};
+//! struct Audit;
+//!
+//! #[async_trait]
+//! impl Interceptor for Audit {
+//!     async fn before(
+//!         &self,
+//!         request: &mut InterceptedRequest,
+//!     ) -> Result<Option<ghoauth::interceptor::InterceptedResponse>, ghoauth::Error> {
+//!         println!("{} {}", request.method(), request.url());
+//!         Ok(None)
+//!     }
+//! }
+//!
+//! # fn client() -> Result<GithubClient, ghoauth::Error> {
+//! let github_client = GithubClient::new("client id", "client secret")?.with_interceptor(Audit);
+//! # Ok(github_client)
+//! # }
+//! ```
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION},
+    Method, Request, Response, StatusCode, Url,
+};
+use serde::Serialize;
+
+use crate::{error::Error, transport::Transport};
+
+/// Headers whose values interceptors don't get to see.
+const SENSITIVE_HEADERS: [HeaderName; 3] = [AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION];
+
+/// Fields of a JSON answer that carry tokens, eg. from
+/// `/login/oauth/access_token`, whose values interceptors don't get to
+/// see either.
+const SENSITIVE_FIELDS: [&str; 4] = ["access_token", "refresh_token", "id_token", "token"];
+
+/// How many times a request is sent at most, retries included.
+pub const MAX_ATTEMPTS: u32 = 5;
+
+/// Hooks a [`GithubClient`](crate::GithubClient) runs around each
+/// request. Both do nothing by default.
+#[async_trait]
+pub trait Interceptor: Send + Sync + 'static {
+    /// Called before a request is sent, and again before each retry.
+    /// Returning a response skips sending the request, and the
+    /// interceptors after this one.
+    async fn before(
+        &self,
+        _request: &mut InterceptedRequest,
+    ) -> Result<Option<InterceptedResponse>, Error> {
+        Ok(None)
+    }
+
+    /// Called once a response has arrived, or been made up by
+    /// [`Interceptor::before`].
+    async fn after(
+        &self,
+        _request: &InterceptedRequest,
+        _response: &mut InterceptedResponse,
+    ) -> Result<Outcome, Error> {
+        Ok(Outcome::Continue)
+    }
+}
+
+/// What to do with a response once an interceptor has seen it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Carry on to the next interceptor, and then the caller.
+    Continue,
+    /// Send the request again, starting over from the first
+    /// interceptor's [`Interceptor::before`]. A request that would be
+    /// sent more than [`MAX_ATTEMPTS`] times fails instead.
+    Retry,
+}
+
+/// A request on its way to Github.
+#[derive(Debug)]
+pub struct InterceptedRequest {
+    inner: Request,
+    attempt: u32,
+}
+
+impl InterceptedRequest {
+    pub fn method(&self) -> &Method {
+        self.inner.method()
+    }
+
+    pub fn url(&self) -> &Url {
+        self.inner.url()
+    }
+
+    /// Change where the request goes, eg. to send it through a proxy.
+    pub fn url_mut(&mut self) -> &mut Url {
+        self.inner.url_mut()
+    }
+
+    /// The request's headers, with the values of any that carry secrets
+    /// replaced by `REDACTED`.
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = self.inner.headers().clone();
+
+        for (name, value) in headers.iter_mut() {
+            if SENSITIVE_HEADERS.contains(name) {
+                *value = HeaderValue::from_static("REDACTED");
+            }
+        }
+
+        headers
+    }
+
+    /// Add a header, replacing any already set with the same name.
+    pub fn insert_header(&mut self, name: HeaderName, value: HeaderValue) {
+        self.inner.headers_mut().insert(name, value);
+    }
+
+    pub fn remove_header(&mut self, name: &HeaderName) {
+        self.inner.headers_mut().remove(name);
+    }
+
+    /// Which attempt at sending the request this is, counting from 1.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+}
+
+/// A response from Github, or one an interceptor made up in its place.
+/// The values of any tokens in a JSON body from Github are replaced by
+/// `REDACTED`, and restored once the interceptors are done, unless one
+/// of them replaced the body.
+#[derive(Clone, Debug)]
+pub struct InterceptedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl InterceptedResponse {
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// A response with a JSON body, as Github would send it.
+    pub fn json<T: Serialize>(status: StatusCode, body: &T) -> Result<Self, Error> {
+        let mut response = Self::new(status);
+
+        response.headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        response.body =
+            serde_json::to_vec(body).map_err(|error| Error::Interceptor(error.to_string()))?;
+
+        Ok(response)
+    }
+}
+
+impl From<InterceptedResponse> for Response {
+    fn from(value: InterceptedResponse) -> Self {
+        let mut response = http::Response::new(value.body);
+
+        *response.status_mut() = value.status;
+        *response.headers_mut() = value.headers;
+
+        response.into()
+    }
+}
+
+/// Send a request through the interceptors and then the transport.
+/// Without any interceptors, the request is sent as-is and the response
+/// left unbuffered.
+pub(crate) async fn execute(
+    transport: &dyn Transport,
+    interceptors: &[Arc<dyn Interceptor>],
+    request: Request,
+) -> Result<Response, Error> {
+    if interceptors.is_empty() {
+        return transport.send(request).await;
+    }
+
+    let mut request = InterceptedRequest {
+        inner: request,
+        attempt: 1,
+    };
+
+    'attempts: loop {
+        let mut response = None;
+
+        for interceptor in interceptors.iter() {
+            response = interceptor.before(&mut request).await?;
+            if response.is_some() {
+                break;
+            }
+        }
+
+        let mut unredacted = None;
+        let mut response = match response {
+            Some(response) => response,
+            None => {
+                let attempt = request.inner.try_clone().ok_or_else(|| {
+                    Error::Interceptor("request body cannot be replayed".to_owned())
+                })?;
+                let response = transport.send(attempt).await?;
+
+                let mut response = InterceptedResponse {
+                    status: response.status(),
+                    headers: response.headers().clone(),
+                    body: response.bytes().await?.to_vec(),
+                };
+
+                if let Some(redacted) = redact(&response.body) {
+                    unredacted = Some((
+                        std::mem::replace(&mut response.body, redacted.clone()),
+                        redacted,
+                    ));
+                }
+
+                response
+            }
+        };
+
+        for interceptor in interceptors.iter() {
+            if interceptor.after(&request, &mut response).await? == Outcome::Retry {
+                if request.attempt >= MAX_ATTEMPTS {
+                    return Err(Error::Interceptor(format!(
+                        "gave up after {} attempts",
+                        MAX_ATTEMPTS
+                    )));
+                }
+
+                request.attempt += 1;
+                continue 'attempts;
+            }
+        }
+
+        if let Some((body, redacted)) = unredacted {
+            if response.body == redacted {
+                response.body = body;
+            }
+        }
+
+        return Ok(response.into());
+    }
+}
+
+/// A copy of a JSON body with the values of any fields that carry
+/// tokens replaced by `REDACTED`, if it has any.
+fn redact(body: &[u8]) -> Option<Vec<u8>> {
+    let mut value = serde_json::from_slice::<serde_json::Value>(body).ok()?;
+    let fields = value.as_object_mut()?;
+    let mut redacted = false;
+
+    for field in SENSITIVE_FIELDS {
+        if let Some(value) = fields.get_mut(field) {
+            *value = serde_json::Value::from("REDACTED");
+            redacted = true;
+        }
+    }
+
+    if !redacted {
+        return None;
+    }
+
+    serde_json::to_vec(&value).ok()
+}
+
+#[cfg(all(test, feature = "fakehub"))]
+mod tests {
+    use std::net::SocketAddr;
+
+    use reqwest::{
+        header::{HeaderName, HeaderValue},
+        StatusCode,
+    };
+
+    use crate::{
+        fakehub::Fakehub,
+        testing::{add_test_user, Audit, CLIENT_ID, CLIENT_SECRET, USER, USER_ID},
+        GithubClient, UserDetailResponse,
+    };
+
+    use super::{InterceptedRequest, InterceptedResponse, Interceptor, Outcome};
+
+    #[tokio::test]
+    async fn request_interceptors() {
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+        add_test_user(&fakehub).await;
+
+        // the api host only resolves by way of the egress interceptor
+        let audit = Audit::default();
+        let github_client = GithubClient::new_with_urls(
+            CLIENT_ID,
+            CLIENT_SECRET,
+            &fakehub.github_dot_com_url(),
+            "http://api.github.invalid",
+        )
+        .unwrap()
+        .with_interceptor(Egress(*fakehub.api_dot_github_dot_com_socket()))
+        .with_interceptor(audit.clone())
+        .with_interceptor(CannedProfiles)
+        .with_interceptor(FlakyFirstAttempt)
+        .with_interceptor(RetryUnavailable);
+
+        let code = fakehub.get_code(USER_ID).await.unwrap();
+        let token = github_client.get_access_token(&code).await.unwrap();
+        let user_detail = github_client
+            .get_user_detail(&token.access_token)
+            .await
+            .unwrap();
+        assert_eq!(USER, user_detail.login);
+
+        // answered without ever reaching Fakehub, which has no octocat
+        let octocat = github_client
+            .get_user_detail_public("octocat")
+            .await
+            .unwrap();
+        assert_eq!(583231, octocat.id);
+
+        fakehub.shutdown().await;
+
+        let audit = audit.0.lock().unwrap();
+        let user_lookups = audit
+            .iter()
+            .filter(|(path, _, _, _)| path == "/user")
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![503, 200],
+            user_lookups
+                .iter()
+                .map(|(_, _, status, _)| *status)
+                .collect::<Vec<_>>()
+        );
+        for (_, headers, _, _) in user_lookups {
+            assert_eq!("REDACTED", headers["authorization"]);
+            assert_eq!("fakehub", headers["x-egress-proxy"]);
+        }
+
+        // interceptors never see the token, though the caller does
+        let (_, _, _, body) = audit
+            .iter()
+            .find(|(path, _, status, _)| path == "/login/oauth/access_token" && *status == 200)
+            .unwrap();
+        let body = String::from_utf8_lossy(body);
+        assert!(body.contains("REDACTED"));
+        assert!(!body.contains(&token.access_token));
+    }
+
+    #[tokio::test]
+    async fn interceptor_retries_are_bounded() {
+        let github_client = GithubClient::new_with_urls(
+            CLIENT_ID,
+            CLIENT_SECRET,
+            "http://github.invalid",
+            "http://api.github.invalid",
+        )
+        .unwrap()
+        .with_interceptor(RetryForever);
+
+        let result = github_client.get_user_detail_public("octocat").await;
+        assert!(matches!(result, Err(crate::Error::Interceptor(_))));
+    }
+
+    /// Sends api requests to Fakehub, as an egress proxy would.
+    struct Egress(SocketAddr);
+
+    #[async_trait::async_trait]
+    impl Interceptor for Egress {
+        async fn before(
+            &self,
+            request: &mut InterceptedRequest,
+        ) -> Result<Option<InterceptedResponse>, crate::Error> {
+            if request.url().host_str() == Some("api.github.invalid") {
+                let url = request.url_mut();
+                url.set_ip_host(self.0.ip()).unwrap();
+                url.set_port(Some(self.0.port())).unwrap();
+                request.insert_header(
+                    HeaderName::from_static("x-egress-proxy"),
+                    HeaderValue::from_static("fakehub"),
+                );
+            }
+
+            Ok(None)
+        }
+    }
+
+    /// Answers public profile lookups for octocat itself.
+    struct CannedProfiles;
+
+    #[async_trait::async_trait]
+    impl Interceptor for CannedProfiles {
+        async fn before(
+            &self,
+            request: &mut InterceptedRequest,
+        ) -> Result<Option<InterceptedResponse>, crate::Error> {
+            if request.url().path() != "/users/octocat" {
+                return Ok(None);
+            }
+
+            Ok(Some(InterceptedResponse::json(
+                StatusCode::OK,
+                &UserDetailResponse {
+                    id: 583231,
+                    login: "octocat".to_string(),
+                    avatar_url: "https://github.com/images/error/octocat_happy.gif".to_string(),
+                    html_url: "https://github.com/octocat".to_string(),
+                },
+            )?))
+        }
+    }
+
+    /// Fails every first attempt at a request.
+    struct FlakyFirstAttempt;
+
+    #[async_trait::async_trait]
+    impl Interceptor for FlakyFirstAttempt {
+        async fn before(
+            &self,
+            request: &mut InterceptedRequest,
+        ) -> Result<Option<InterceptedResponse>, crate::Error> {
+            match request.attempt() {
+                1 => Ok(Some(InterceptedResponse::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                ))),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    /// Retries unavailable responses, a couple of times at most.
+    struct RetryUnavailable;
+
+    #[async_trait::async_trait]
+    impl Interceptor for RetryUnavailable {
+        async fn after(
+            &self,
+            request: &InterceptedRequest,
+            response: &mut InterceptedResponse,
+        ) -> Result<Outcome, crate::Error> {
+            if response.status == StatusCode::SERVICE_UNAVAILABLE && request.attempt() < 3 {
+                Ok(Outcome::Retry)
+            } else {
+                Ok(Outcome::Continue)
+            }
+        }
+    }
+
+    /// Answers every request as unavailable, and retries it, forever.
+    struct RetryForever;
+
+    #[async_trait::async_trait]
+    impl Interceptor for RetryForever {
+        async fn before(
+            &self,
+            _request: &mut InterceptedRequest,
+        ) -> Result<Option<InterceptedResponse>, crate::Error> {
+            Ok(Some(InterceptedResponse::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+            )))
+        }
+
+        async fn after(
+            &self,
+            _request: &InterceptedRequest,
+            _response: &mut InterceptedResponse,
+        ) -> Result<Outcome, crate::Error> {
+            Ok(Outcome::Retry)
+        }
+    }
+}
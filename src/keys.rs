@@ -0,0 +1,618 @@
+//! Users' public SSH and GPG keys, as Github publishes them, for
+//! provisioning access to hosts from Github identities.
+//!
+//! ```no_run
+//! # use ghoauth::{keys, GithubClient};
+//! # async fn provision(github_client: GithubClient) -> Result<(), ghoauth::Error> {
+//! let keys = github_client.list_ssh_keys("octocat").await?;
+//!
+//! std::fs::write(
+//!     "/home/octocat/.ssh/authorized_keys",
+//!     keys::authorized_keys(&keys, "octocat@github"),
+//! )
+//! .unwrap();
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{fmt, str::FromStr};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+
+/// A public key in the OpenSSH format, eg. `ssh-ed25519 AAAA… comment`.
+///
+/// Parsing checks that the key data is base64 and names the same
+/// algorithm as the key does, which catches keys mangled in transit,
+/// but not whether the key itself is sound.
+#[derive(Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SshPublicKey {
+    algorithm: String,
+    blob: Vec<u8>,
+    comment: Option<String>,
+}
+
+impl SshPublicKey {
+    /// The key type, eg. `ssh-ed25519` or `ecdsa-sha2-nistp256`.
+    pub fn algorithm(&self) -> &str {
+        &self.algorithm
+    }
+
+    /// The key data, in the SSH wire format.
+    pub fn blob(&self) -> &[u8] {
+        &self.blob
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /// The same key with a different comment. Github keeps no comments,
+    /// so this is where to say whose key it is.
+    pub fn with_comment(self, comment: &str) -> Self {
+        Self {
+            comment: Some(comment.to_owned()).filter(|comment| !comment.is_empty()),
+            ..self
+        }
+    }
+
+    /// The SHA256 fingerprint, as `ssh-keygen -l` shows it.
+    pub fn fingerprint(&self) -> String {
+        format!(
+            "SHA256:{}",
+            base64::engine::general_purpose::STANDARD_NO_PAD.encode(Sha256::digest(&self.blob))
+        )
+    }
+}
+
+impl FromStr for SshPublicKey {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        let malformed = || Error::MalformedKey(format!("{} is not an SSH public key", value));
+        let mut parts = value.trim().splitn(3, char::is_whitespace);
+        let algorithm = parts.next().filter(|part| !part.is_empty());
+        let blob = parts.next().map(|blob| STANDARD.decode(blob));
+        let comment = parts
+            .next()
+            .map(str::trim)
+            .filter(|comment| !comment.is_empty());
+
+        let (algorithm, blob) = match (algorithm, blob) {
+            (Some(algorithm), Some(Ok(blob))) => (algorithm, blob),
+            _ => return Err(malformed()),
+        };
+
+        // the blob starts with the algorithm again, as a length prefixed
+        // string
+        let named = blob
+            .get(..4)
+            .map(|length| u32::from_be_bytes(length.try_into().unwrap()) as usize)
+            .and_then(|length| blob.get(4..4 + length));
+
+        if named != Some(algorithm.as_bytes()) {
+            return Err(malformed());
+        }
+
+        Ok(Self {
+            algorithm: algorithm.to_owned(),
+            blob,
+            comment: comment.map(ToOwned::to_owned),
+        })
+    }
+}
+
+impl TryFrom<String> for SshPublicKey {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Error> {
+        value.parse()
+    }
+}
+
+impl From<SshPublicKey> for String {
+    fn from(value: SshPublicKey) -> Self {
+        value.to_string()
+    }
+}
+
+impl fmt::Display for SshPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.algorithm, STANDARD.encode(&self.blob))?;
+
+        match &self.comment {
+            Some(comment) => write!(f, " {}", comment),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for SshPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SshPublicKey({} {})", self.algorithm, self.fingerprint())
+    }
+}
+
+/// An SSH key a user authenticates with, from `/users/{username}/keys`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SshKey {
+    pub id: i64,
+    pub key: SshPublicKey,
+}
+
+impl SshKey {
+    /// The key as a line of an `authorized_keys` file, without the
+    /// trailing newline.
+    pub fn authorized_keys_line(&self, comment: &str) -> String {
+        self.key.clone().with_comment(comment).to_string()
+    }
+}
+
+/// An `authorized_keys` file granting each of the keys, each line
+/// commented with eg. whose keys they are.
+pub fn authorized_keys(keys: &[SshKey], comment: &str) -> String {
+    keys.iter()
+        .map(|key| key.authorized_keys_line(comment) + "\n")
+        .collect()
+}
+
+/// An SSH key a user signs commits with, from
+/// `/users/{username}/ssh_signing_keys`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SshSigningKey {
+    pub id: i64,
+    pub key: SshPublicKey,
+    pub title: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SshSigningKey {
+    /// The key as a line of an `allowed_signers` file, which `git` and
+    /// `ssh-keygen -Y verify` check signatures against, trusting it to
+    /// sign commits as the principal, usually an email address.
+    pub fn allowed_signers_line(&self, principal: &str) -> String {
+        format!(
+            "{} namespaces=\"git\" {}",
+            principal,
+            self.key.clone().with_comment("")
+        )
+    }
+}
+
+/// A GPG key, from `/users/{username}/gpg_keys`. Subkeys come without
+/// an armored key of their own.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct GpgKey {
+    pub id: i64,
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The id of the primary key, for a subkey.
+    #[serde(default)]
+    pub primary_key_id: Option<i64>,
+    /// The long key id, in hex.
+    pub key_id: String,
+    /// The base64 encoded public key packet.
+    pub public_key: String,
+    #[serde(default)]
+    pub emails: Vec<GpgKeyEmail>,
+    #[serde(default)]
+    pub subkeys: Vec<GpgKey>,
+    pub can_sign: bool,
+    pub can_encrypt_comms: bool,
+    pub can_encrypt_storage: bool,
+    pub can_certify: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub revoked: bool,
+    /// The key as it was uploaded, ASCII armored.
+    #[serde(default)]
+    pub raw_key: Option<String>,
+}
+
+impl GpgKey {
+    /// The full fingerprint of the key, in hex, which Github doesn't
+    /// report but is what keyrings are best trusted by. Computed from
+    /// the public key packet, so it's only as good as that is.
+    pub fn fingerprint(&self) -> Result<String, Error> {
+        let packet = STANDARD
+            .decode(&self.public_key)
+            .map_err(|_| Error::MalformedKey(format!("{} is not base64", self.public_key)))?;
+        let (packet, _) = next_packet(&packet)?;
+
+        match packet.tag {
+            PUBLIC_KEY_TAG | PUBLIC_SUBKEY_TAG => fingerprint(packet.body),
+            _ => Err(Error::MalformedKey("not a public key packet".to_owned())),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at <= Utc::now())
+            .unwrap_or(false)
+    }
+
+    /// Whether the key may be trusted at all, being neither revoked nor
+    /// expired.
+    pub fn is_usable(&self) -> bool {
+        !self.revoked && !self.is_expired()
+    }
+}
+
+/// An email address in one of a GPG key's user ids.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct GpgKeyEmail {
+    pub email: String,
+    /// Whether the address is verified on the user's Github account.
+    pub verified: bool,
+}
+
+/// What Fakehub makes of an armored key when a user uploads one: the
+/// primary key and subkeys, and the user ids.
+#[cfg(feature = "fakehub")]
+#[derive(Debug)]
+pub(crate) struct OpenPgpCertificate {
+    pub keys: Vec<OpenPgpKey>,
+    pub user_ids: Vec<String>,
+}
+
+#[cfg(feature = "fakehub")]
+#[derive(Debug)]
+pub(crate) struct OpenPgpKey {
+    /// The whole packet, header and all.
+    pub packet: Vec<u8>,
+    pub fingerprint: String,
+    pub created_at: DateTime<Utc>,
+    pub algorithm: u8,
+}
+
+/// OpenPGP packet tags, from RFC 4880.
+const PUBLIC_KEY_TAG: u8 = 6;
+#[cfg(feature = "fakehub")]
+const USER_ID_TAG: u8 = 13;
+const PUBLIC_SUBKEY_TAG: u8 = 14;
+
+#[cfg(feature = "fakehub")]
+impl OpenPgpCertificate {
+    pub(crate) fn from_armored(armored: &str) -> Result<Self, Error> {
+        let mut certificate = Self {
+            keys: Vec::new(),
+            user_ids: Vec::new(),
+        };
+        let dearmored = dearmor(armored)?;
+        let mut data = dearmored.as_slice();
+
+        while !data.is_empty() {
+            let (Packet { tag, packet, body }, rest) = next_packet(data)?;
+
+            match tag {
+                PUBLIC_KEY_TAG | PUBLIC_SUBKEY_TAG => {
+                    if (tag == PUBLIC_KEY_TAG) != certificate.keys.is_empty() {
+                        return Err(Error::MalformedKey(
+                            "expected one primary key, before any subkeys".to_owned(),
+                        ));
+                    }
+
+                    certificate.keys.push(OpenPgpKey {
+                        packet: packet.to_owned(),
+                        fingerprint: fingerprint(body)?,
+                        created_at: DateTime::from_timestamp(
+                            u32::from_be_bytes(body[1..5].try_into().unwrap()) as i64,
+                            0,
+                        )
+                        .expect("a u32 of seconds is in range"),
+                        algorithm: body[5],
+                    });
+                }
+                USER_ID_TAG => certificate
+                    .user_ids
+                    .push(String::from_utf8_lossy(body).into_owned()),
+                _ => {}
+            }
+
+            data = rest;
+        }
+
+        if certificate.keys.is_empty() {
+            return Err(Error::MalformedKey("no public key".to_owned()));
+        }
+
+        Ok(certificate)
+    }
+}
+
+#[cfg(feature = "fakehub")]
+impl OpenPgpKey {
+    /// The long key id, the last 64 bits of the fingerprint.
+    pub(crate) fn key_id(&self) -> &str {
+        &self.fingerprint[self.fingerprint.len() - 16..]
+    }
+}
+
+/// The fingerprint of the key in the body of a key packet.
+fn fingerprint(body: &[u8]) -> Result<String, Error> {
+    // only version 4 keys, which is all Github accepts, put their
+    // creation time and algorithm in the first six bytes and
+    // fingerprint with SHA1
+    if body.len() < 6 || body[0] != 4 {
+        return Err(Error::MalformedKey(
+            "only version 4 OpenPGP keys are supported".to_owned(),
+        ));
+    }
+
+    let mut hasher = Sha1::new();
+
+    hasher.update([0x99]);
+    hasher.update((body.len() as u16).to_be_bytes());
+    hasher.update(body);
+
+    Ok(hex::encode_upper(hasher.finalize()))
+}
+
+/// An OpenPGP packet, borrowed from the data it was read from.
+struct Packet<'a> {
+    tag: u8,
+    /// The whole packet, header and all.
+    #[cfg_attr(not(feature = "fakehub"), allow(dead_code))]
+    packet: &'a [u8],
+    body: &'a [u8],
+}
+
+/// The first packet in the data, and the data after it.
+fn next_packet(data: &[u8]) -> Result<(Packet<'_>, &[u8]), Error> {
+    let truncated = || Error::MalformedKey("truncated OpenPGP packet".to_owned());
+    let header = *data.first().ok_or_else(truncated)?;
+    // a big endian length of some bytes, starting at an offset
+    let length = |offset: usize, count: usize| {
+        data.get(offset..offset + count)
+            .map(|bytes| {
+                bytes
+                    .iter()
+                    .fold(0usize, |length, byte| length << 8 | *byte as usize)
+            })
+            .ok_or_else(truncated)
+    };
+
+    if header & 0x80 == 0 {
+        return Err(Error::MalformedKey("not an OpenPGP packet".to_owned()));
+    }
+
+    let (tag, header_length, body_length) = if header & 0x40 != 0 {
+        let tag = header & 0x3f;
+
+        match length(1, 1)? {
+            first @ 0..=191 => (tag, 2, first),
+            first @ 192..=223 => (tag, 3, ((first - 192) << 8) + length(2, 1)? + 192),
+            255 => (tag, 6, length(2, 4)?),
+            _ => {
+                return Err(Error::MalformedKey(
+                    "partial OpenPGP packet lengths are not supported".to_owned(),
+                ))
+            }
+        }
+    } else {
+        let tag = (header >> 2) & 0x0f;
+
+        match header & 0x03 {
+            0 => (tag, 2, length(1, 1)?),
+            1 => (tag, 3, length(1, 2)?),
+            2 => (tag, 5, length(1, 4)?),
+            _ => {
+                return Err(Error::MalformedKey(
+                    "indeterminate OpenPGP packet lengths are not supported".to_owned(),
+                ))
+            }
+        }
+    };
+    let end = header_length + body_length;
+    let packet = data.get(..end).ok_or_else(truncated)?;
+
+    Ok((
+        Packet {
+            tag,
+            packet,
+            body: &packet[header_length..],
+        },
+        &data[end..],
+    ))
+}
+
+/// The binary data in an ASCII armored OpenPGP block. The checksum is
+/// left unchecked, as newer implementations do.
+#[cfg(feature = "fakehub")]
+fn dearmor(armored: &str) -> Result<Vec<u8>, Error> {
+    let malformed = || Error::MalformedKey("not an ASCII armored OpenPGP key".to_owned());
+    let mut lines = armored
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| !line.starts_with("-----BEGIN PGP PUBLIC KEY BLOCK-----"));
+
+    lines.next().ok_or_else(malformed)?;
+
+    // armor headers, up to a blank line
+    let mut lines = lines.skip_while(|line| !line.is_empty()).skip(1);
+    let encoded = lines
+        .by_ref()
+        .take_while(|line| !line.starts_with('=') && !line.starts_with("-----"))
+        .collect::<String>();
+
+    STANDARD.decode(encoded).map_err(|_| malformed())
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "fakehub")]
+    use reqwest::StatusCode;
+
+    #[cfg(feature = "fakehub")]
+    use crate::{
+        fakehub::Fakehub,
+        testing::{add_test_user, CLIENT_ID, CLIENT_SECRET, GPG_PUBLIC_KEY, USER, USER_ID},
+        GithubApi,
+    };
+
+    use super::SshPublicKey;
+    #[cfg(feature = "fakehub")]
+    use super::{authorized_keys, GpgKeyEmail};
+
+    // generated with ssh-keygen -t ed25519 -C user@laptop
+    const KEY: &str = "ssh-ed25519 \
+        AAAAC3NzaC1lZDI1NTE5AAAAIITSWfKD1oLwgvkGuQBvK7V3qvEc98EtlEjV8T+OZgxX user@laptop";
+
+    #[test]
+    fn parse_ssh_keys() {
+        let key: SshPublicKey = KEY.parse().unwrap();
+
+        assert_eq!("ssh-ed25519", key.algorithm());
+        assert_eq!(Some("user@laptop"), key.comment());
+        assert_eq!(
+            "SHA256:usroO/RFTrlKn2SwYTu1wE8y0UmwFNwCPoOtZNHDTDg",
+            key.fingerprint()
+        );
+        assert_eq!(KEY, key.to_string());
+
+        // the algorithm must match the one named in the key data
+        assert!(KEY
+            .replacen("ssh-ed25519", "ssh-rsa", 1)
+            .parse::<SshPublicKey>()
+            .is_err());
+        assert!("ssh-ed25519 not-base64".parse::<SshPublicKey>().is_err());
+        assert!("ssh-ed25519".parse::<SshPublicKey>().is_err());
+    }
+
+    #[cfg(feature = "fakehub")]
+    #[tokio::test]
+    async fn user_keys() {
+        const SSH_KEY: &str = "ssh-ed25519 \
+            AAAAC3NzaC1lZDI1NTE5AAAAIITSWfKD1oLwgvkGuQBvK7V3qvEc98EtlEjV8T+OZgxX";
+        const OTHER_SSH_KEY: &str = "ssh-ed25519 \
+            AAAAC3NzaC1lZDI1NTE5AAAAINrj2aEaoySigM41kVqaBgnscHlQ6+mcSx2YrJSci+RS";
+
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+        add_test_user(&fakehub).await;
+        fakehub
+            .add_email(USER_ID, "user@example.com", true)
+            .await
+            .unwrap();
+        fakehub
+            .add_ssh_key(USER_ID, &format!("{} user@laptop", SSH_KEY))
+            .await
+            .unwrap();
+        fakehub.add_ssh_key(USER_ID, OTHER_SSH_KEY).await.unwrap();
+        fakehub
+            .add_ssh_signing_key(USER_ID, "laptop", SSH_KEY)
+            .await
+            .unwrap();
+        fakehub.add_gpg_key(USER_ID, GPG_PUBLIC_KEY).await.unwrap();
+        assert!(fakehub
+            .add_ssh_key(USER_ID, "ssh-rsa not-a-key")
+            .await
+            .is_err());
+
+        // github keeps no comments, so keys come back without one
+        let ssh_keys = github_client.list_ssh_keys(USER).await.unwrap();
+        assert_eq!(
+            vec![SSH_KEY, OTHER_SSH_KEY],
+            ssh_keys
+                .iter()
+                .map(|key| key.key.to_string())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            format!("{} user@github\n{} user@github\n", SSH_KEY, OTHER_SSH_KEY),
+            authorized_keys(&ssh_keys, "user@github")
+        );
+        assert_eq!(
+            "SHA256:usroO/RFTrlKn2SwYTu1wE8y0UmwFNwCPoOtZNHDTDg",
+            ssh_keys[0].key.fingerprint()
+        );
+
+        let signing_keys = github_client.list_ssh_signing_keys(USER).await.unwrap();
+        assert_eq!(1, signing_keys.len());
+        assert_eq!("laptop", signing_keys[0].title);
+        assert_eq!(
+            format!("user@example.com namespaces=\"git\" {}", SSH_KEY),
+            signing_keys[0].allowed_signers_line("user@example.com")
+        );
+
+        let gpg_keys = github_client.list_gpg_keys(USER).await.unwrap();
+        assert_eq!(1, gpg_keys.len());
+        let gpg_key = &gpg_keys[0];
+        assert_eq!("1C784EFF48CBBAD6", gpg_key.key_id);
+        assert_eq!(
+            "D6296A60F7CA6101728232841C784EFF48CBBAD6",
+            gpg_key.fingerprint().unwrap()
+        );
+        assert_eq!(1792375695, gpg_key.created_at.timestamp());
+        assert!(gpg_key.can_sign && gpg_key.can_certify && !gpg_key.can_encrypt_comms);
+        assert!(gpg_key.is_usable());
+        assert_eq!(
+            vec![GpgKeyEmail {
+                email: "user@example.com".to_owned(),
+                verified: true,
+            }],
+            gpg_key.emails
+        );
+        assert_eq!(Some(GPG_PUBLIC_KEY), gpg_key.raw_key.as_deref());
+        assert_eq!(1, gpg_key.subkeys.len());
+        let subkey = &gpg_key.subkeys[0];
+        assert_eq!(Some(gpg_key.id), subkey.primary_key_id);
+        assert_eq!("4B1738D15F9E08C7", subkey.key_id);
+        assert_eq!(
+            "2FDB2BFBF98663BE330E7C254B1738D15F9E08C7",
+            subkey.fingerprint().unwrap()
+        );
+        assert!(!subkey.can_sign && subkey.can_encrypt_comms);
+
+        assert!(matches!(
+            github_client.list_ssh_keys("nobody").await,
+            Err(crate::Error::Http(Some(404), _))
+        ));
+        // usernames can't reach beyond their own segment of the path
+        assert!(matches!(
+            github_client
+                .list_ssh_keys(&format!("{}/ssh_signing_keys#", USER))
+                .await,
+            Err(crate::Error::Http(Some(404), _))
+        ));
+        assert!(matches!(
+            github_client.list_gpg_keys("..").await,
+            Err(crate::Error::InvalidName(_))
+        ));
+
+        // the plain text forms on github.com
+        let keys_file = reqwest::get(format!("{}/{}.keys", fakehub.github_dot_com_url(), USER))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(format!("{}\n{}\n", SSH_KEY, OTHER_SSH_KEY), keys_file);
+        let gpg_file = reqwest::get(format!("{}/{}.gpg", fakehub.github_dot_com_url(), USER))
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(GPG_PUBLIC_KEY, gpg_file);
+        assert_eq!(
+            StatusCode::NOT_FOUND,
+            reqwest::get(format!("{}/nobody.keys", fakehub.github_dot_com_url()))
+                .await
+                .unwrap()
+                .status()
+        );
+
+        let in_memory = fakehub.in_memory_client(CLIENT_ID, CLIENT_SECRET).await;
+        assert_eq!(ssh_keys, in_memory.list_ssh_keys(USER).await.unwrap());
+
+        fakehub.shutdown().await;
+    }
+}
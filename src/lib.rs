@@ -47,55 +47,105 @@
 //! # }
 //! ```
 
+#[cfg(feature = "api")]
+pub use crate::api::GithubApi;
+#[cfg(feature = "app")]
+pub use crate::app_client::{AppClaims, AppClient};
+#[cfg(feature = "pkce")]
+pub use crate::pkce::Pkce;
+#[cfg(feature = "app")]
+pub use crate::shapes::{
+    Installation, InstallationAccessToken, InstallationAccount, InstallationTokenRequest,
+    TokenRepository,
+};
 pub use crate::{
+    callback::{AuthorizationCode, AuthorizationError, AuthorizationErrorCode, Callback},
     client::GithubClient,
     error::Error,
-    shapes::{GetAccessTokenResponse, UserDetailResponse},
+    provider::{Provider, UserFields},
+    shapes::{
+        Affiliation, AuthorizationRequest, Email, GetAccessTokenResponse, Organization, Permission,
+        Repository, RepositoryOwner, RepositoryPermissions, Team, UserDetailResponse,
+    },
 };
 
+#[cfg(feature = "api")]
+mod api;
+#[cfg(feature = "app")]
+mod app_client;
+#[cfg(feature = "tower")]
+pub mod bearer;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod callback;
 mod client;
 mod error;
+pub mod graphql;
+#[cfg(feature = "identity")]
+pub mod identity;
+#[cfg(feature = "interceptors")]
+pub mod interceptor;
+#[cfg(feature = "keys")]
+pub mod keys;
+#[cfg(feature = "pkce")]
+mod pkce;
+mod provider;
 mod shapes;
+mod telemetry;
+#[cfg(feature = "tokens")]
+pub mod tokens;
+#[cfg(feature = "interceptors")]
+pub mod transport;
 
 #[cfg(feature = "fakehub")]
 pub mod fakehub;
+#[cfg(feature = "login")]
+pub mod login;
+#[cfg(feature = "native")]
+pub mod native;
+#[cfg(feature = "oidc")]
+pub mod oidc;
+pub mod policy;
+#[cfg(feature = "webhooks")]
+pub mod webhooks;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "fakehub"))]
+mod testing;
+#[cfg(all(test, feature = "fakehub"))]
 mod tests {
-    use crate::fakehub::{Fakehub, User};
-
-    const CLIENT_ID: &str = "1234567890";
-    const CLIENT_SECRET: &str = "SECRET_SQUIRREL_STUFF";
-    const USER: &str = "user";
-    const USER_ID: i64 = 1;
-    const USER_AVATAR_URL: &str = "https://github.com/";
-    const USER_HTML_URL: &str = "https://github.com/";
+    use crate::{
+        fakehub::Fakehub,
+        testing::{add_test_user, CLIENT_ID, CLIENT_SECRET, USER, USER_ID},
+    };
 
     #[tokio::test]
     async fn oauth_flow() {
-        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        // the same flow over sockets, and through the routers in memory
+        for fakehub in [
+            Fakehub::new().expect("cannot start local fakehub server"),
+            Fakehub::new_in_process().expect("cannot start in-process fakehub"),
+        ] {
+            let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+            add_test_user(&fakehub).await;
 
-        let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
-        fakehub
-            .add_user(
-                USER_ID,
-                User {
-                    login: USER.to_string(),
-                    avatar_url: USER_AVATAR_URL.to_string(),
-                    html_url: USER_HTML_URL.to_string(),
-                },
-            )
-            .await;
+            let code = fakehub.get_code(USER_ID).await.unwrap();
+            let token = github_client.get_access_token(&code).await.unwrap();
+            let user_detail = github_client
+                .get_user_detail(&token.access_token)
+                .await
+                .unwrap();
 
-        let code = fakehub.get_code(USER_ID).await.unwrap();
-        let token = github_client.get_access_token(&code).await.unwrap();
-        let user_detail = github_client
-            .get_user_detail(&token.access_token)
-            .await
-            .unwrap();
+            assert_eq!(USER, user_detail.login);
 
-        assert_eq!(USER, user_detail.login);
+            // anyone's public profile, without a token
+            let public = github_client.get_user_detail_public(USER).await.unwrap();
+            assert_eq!(user_detail.id, public.id);
+            assert!(matches!(
+                github_client.get_user_detail_public("nobody").await,
+                Err(crate::Error::Http(Some(404), _))
+            ));
 
-        fakehub.shutdown().await;
+            fakehub.shutdown().await;
+        }
     }
 }
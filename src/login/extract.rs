@@ -0,0 +1,40 @@
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+};
+
+use crate::UserDetailResponse;
+
+use super::{GithubLoginRouter, Unauthenticated};
+
+/// An axum extractor for the logged-in user. Requests without a session
+/// are turned away before the handler runs, either with a 401 or a
+/// redirect to the login route.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUser(pub UserDetailResponse);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for AuthenticatedUser
+where
+    GithubLoginRouter: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let github_login = GithubLoginRouter::from_ref(state);
+
+        match github_login.session(&parts.headers).await {
+            Ok(Some(session)) => Ok(Self(session.user)),
+            Ok(None) => Err(match github_login.config.unauthenticated {
+                Unauthenticated::Unauthorized => StatusCode::UNAUTHORIZED.into_response(),
+                Unauthenticated::Redirect => {
+                    Redirect::to(&github_login.config.login_path).into_response()
+                }
+            }),
+            Err(e) => Err(e.into_response()),
+        }
+    }
+}
@@ -0,0 +1,553 @@
+//! A drop-in "login with Github" for axum applications.
+//!
+//! [`GithubLoginRouter`] mounts `/login`, `/callback` and `/logout`,
+//! taking care of the state and PKCE parameters of the OAuth workflow,
+//! and [`AuthenticatedUser`] extracts the logged-in user in your own
+//! handlers.
+//!
+//! ```no_run
+//! # use axum::{routing::get, Router};
+//! # use ghoauth::{
+//! #     login::{AuthenticatedUser, GithubLoginRouter, MemorySessionStore},
+//! #     GithubClient,
+//! # };
+//! # fn app(github_client: GithubClient) -> Router {
+//! let github_login = GithubLoginRouter::new(github_client, MemorySessionStore::new())
+//!     .redirect_uri("https://example.com/callback");
+//!
+//! Router::new()
+//!     .route(
+//!         "/",
+//!         get(|AuthenticatedUser(user): AuthenticatedUser| async move {
+//!             format!("Hello, {}!", user.login)
+//!         }),
+//!     )
+//!     .merge(github_login.router())
+//!     .with_state(github_login)
+//! # }
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{RawQuery, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::get,
+    Router,
+};
+use thiserror::Error;
+
+use crate::{
+    pkce::random_string, AuthorizationCode, AuthorizationRequest, Callback, GithubClient, Pkce,
+};
+
+mod extract;
+mod session;
+
+pub use self::{
+    extract::AuthenticatedUser,
+    session::{MemorySessionStore, Session, SessionStore},
+};
+
+/// How long a user has to finish logging in on Github.
+const PENDING_LOGIN_LIFETIME: Duration = Duration::from_secs(10 * 60);
+/// How many logins may be waiting at once. Anyone can start one, so past
+/// this the oldest are forgotten rather than memory growing unbounded.
+const MAX_PENDING_LOGINS: usize = 10_000;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Client(#[from] crate::Error),
+    #[error("Session store failed: {0}")]
+    Store(String),
+    #[error("Login state is missing or does not match")]
+    InvalidState,
+    #[error("Callback is missing the {0} parameter")]
+    MissingParameter(&'static str),
+    #[error("{0}")]
+    MalformedCallback(String),
+    #[error("Github refused the authorization: {0}")]
+    Denied(String),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Client(_) => (StatusCode::BAD_GATEWAY, format!("{}", self)).into_response(),
+            Self::Store(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, format!("{}", self)).into_response()
+            }
+            Self::InvalidState => (StatusCode::BAD_REQUEST, format!("{}", self)).into_response(),
+            Self::MissingParameter(_) => {
+                (StatusCode::BAD_REQUEST, format!("{}", self)).into_response()
+            }
+            Self::MalformedCallback(_) => {
+                (StatusCode::BAD_REQUEST, format!("{}", self)).into_response()
+            }
+            Self::Denied(_) => (StatusCode::FORBIDDEN, format!("{}", self)).into_response(),
+        }
+    }
+}
+
+/// What [`AuthenticatedUser`] does with requests that aren't logged in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unauthenticated {
+    /// Respond with 401 Unauthorized, which suits APIs.
+    Unauthorized,
+    /// Redirect to the login route, which suits pages.
+    Redirect,
+}
+
+#[derive(Clone, Debug)]
+struct LoginConfig {
+    redirect_uri: Option<String>,
+    scopes: Vec<String>,
+    login_path: String,
+    after_login: String,
+    after_logout: String,
+    cookie_name: String,
+    secure_cookies: bool,
+    unauthenticated: Unauthenticated,
+}
+
+/// A login waiting on the user to come back from Github.
+struct PendingLogin {
+    code_verifier: String,
+    started: Instant,
+}
+
+/// Routes for logging in with Github, and the state they share with
+/// [`AuthenticatedUser`]. Make it reachable from your router state with
+/// [`axum::extract::FromRef`], or use it as the state outright.
+#[derive(Clone)]
+pub struct GithubLoginRouter {
+    github_client: GithubClient,
+    sessions: Arc<dyn SessionStore>,
+    pending: Arc<Mutex<HashMap<String, PendingLogin>>>,
+    config: LoginConfig,
+}
+
+impl GithubLoginRouter {
+    pub fn new(github_client: GithubClient, sessions: impl SessionStore) -> Self {
+        Self {
+            github_client,
+            sessions: Arc::new(sessions),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            config: LoginConfig {
+                redirect_uri: None,
+                scopes: Vec::new(),
+                login_path: "/login".to_owned(),
+                after_login: "/".to_owned(),
+                after_logout: "/".to_owned(),
+                cookie_name: "ghoauth_session".to_owned(),
+                secure_cookies: false,
+                unauthenticated: Unauthenticated::Redirect,
+            },
+        }
+    }
+
+    /// The full URL of the `/callback` route, which Github sends users
+    /// back to. Without it, Github uses the app's registered callback
+    /// URL.
+    pub fn redirect_uri(mut self, redirect_uri: &str) -> Self {
+        self.config.redirect_uri = Some(redirect_uri.to_owned());
+        self
+    }
+
+    /// The scopes to ask the user for.
+    pub fn scopes(mut self, scopes: &[&str]) -> Self {
+        self.config.scopes = scopes.iter().map(|scope| scope.to_string()).collect();
+        self
+    }
+
+    /// Where the `/login` route ends up once mounted, if the router is
+    /// nested under a prefix. Defaults to `/login`.
+    pub fn login_path(mut self, login_path: &str) -> Self {
+        self.config.login_path = login_path.to_owned();
+        self
+    }
+
+    /// Where to send users once they've logged in. Defaults to `/`.
+    pub fn after_login(mut self, after_login: &str) -> Self {
+        self.config.after_login = after_login.to_owned();
+        self
+    }
+
+    /// Where to send users once they've logged out. Defaults to `/`.
+    pub fn after_logout(mut self, after_logout: &str) -> Self {
+        self.config.after_logout = after_logout.to_owned();
+        self
+    }
+
+    /// The name of the session cookie. Defaults to `ghoauth_session`.
+    pub fn cookie_name(mut self, cookie_name: &str) -> Self {
+        self.config.cookie_name = cookie_name.to_owned();
+        self
+    }
+
+    /// Whether to mark cookies `Secure`, which should be on whenever the
+    /// site is served over https.
+    pub fn secure_cookies(mut self, secure_cookies: bool) -> Self {
+        self.config.secure_cookies = secure_cookies;
+        self
+    }
+
+    /// What to do with requests for [`AuthenticatedUser`] that aren't
+    /// logged in. Defaults to [`Unauthenticated::Redirect`].
+    pub fn unauthenticated(mut self, unauthenticated: Unauthenticated) -> Self {
+        self.config.unauthenticated = unauthenticated;
+        self
+    }
+
+    /// The `/login`, `/callback` and `/logout` routes, ready to merge or
+    /// nest into an application.
+    pub fn router<S>(&self) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        Router::new()
+            .route("/login", get(login))
+            .route("/callback", get(callback))
+            .route("/logout", get(logout).post(logout))
+            .with_state(self.clone())
+    }
+
+    /// Look up the session a request's cookie refers to.
+    pub async fn session(&self, headers: &HeaderMap) -> Result<Option<Session>, Error> {
+        match cookie_value(headers, &self.config.cookie_name) {
+            Some(session_id) => self.sessions.load(&session_id).await,
+            None => Ok(None),
+        }
+    }
+
+    fn state_cookie_name(&self) -> String {
+        format!("{}_state", self.config.cookie_name)
+    }
+
+    fn cookie(&self, name: &str, value: &str, max_age: Option<Duration>) -> HeaderValue {
+        let mut cookie = format!("{}={}; Path=/; HttpOnly; SameSite=Lax", name, value);
+
+        if let Some(max_age) = max_age {
+            cookie.push_str(&format!("; Max-Age={}", max_age.as_secs()));
+        }
+        if self.config.secure_cookies {
+            cookie.push_str("; Secure");
+        }
+
+        HeaderValue::from_str(&cookie).expect("cookie values are url-safe")
+    }
+}
+
+impl std::fmt::Debug for GithubLoginRouter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "GithubLoginRouter {{ github_client: {:?}, config: {:?} }}",
+            self.github_client, self.config,
+        )
+    }
+}
+
+// GET /login
+async fn login(State(github_login): State<GithubLoginRouter>) -> Response {
+    let state = random_string(16);
+    let pkce = Pkce::new();
+    let authorization_url =
+        github_login
+            .github_client
+            .authorization_url_with(&AuthorizationRequest {
+                redirect_uri: github_login.config.redirect_uri.clone(),
+                scopes: github_login.config.scopes.clone(),
+                state: Some(state.clone()),
+                code_challenge: Some(pkce.challenge),
+                login: None,
+            });
+
+    insert_pending(
+        &mut github_login.pending.lock().unwrap(),
+        state.clone(),
+        PendingLogin {
+            code_verifier: pkce.verifier,
+            started: Instant::now(),
+        },
+    );
+
+    (
+        [(
+            header::SET_COOKIE,
+            github_login.cookie(
+                &github_login.state_cookie_name(),
+                &state,
+                Some(PENDING_LOGIN_LIFETIME),
+            ),
+        )],
+        Redirect::to(&authorization_url),
+    )
+        .into_response()
+}
+
+// GET /callback?code=:code&state=:state
+async fn callback(
+    State(github_login): State<GithubLoginRouter>,
+    headers: HeaderMap,
+    RawQuery(query): RawQuery,
+) -> Result<Response, Error> {
+    let (code, state) = match Callback::from_query(query.as_deref().unwrap_or_default()) {
+        Ok(Callback::Code(AuthorizationCode { code, state })) => {
+            (code, state.ok_or(Error::MissingParameter("state"))?)
+        }
+        Ok(Callback::Error(error)) => return Err(Error::Denied(error.to_string())),
+        Err(error) => return Err(Error::MalformedCallback(error.to_string())),
+    };
+
+    // the state must be the one this browser was sent off with, not just
+    // any state we handed out
+    if cookie_value(&headers, &github_login.state_cookie_name()).as_deref() != Some(&state) {
+        return Err(Error::InvalidState);
+    }
+
+    let pending = github_login
+        .pending
+        .lock()
+        .unwrap()
+        .remove(&state)
+        .filter(|login| login.started.elapsed() < PENDING_LOGIN_LIFETIME)
+        .ok_or(Error::InvalidState)?;
+    let token = github_login
+        .github_client
+        .get_access_token_with_verifier(&code, &pending.code_verifier)
+        .await?;
+    let user = github_login
+        .github_client
+        .get_user_detail(&token.access_token)
+        .await?;
+    let session_id = random_string(32);
+
+    github_login
+        .sessions
+        .store(&session_id, Session { user })
+        .await?;
+
+    let mut response = Redirect::to(&github_login.config.after_login).into_response();
+    let response_headers = response.headers_mut();
+
+    response_headers.append(
+        header::SET_COOKIE,
+        github_login.cookie(&github_login.config.cookie_name, &session_id, None),
+    );
+    response_headers.append(
+        header::SET_COOKIE,
+        github_login.cookie(&github_login.state_cookie_name(), "", Some(Duration::ZERO)),
+    );
+
+    Ok(response)
+}
+
+// GET or POST /logout
+async fn logout(
+    State(github_login): State<GithubLoginRouter>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    if let Some(session_id) = cookie_value(&headers, &github_login.config.cookie_name) {
+        github_login.sessions.remove(&session_id).await?;
+    }
+
+    Ok((
+        [(
+            header::SET_COOKIE,
+            github_login.cookie(&github_login.config.cookie_name, "", Some(Duration::ZERO)),
+        )],
+        Redirect::to(&github_login.config.after_logout),
+    )
+        .into_response())
+}
+
+/// Remember a login until the user comes back from Github, forgetting
+/// expired ones, and the oldest once there are too many.
+fn insert_pending(pending: &mut HashMap<String, PendingLogin>, state: String, login: PendingLogin) {
+    pending.retain(|_, login| login.started.elapsed() < PENDING_LOGIN_LIFETIME);
+
+    while pending.len() >= MAX_PENDING_LOGINS {
+        let oldest = pending
+            .iter()
+            .min_by_key(|(_, login)| login.started)
+            .map(|(state, _)| state.clone())
+            .expect("pending logins are not empty");
+
+        pending.remove(&oldest);
+    }
+
+    pending.insert(state, login);
+}
+
+fn cookie_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "fakehub")]
+    use std::net::TcpListener;
+    use std::{collections::HashMap, time::Instant};
+
+    #[cfg(feature = "fakehub")]
+    use axum::{routing::get, Router, Server};
+    #[cfg(feature = "fakehub")]
+    use reqwest::{redirect::Policy, StatusCode};
+
+    #[cfg(feature = "fakehub")]
+    use crate::{
+        fakehub::Fakehub,
+        testing::{add_test_user, CLIENT_ID, CLIENT_SECRET, USER, USER_ID},
+        AuthorizationErrorCode, Callback,
+    };
+
+    use super::{insert_pending, PendingLogin, MAX_PENDING_LOGINS};
+    #[cfg(feature = "fakehub")]
+    use super::{AuthenticatedUser, GithubLoginRouter, MemorySessionStore};
+
+    #[test]
+    fn pending_logins_are_bounded() {
+        let mut pending = HashMap::new();
+
+        for n in 0..MAX_PENDING_LOGINS + 10 {
+            insert_pending(
+                &mut pending,
+                n.to_string(),
+                PendingLogin {
+                    code_verifier: String::new(),
+                    started: Instant::now(),
+                },
+            );
+        }
+
+        assert_eq!(MAX_PENDING_LOGINS, pending.len());
+        assert!(pending.contains_key(&(MAX_PENDING_LOGINS + 9).to_string()));
+    }
+
+    #[cfg(feature = "fakehub")]
+    #[tokio::test]
+    async fn login_router() {
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+        add_test_user(&fakehub).await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let app_url = format!("http://{}", listener.local_addr().unwrap());
+        let github_login = GithubLoginRouter::new(github_client, MemorySessionStore::new())
+            .redirect_uri(&format!("{}/callback", app_url));
+        let app = Router::new()
+            .route(
+                "/me",
+                get(|AuthenticatedUser(user): AuthenticatedUser| async move { user.login }),
+            )
+            .merge(github_login.router())
+            .with_state(github_login);
+        tokio::spawn(
+            Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        // a browser that doesn't follow redirects, so each step of the
+        // flow can be checked
+        let browser = reqwest::ClientBuilder::new()
+            .cookie_store(true)
+            .redirect(Policy::none())
+            .build()
+            .unwrap();
+        let location = |response: &reqwest::Response| {
+            response.headers()["Location"].to_str().unwrap().to_string()
+        };
+
+        let response = browser.get(format!("{}/me", app_url)).send().await.unwrap();
+        assert_eq!("/login", location(&response));
+
+        let response = browser
+            .get(format!("{}/login", app_url))
+            .send()
+            .await
+            .unwrap();
+        let authorization_url = location(&response);
+        assert!(authorization_url.starts_with(&fakehub.github_dot_com_url()));
+
+        // pick the user on Fakehub's login page
+        let response = browser
+            .post(format!("{}&user_id={}", authorization_url, USER_ID))
+            .send()
+            .await
+            .unwrap();
+        let callback_url = location(&response);
+        assert!(callback_url.starts_with(&format!("{}/callback", app_url)));
+
+        let response = browser.get(callback_url).send().await.unwrap();
+        assert_eq!("/", location(&response));
+
+        let response = browser.get(format!("{}/me", app_url)).send().await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(USER, response.text().await.unwrap());
+
+        browser
+            .get(format!("{}/logout", app_url))
+            .send()
+            .await
+            .unwrap();
+        let response = browser.get(format!("{}/me", app_url)).send().await.unwrap();
+        assert_eq!("/login", location(&response));
+
+        // turn the app down on Fakehub's login page instead
+        let response = browser
+            .get(format!("{}/login", app_url))
+            .send()
+            .await
+            .unwrap();
+        let authorization_url = location(&response);
+        let login_page = browser
+            .get(&authorization_url)
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert!(login_page.contains("Cancel"));
+
+        let response = browser
+            .post(
+                authorization_url
+                    .replace("/login/oauth/authorize?", "/login/oauth/authorize/cancel?"),
+            )
+            .send()
+            .await
+            .unwrap();
+        let callback_url = location(&response);
+        match Callback::from_url(&callback_url).unwrap() {
+            Callback::Error(error) => {
+                assert_eq!(AuthorizationErrorCode::AccessDenied, error.error);
+                assert!(error.error_description.is_some());
+                assert!(error.state.is_some());
+            }
+            callback => panic!("expected access_denied, got {:?}", callback),
+        }
+
+        let response = browser.get(callback_url).send().await.unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, response.status());
+        let response = browser.get(format!("{}/me", app_url)).send().await.unwrap();
+        assert_eq!("/login", location(&response));
+
+        fakehub.shutdown().await;
+    }
+}
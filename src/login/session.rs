@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::UserDetailResponse;
+
+use super::Error;
+
+/// What is remembered about a user once they've logged in.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Session {
+    pub user: UserDetailResponse,
+}
+
+/// Somewhere to keep sessions, keyed by the session id held in the
+/// user's cookie. Implement this over your database or cache of choice
+/// to share sessions between processes.
+#[async_trait]
+pub trait SessionStore: Send + Sync + 'static {
+    async fn load(&self, session_id: &str) -> Result<Option<Session>, Error>;
+    async fn store(&self, session_id: &str, session: Session) -> Result<(), Error>;
+    async fn remove(&self, session_id: &str) -> Result<(), Error>;
+}
+
+/// How long a session lasts in a [`MemorySessionStore`], unless set.
+const DEFAULT_SESSION_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Keeps sessions in memory, so they are lost when the process exits.
+/// Sessions expire a day after they're stored, unless set otherwise with
+/// [`MemorySessionStore::with_lifetime`].
+#[derive(Debug)]
+pub struct MemorySessionStore {
+    sessions: Mutex<HashMap<String, (Session, Instant)>>,
+    lifetime: Duration,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Self::with_lifetime(DEFAULT_SESSION_LIFETIME)
+    }
+
+    /// Keep sessions for `lifetime` after they're stored.
+    pub fn with_lifetime(lifetime: Duration) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            lifetime,
+        }
+    }
+}
+
+impl Default for MemorySessionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn load(&self, session_id: &str) -> Result<Option<Session>, Error> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        match sessions.get(session_id) {
+            Some((session, stored)) if stored.elapsed() < self.lifetime => {
+                Ok(Some(session.clone()))
+            }
+            Some(_) => {
+                sessions.remove(session_id);
+
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn store(&self, session_id: &str, session: Session) -> Result<(), Error> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        sessions.retain(|_, (_, stored)| stored.elapsed() < self.lifetime);
+        sessions.insert(session_id.to_owned(), (session, Instant::now()));
+
+        Ok(())
+    }
+
+    async fn remove(&self, session_id: &str) -> Result<(), Error> {
+        self.sessions.lock().unwrap().remove(session_id);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::UserDetailResponse;
+
+    use super::{MemorySessionStore, Session, SessionStore};
+
+    #[test]
+    fn sessions_expire() {
+        let store = MemorySessionStore::with_lifetime(Duration::from_millis(100));
+        let session = Session {
+            user: UserDetailResponse {
+                login: "user".to_owned(),
+                id: 1,
+                avatar_url: String::new(),
+                html_url: String::new(),
+            },
+        };
+
+        tokio_test::block_on(store.store("session", session)).unwrap();
+        assert!(tokio_test::block_on(store.load("session"))
+            .unwrap()
+            .is_some());
+
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(tokio_test::block_on(store.load("session"))
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,529 @@
+//! "Login with Github" for command line tools and other native apps,
+//! by way of the loopback redirect of
+//! [RFC 8252](https://www.rfc-editor.org/rfc/rfc8252#section-7.3).
+//!
+//! [`LoopbackLogin`] listens on a random port of `127.0.0.1`, sends the
+//! user's browser to Github with a redirect back to that port, and once
+//! Github sends them back checks the state and exchanges the code, with
+//! PKCE, for a token.
+//!
+//! Register `http://127.0.0.1/callback` as the app's callback URL; Github
+//! lets loopback redirects pick any port.
+//!
+//! ```no_run
+//! # use ghoauth::{native::LoopbackLogin, GithubClient};
+//! # async fn login(github_client: GithubClient) -> Result<(), ghoauth::Error> {
+//! let login = LoopbackLogin::new(github_client)
+//!     .scopes(&["read:org"])
+//!     .start()
+//!     .await?;
+//!
+//! if let Some(url) = login.open_browser() {
+//!     println!("Open {} in a browser to log in.", url);
+//! }
+//!
+//! let token = login.finish().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    process::{Command, Stdio},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinSet,
+};
+
+use crate::{
+    error::Error, pkce::random_string, AuthorizationCode, AuthorizationRequest, Callback,
+    GetAccessTokenResponse, GithubApi, Pkce,
+};
+
+/// How long a user has to finish logging in on Github, unless set.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// The path Github redirects back to on the loopback listener.
+const CALLBACK_PATH: &str = "/callback";
+
+/// How long a connection to the loopback listener has to send its
+/// request head.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The longest request or header line the loopback listener reads.
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+
+const LOGGED_IN_PAGE: &str = "<!DOCTYPE html><html><head><title>Logged in</title></head>\
+    <body><p>You are logged in, and can close this window.</p></body></html>";
+const FAILED_PAGE: &str = "<!DOCTYPE html><html><head><title>Login failed</title></head>\
+    <body><p>Logging in failed. Return to the application for details.</p></body></html>";
+
+/// Whether [`PendingLogin::open_browser`] launches one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Browser {
+    /// Launch the user's browser, unless there is evidently no display to
+    /// show it on, eg. over SSH.
+    Auto,
+    /// Never launch a browser; the user opens the URL themselves.
+    Never,
+}
+
+/// A login through the user's browser and a loopback redirect. Set it up,
+/// then [`start`](LoopbackLogin::start) it.
+pub struct LoopbackLogin {
+    github: Arc<dyn GithubApi>,
+    scopes: Vec<String>,
+    login: Option<String>,
+    timeout: Duration,
+    browser: Browser,
+}
+
+impl LoopbackLogin {
+    pub fn new(github: impl GithubApi + 'static) -> Self {
+        Self {
+            github: Arc::new(github),
+            scopes: Vec::new(),
+            login: None,
+            timeout: DEFAULT_TIMEOUT,
+            browser: Browser::Auto,
+        }
+    }
+
+    /// The scopes to ask the user for.
+    pub fn scopes(mut self, scopes: &[&str]) -> Self {
+        self.scopes = scopes.iter().map(|scope| scope.to_string()).collect();
+        self
+    }
+
+    /// Suggest a specific account to log in with.
+    pub fn login(mut self, login: &str) -> Self {
+        self.login = Some(login.to_owned());
+        self
+    }
+
+    /// How long to wait for the user to come back from Github. Five
+    /// minutes unless set.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Whether to launch a browser. Defaults to [`Browser::Auto`].
+    pub fn browser(mut self, browser: Browser) -> Self {
+        self.browser = browser;
+        self
+    }
+
+    /// Start listening for the redirect, and work out where to send the
+    /// user.
+    pub async fn start(self) -> Result<PendingLogin, Error> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .map_err(|error| {
+                Error::OtherHttp(format!("cannot listen for the redirect: {}", error))
+            })?;
+        let local_addr = listener.local_addr().map_err(|error| {
+            Error::OtherHttp(format!("cannot listen for the redirect: {}", error))
+        })?;
+        let redirect_uri = format!("http://{}{}", local_addr, CALLBACK_PATH);
+        let state = random_string(16);
+        let pkce = Pkce::new();
+        let authorization_url = self.github.authorization_url_with(&AuthorizationRequest {
+            redirect_uri: Some(redirect_uri.clone()),
+            scopes: self.scopes,
+            state: Some(state.clone()),
+            code_challenge: Some(pkce.challenge),
+            login: self.login,
+        });
+
+        Ok(PendingLogin {
+            github: self.github,
+            listener,
+            local_addr,
+            redirect_uri,
+            authorization_url,
+            state,
+            code_verifier: pkce.verifier,
+            timeout: self.timeout,
+            browser: self.browser,
+        })
+    }
+}
+
+impl std::fmt::Debug for LoopbackLogin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "LoopbackLogin {{ scopes: {:?}, login: {:?}, timeout: {:?}, browser: {:?} }}",
+            self.scopes, self.login, self.timeout, self.browser,
+        )
+    }
+}
+
+/// A login waiting on the user to come back from Github.
+pub struct PendingLogin {
+    github: Arc<dyn GithubApi>,
+    listener: TcpListener,
+    local_addr: SocketAddr,
+    redirect_uri: String,
+    authorization_url: String,
+    state: String,
+    code_verifier: String,
+    timeout: Duration,
+    browser: Browser,
+}
+
+impl PendingLogin {
+    /// Where to send the user to log in.
+    pub fn authorization_url(&self) -> &str {
+        &self.authorization_url
+    }
+
+    /// Where Github sends the user back to.
+    pub fn redirect_uri(&self) -> &str {
+        &self.redirect_uri
+    }
+
+    /// The address the redirect is listened for on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Send the user to Github in their browser. When no browser could
+    /// be launched, the URL is handed back for the user to open
+    /// themselves.
+    pub fn open_browser(&self) -> Option<&str> {
+        if self.browser == Browser::Never || is_headless() {
+            return Some(&self.authorization_url);
+        }
+
+        match launch_browser(&self.authorization_url) {
+            true => None,
+            false => Some(&self.authorization_url),
+        }
+    }
+
+    /// Wait for Github to send the user back, then exchange the code for
+    /// a token.
+    pub async fn finish(self) -> Result<GetAccessTokenResponse, Error> {
+        let code = tokio::time::timeout(self.timeout, self.wait_for_code())
+            .await
+            .map_err(|_| {
+                Error::Timeout(format!(
+                    "the user did not come back from Github within {:?}",
+                    self.timeout
+                ))
+            })??;
+
+        self.github
+            .get_access_token_with_verifier(&code, &self.code_verifier)
+            .await
+    }
+
+    async fn wait_for_code(&self) -> Result<String, Error> {
+        // connections are read side by side, so one that's left idle,
+        // like a browser's preconnect, doesn't hold up the redirect
+        let mut reads = JoinSet::new();
+
+        loop {
+            let (mut stream, target) = tokio::select! {
+                accepted = self.listener.accept() => {
+                    let (stream, _) = accepted.map_err(|error| {
+                        Error::OtherHttp(format!("cannot accept the redirect: {}", error))
+                    })?;
+
+                    reads.spawn(async move {
+                        let mut stream = BufReader::new(stream);
+                        let target =
+                            tokio::time::timeout(READ_TIMEOUT, read_request_target(&mut stream))
+                                .await
+                                .ok()
+                                .flatten();
+
+                        (stream, target)
+                    });
+                    continue;
+                }
+                Some(Ok(read)) = reads.join_next() => read,
+            };
+
+            // browsers ask for favicons and the like; only the callback
+            // path ends the wait
+            let query = match target {
+                Some(target) => match target.split_once('?') {
+                    Some((CALLBACK_PATH, query)) => query.to_owned(),
+                    _ if target == CALLBACK_PATH => String::new(),
+                    _ => {
+                        respond(stream.get_mut(), "404 Not Found", "").await;
+                        continue;
+                    }
+                },
+                None => continue,
+            };
+
+            // a callback without this login's state isn't the redirect
+            // being waited for, and may well be forged
+            let code = match self.check_callback(&query) {
+                Some(code) => code,
+                None => {
+                    respond(stream.get_mut(), "400 Bad Request", "").await;
+                    continue;
+                }
+            };
+            let page = match code {
+                Ok(_) => ("200 OK", LOGGED_IN_PAGE),
+                Err(_) => ("400 Bad Request", FAILED_PAGE),
+            };
+
+            respond(stream.get_mut(), page.0, page.1).await;
+
+            return code;
+        }
+    }
+
+    /// The code, or the error, a callback carrying this login's state
+    /// brings. `None` for any other callback.
+    fn check_callback(&self, query: &str) -> Option<Result<String, Error>> {
+        let ours = |state: Option<&str>| state == Some(self.state.as_str());
+
+        match Callback::from_query(query).ok()? {
+            Callback::Code(AuthorizationCode { code, state }) => {
+                ours(state.as_deref()).then_some(Ok(code))
+            }
+            Callback::Error(error) => ours(error.state.as_deref()).then(|| {
+                Err(Error::OAuth(
+                    error.error.as_str().to_owned(),
+                    error.error_description,
+                ))
+            }),
+        }
+    }
+}
+
+// Custom debug printer omits the state and verifier, which should never
+// be logged for security reasons.
+impl std::fmt::Debug for PendingLogin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "PendingLogin {{ redirect_uri: {}, authorization_url: {}, state: REDACTED, code_verifier: REDACTED, timeout: {:?} }}",
+            self.redirect_uri, self.authorization_url, self.timeout,
+        )
+    }
+}
+
+/// Read a request's head, and return its target if it is a `GET`.
+async fn read_request_target(stream: &mut BufReader<TcpStream>) -> Option<String> {
+    let request_line = read_line(stream).await?;
+
+    // drain the headers, so the browser isn't cut off mid-request
+    loop {
+        if read_line(stream).await?.trim_end().is_empty() {
+            break;
+        }
+    }
+
+    match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", target, _] => Some(target.to_owned()),
+        _ => None,
+    }
+}
+
+/// Read a line, or an empty one at the end of the stream. `None` if the
+/// line is longer than [`MAX_LINE_LENGTH`] or can't be read.
+async fn read_line(stream: &mut BufReader<TcpStream>) -> Option<String> {
+    let mut line = String::new();
+    let read = (&mut *stream)
+        .take(MAX_LINE_LENGTH)
+        .read_line(&mut line)
+        .await
+        .ok()?;
+
+    (read == 0 || line.ends_with('\n')).then_some(line)
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body,
+    );
+
+    // the user's browser going away doesn't change how the login went
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+/// Whether there is evidently no display to launch a browser on.
+fn is_headless() -> bool {
+    let set = |name: &str| std::env::var_os(name).is_some_and(|value| !value.is_empty());
+
+    if set("SSH_CONNECTION") || set("SSH_TTY") {
+        return true;
+    }
+
+    cfg!(all(unix, not(target_os = "macos"))) && !set("DISPLAY") && !set("WAYLAND_DISPLAY")
+}
+
+fn launch_browser(url: &str) -> bool {
+    let mut command = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        // unlike cmd's start, this leaves the url's ampersands alone
+        let mut command = Command::new("rundll32");
+        command.arg("url.dll,FileProtocolHandler");
+        command
+    } else {
+        Command::new("xdg-open")
+    };
+
+    command
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+#[cfg(all(test, feature = "fakehub"))]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::{redirect::Policy, StatusCode, Url};
+
+    use crate::{
+        fakehub::Fakehub,
+        testing::{add_test_user, CLIENT_ID, CLIENT_SECRET, USER, USER_ID},
+    };
+
+    use super::{Browser, LoopbackLogin};
+
+    #[tokio::test]
+    async fn native_loopback_login() {
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+        add_test_user(&fakehub).await;
+
+        // stands in for the user's browser, one redirect at a time
+        let browser = reqwest::ClientBuilder::new()
+            .redirect(Policy::none())
+            .build()
+            .unwrap();
+        let location = |response: &reqwest::Response| {
+            response.headers()["Location"].to_str().unwrap().to_string()
+        };
+        let start = || async {
+            LoopbackLogin::new(github_client.clone())
+                .browser(Browser::Never)
+                .timeout(Duration::from_secs(5))
+                .start()
+                .await
+                .unwrap()
+        };
+
+        let login = start().await;
+        let authorization_url = login.open_browser().unwrap().to_owned();
+        assert!(login.redirect_uri().starts_with("http://127.0.0.1:"));
+        let token = tokio::spawn(login.finish());
+
+        // pick the user on Fakehub's login page
+        let response = browser
+            .post(format!("{}&user_id={}", authorization_url, USER_ID))
+            .send()
+            .await
+            .unwrap();
+        let callback_url = location(&response);
+
+        // stray requests don't end the wait
+        let favicon = Url::parse(&callback_url)
+            .unwrap()
+            .join("/favicon.ico")
+            .unwrap();
+        let response = browser.get(favicon).send().await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+
+        let response = browser.get(callback_url).send().await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+
+        let token = token.await.unwrap().unwrap();
+        let user_detail = github_client
+            .get_user_detail(&token.access_token)
+            .await
+            .unwrap();
+        assert_eq!(USER, user_detail.login);
+
+        // a callback with someone else's state is turned away, as are
+        // overlong requests, and neither they nor an idle connection
+        // keep the real callback from ending the wait
+        let login = start().await;
+        let authorization_url = login.authorization_url().to_owned();
+        let redirect_uri = login.redirect_uri().to_owned();
+        let token = tokio::spawn(login.finish());
+        let listener = redirect_uri
+            .trim_start_matches("http://")
+            .split('/')
+            .next()
+            .unwrap()
+            .to_owned();
+        let _idle = tokio::net::TcpStream::connect(&listener).await.unwrap();
+        let response = browser
+            .get(format!("{}?code=stolen&state=forged", redirect_uri))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        let response = browser
+            .get(format!("{}?code=stolen", redirect_uri))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, response.status());
+        assert!(browser
+            .get(format!("{}?{}", redirect_uri, "a".repeat(10_000)))
+            .send()
+            .await
+            .is_err());
+        let response = browser
+            .post(format!("{}&user_id={}", authorization_url, USER_ID))
+            .send()
+            .await
+            .unwrap();
+        let response = browser.get(location(&response)).send().await.unwrap();
+        assert_eq!(StatusCode::OK, response.status());
+        assert!(token.await.unwrap().is_ok());
+
+        // as is a user who turns the app down
+        let login = start().await;
+        let cancel_url = login
+            .authorization_url()
+            .replace("/login/oauth/authorize", "/login/oauth/authorize/cancel");
+        let token = tokio::spawn(login.finish());
+        let response = browser.post(cancel_url).send().await.unwrap();
+        browser.get(location(&response)).send().await.unwrap();
+        assert!(matches!(
+            token.await.unwrap(),
+            Err(crate::Error::OAuth(error, _)) if error == "access_denied"
+        ));
+
+        // and one who never comes back
+        let login = LoopbackLogin::new(github_client.clone())
+            .browser(Browser::Never)
+            .timeout(Duration::from_millis(50))
+            .start()
+            .await
+            .unwrap();
+        assert!(matches!(
+            login.finish().await,
+            Err(crate::Error::Timeout(_))
+        ));
+
+        fakehub.shutdown().await;
+    }
+}
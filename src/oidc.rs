@@ -0,0 +1,424 @@
+//! Verification of the OIDC tokens Github Actions issues to workflow
+//! runs, so a service can trust a workload without sharing a secret
+//! with it.
+//!
+//! ```no_run
+//! # use ghoauth::oidc::{ClaimPolicy, OidcVerifier};
+//! # async fn verify(token: &str) -> Result<(), ghoauth::oidc::Error> {
+//! let verifier = OidcVerifier::new("https://deploy.example.com")?.with_policy(
+//!     ClaimPolicy::new()
+//!         .require("repository", "my-org/*")
+//!         .require("ref", "refs/heads/main"),
+//! );
+//!
+//! let claims = verifier.verify(token).await?;
+//!
+//! println!("deploying {} at {}", claims.repository, claims.sha);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use reqwest::Client as ReqwestClient;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+const ISSUER: &str = "https://token.actions.githubusercontent.com";
+
+/// How long to trust a fetched JWKS before fetching it again.
+const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// How long after fetching the JWKS a token naming a key that isn't in
+/// it can have it fetched again.
+const DEFAULT_REFETCH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Client(#[from] crate::Error),
+    #[error("Invalid token: {0}")]
+    InvalidToken(String),
+    #[error("No key with id {0} is published by the issuer")]
+    UnknownKey(String),
+    #[error("Claim {0} does not match the policy")]
+    PolicyMismatch(String),
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(value: reqwest::Error) -> Self {
+        Self::Client(value.into())
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for Error {
+    fn from(value: jsonwebtoken::errors::Error) -> Self {
+        Self::InvalidToken(value.to_string())
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The claims in a Github Actions OIDC token. Claims not modelled here
+/// are kept in `other`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ActionsClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    /// The repository the workflow ran in, as `owner/name`.
+    pub repository: String,
+    pub repository_owner: String,
+    /// The git ref the workflow ran against, eg. `refs/heads/main`.
+    #[serde(rename = "ref")]
+    pub git_ref: String,
+    #[serde(default)]
+    pub sha: String,
+    pub workflow: String,
+    #[serde(default)]
+    pub actor: String,
+    #[serde(default)]
+    pub event_name: String,
+    /// The deployment environment of the job, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job_workflow_ref: Option<String>,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, serde_json::Value>,
+}
+
+impl ActionsClaims {
+    /// Look up a claim by the name Github gives it, as a string.
+    pub fn claim(&self, name: &str) -> Option<String> {
+        match serde_json::to_value(self).ok()?.get(name)? {
+            serde_json::Value::String(value) => Some(value.clone()),
+            serde_json::Value::Null => None,
+            other => Some(other.to_string()),
+        }
+    }
+}
+
+/// Rules a token's claims must satisfy beyond a valid signature,
+/// audience and issuer. Patterns match a whole claim, with `*` standing
+/// in for any run of characters.
+#[derive(Clone, Debug, Default)]
+pub struct ClaimPolicy {
+    requirements: Vec<(String, String)>,
+}
+
+impl ClaimPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require a claim to match a pattern. Requiring the same claim
+    /// twice requires it to match both patterns.
+    pub fn require(mut self, claim: &str, pattern: &str) -> Self {
+        self.requirements
+            .push((claim.to_owned(), pattern.to_owned()));
+        self
+    }
+
+    /// Check a token's claims against every requirement.
+    pub fn check(&self, claims: &ActionsClaims) -> Result<()> {
+        for (claim, pattern) in self.requirements.iter() {
+            match claims.claim(claim) {
+                Some(value) if pattern_matches(pattern, &value) => (),
+                _ => return Err(Error::PolicyMismatch(claim.clone())),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn pattern_matches(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+
+    let mut rest = match value.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let parts = parts.collect::<Vec<_>>();
+
+    match parts.split_last() {
+        // no wildcards, so the prefix had to be the whole thing
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+
+            rest.ends_with(last)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenIdConfiguration {
+    jwks_uri: String,
+}
+
+#[derive(Debug)]
+struct CachedJwks {
+    jwks: JwkSet,
+    fetched_at: Instant,
+}
+
+/// Verifies Github Actions OIDC tokens against the keys published by
+/// the issuer. Keys are cached, and fetched again when they go stale or
+/// a token names a key that isn't in the cache, which is how a rotated
+/// key gets picked up. Tokens naming unknown keys get the keys fetched
+/// at most once a minute, so they can't be used to hammer the issuer.
+#[derive(Debug)]
+pub struct OidcVerifier {
+    http_client: ReqwestClient,
+    /// The expected `iss` claim, which is also where the issuer's
+    /// OpenID configuration is published.
+    issuer: String,
+    /// The expected `aud` claim.
+    audience: String,
+    policy: ClaimPolicy,
+    cache_ttl: Duration,
+    refetch_interval: Duration,
+    jwks: RwLock<Option<CachedJwks>>,
+}
+
+impl OidcVerifier {
+    /// Create a verifier for tokens issued by Github Actions for the
+    /// given audience.
+    pub fn new(audience: &str) -> Result<Self> {
+        Self::new_with_issuer(ISSUER, audience)
+    }
+
+    /// Create a verifier for tokens from an arbitrary issuer, such as
+    /// Github Enterprise Server.
+    ///
+    /// See also [`crate::fakehub::Fakehub::oidc_issuer_url`].
+    pub fn new_with_issuer(issuer: &str, audience: &str) -> Result<Self> {
+        Ok(Self {
+            http_client: reqwest::ClientBuilder::new()
+                .user_agent("Rust/request/ghoauth")
+                .build()?,
+            issuer: issuer.trim_end_matches('/').to_owned(),
+            audience: audience.to_owned(),
+            policy: ClaimPolicy::default(),
+            cache_ttl: DEFAULT_CACHE_TTL,
+            refetch_interval: DEFAULT_REFETCH_INTERVAL,
+            jwks: RwLock::new(None),
+        })
+    }
+
+    /// Require verified tokens to also satisfy a claim policy.
+    pub fn with_policy(mut self, policy: ClaimPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// How long to keep using fetched keys before fetching them again.
+    pub fn with_cache_ttl(mut self, cache_ttl: Duration) -> Self {
+        self.cache_ttl = cache_ttl;
+        self
+    }
+
+    /// How long after fetching keys a token naming a key that isn't
+    /// among them can have them fetched again. Until then, such tokens
+    /// are refused.
+    pub fn with_refetch_interval(mut self, refetch_interval: Duration) -> Self {
+        self.refetch_interval = refetch_interval;
+        self
+    }
+
+    /// Verify a token's signature, issuer, audience and expiry, then
+    /// check it against the policy.
+    pub async fn verify(&self, token: &str) -> Result<ActionsClaims> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let kid = header
+            .kid
+            .ok_or_else(|| Error::InvalidToken("token has no key id".to_owned()))?;
+
+        let decoding_key = match self.cached_key(&kid)? {
+            Some(decoding_key) => decoding_key,
+            None if !self.may_refresh_jwks() => return Err(Error::UnknownKey(kid)),
+            None => {
+                self.refresh_jwks().await?;
+                self.cached_key(&kid)?
+                    .ok_or_else(|| Error::UnknownKey(kid.clone()))?
+            }
+        };
+
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+
+        let claims =
+            jsonwebtoken::decode::<ActionsClaims>(token, &decoding_key, &validation)?.claims;
+
+        self.policy.check(&claims)?;
+
+        Ok(claims)
+    }
+
+    fn cached_key(&self, kid: &str) -> Result<Option<DecodingKey>> {
+        let jwks = self.jwks.read().expect("jwks cache poisoned");
+
+        match jwks.as_ref() {
+            Some(cached) if cached.fetched_at.elapsed() < self.cache_ttl => {
+                match cached.jwks.find(kid) {
+                    Some(jwk) => Ok(Some(DecodingKey::from_jwk(jwk)?)),
+                    None => Ok(None),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether the keys are missing, stale or old enough to fetch again.
+    fn may_refresh_jwks(&self) -> bool {
+        let jwks = self.jwks.read().expect("jwks cache poisoned");
+
+        match jwks.as_ref() {
+            Some(cached) => {
+                let age = cached.fetched_at.elapsed();
+
+                age >= self.cache_ttl || age >= self.refetch_interval
+            }
+            None => true,
+        }
+    }
+
+    async fn refresh_jwks(&self) -> Result<()> {
+        let configuration: OpenIdConfiguration = self
+            .http_client
+            .get(format!("{}/.well-known/openid-configuration", self.issuer))
+            .header("Accept", "application/json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let jwks: JwkSet = self
+            .http_client
+            .get(configuration.jwks_uri)
+            .header("Accept", "application/json")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        *self.jwks.write().expect("jwks cache poisoned") = Some(CachedJwks {
+            jwks,
+            fetched_at: Instant::now(),
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "fakehub")]
+    use std::time::Duration;
+
+    #[cfg(feature = "fakehub")]
+    use serde_json::json;
+
+    #[cfg(feature = "fakehub")]
+    use crate::{fakehub::Fakehub, testing::AUDIENCE};
+
+    use super::pattern_matches;
+    #[cfg(feature = "fakehub")]
+    use super::{ClaimPolicy, Error, OidcVerifier};
+
+    #[test]
+    fn patterns() {
+        assert!(pattern_matches("refs/heads/main", "refs/heads/main"));
+        assert!(!pattern_matches("refs/heads/main", "refs/heads/main2"));
+        assert!(pattern_matches("my-org/*", "my-org/service"));
+        assert!(!pattern_matches("my-org/*", "other-org/service"));
+        assert!(pattern_matches(
+            "*/deploy.yml@*",
+            "my-org/ops/deploy.yml@refs/heads/main"
+        ));
+        assert!(!pattern_matches(
+            "*/deploy.yml@*",
+            "my-org/ops/build.yml@refs/heads/main"
+        ));
+    }
+
+    #[cfg(feature = "fakehub")]
+    #[tokio::test]
+    async fn actions_oidc_token() {
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let verifier = OidcVerifier::new_with_issuer(&fakehub.oidc_issuer_url(), AUDIENCE)
+            .unwrap()
+            .with_policy(ClaimPolicy::new().require("repository", "org/*"))
+            .with_refetch_interval(Duration::from_millis(200));
+        let claims = json!({
+            "repository": "org/service",
+            "repository_owner": "org",
+            "ref": "refs/heads/main",
+            "workflow": "deploy",
+            "environment": "production",
+        });
+
+        let token = fakehub
+            .mint_oidc_token(AUDIENCE, claims.clone())
+            .await
+            .unwrap();
+        let verified = verifier.verify(&token).await.unwrap();
+
+        assert_eq!("refs/heads/main", verified.git_ref);
+        assert_eq!(Some("production".to_string()), verified.environment);
+
+        // tokens signed with a freshly rotated key are verified once the
+        // new key is fetched, which is not straight after the last fetch
+        fakehub.rotate_oidc_key().await;
+        let token = fakehub
+            .mint_oidc_token(AUDIENCE, claims.clone())
+            .await
+            .unwrap();
+        assert!(matches!(
+            verifier.verify(&token).await,
+            Err(Error::UnknownKey(_))
+        ));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        verifier.verify(&token).await.unwrap();
+
+        // tokens for another audience or outside the policy are refused
+        let token = fakehub
+            .mint_oidc_token("https://elsewhere.example.com", claims)
+            .await
+            .unwrap();
+        assert!(verifier.verify(&token).await.is_err());
+
+        let token = fakehub
+            .mint_oidc_token(
+                AUDIENCE,
+                json!({
+                    "repository": "other-org/service",
+                    "repository_owner": "other-org",
+                    "ref": "refs/heads/main",
+                    "workflow": "deploy",
+                }),
+            )
+            .await
+            .unwrap();
+        assert!(verifier.verify(&token).await.is_err());
+
+        fakehub.shutdown().await;
+    }
+}
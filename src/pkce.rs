@@ -0,0 +1,59 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// A Proof Key for Code Exchange, which ties the code exchange to the
+/// party that started the authorization. Send the challenge with the
+/// authorization request and keep the verifier secret until the code is
+/// exchanged.
+#[derive(Clone)]
+pub struct Pkce {
+    pub verifier: String,
+    pub challenge: String,
+}
+
+impl Pkce {
+    /// Generate a fresh verifier and its `S256` challenge.
+    pub fn new() -> Self {
+        let verifier = random_string(32);
+        let challenge = Self::challenge_for(&verifier);
+
+        Self {
+            verifier,
+            challenge,
+        }
+    }
+
+    /// The `S256` challenge for a verifier.
+    pub fn challenge_for(verifier: &str) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+    }
+}
+
+impl Default for Pkce {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Custom debug printer omits the verifier, which should never be logged
+// for security reasons.
+impl std::fmt::Debug for Pkce {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Pkce {{ verifier: REDACTED, challenge: {} }}",
+            self.challenge
+        )
+    }
+}
+
+/// A url-safe string of random bytes, suitable for states, verifiers and
+/// session ids.
+pub(crate) fn random_string(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+
+    rand::thread_rng().fill_bytes(&mut buf);
+
+    URL_SAFE_NO_PAD.encode(buf)
+}
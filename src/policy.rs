@@ -0,0 +1,590 @@
+//! Declarative access policies over Github identities, for the "let in
+//! these users, members of that org, or anyone on this team" logic every
+//! service ends up needing.
+//!
+//! Policies are plain data, so they can be built in code or loaded from
+//! configuration:
+//!
+//! ```toml
+//! require_email_domains = ["example.com"]
+//!
+//! [allow]
+//! orgs = ["my-org"]
+//! teams = ["partner-org/contractors"]
+//!
+//! [deny]
+//! users = [1234]
+//! ```
+//!
+//! Evaluating a policy only asks Github for what its rules need, so a
+//! policy that only names user ids costs no API calls at all.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    shapes::{Email, Organization, Team},
+    GithubClient, UserDetailResponse,
+};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Client(#[from] crate::Error),
+    #[error("Invalid policy: {0}")]
+    Config(String),
+}
+
+#[cfg(feature = "policy-toml")]
+impl From<toml::de::Error> for Error {
+    fn from(value: toml::de::Error) -> Self {
+        Self::Config(value.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Config(value.to_string())
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Users, orgs and teams a policy names. Orgs are named by login, and
+/// teams as `org/team-slug`; both are matched case-insensitively, as
+/// Github does.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rules {
+    pub users: Vec<i64>,
+    pub orgs: Vec<String>,
+    pub teams: Vec<String>,
+}
+
+impl Rules {
+    fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.orgs.is_empty() && self.teams.is_empty()
+    }
+}
+
+/// Who may and may not use a service.
+///
+/// Deny rules win over everything else. Users who aren't denied must
+/// then have a verified email in one of the required domains, if any
+/// are required, and finally match one of the allow rules. A policy
+/// with no allow rules at all allows everyone who gets that far, so
+/// [`Policy::new`] on its own lets in every Github user.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    pub allow: Rules,
+    pub deny: Rules,
+    /// Domains users need a verified email address in, such as
+    /// `example.com`.
+    pub require_email_domains: Vec<String>,
+    /// Further domain lists, each of which users need a verified email
+    /// address in as well. [`Policy::merge`] adds the other policy's
+    /// requirement here rather than widening `require_email_domains`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub also_require_email_domains: Vec<Vec<String>>,
+}
+
+impl Policy {
+    /// An empty policy. Until it's given allow rules it allows everyone,
+    /// with [`Reason::AllowedByDefault`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a policy from TOML.
+    #[cfg(feature = "policy-toml")]
+    pub fn from_toml_str(toml: &str) -> Result<Self> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    /// Load a policy from JSON.
+    pub fn from_json_str(json: &str) -> Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn allow_user(mut self, user_id: i64) -> Self {
+        self.allow.users.push(user_id);
+        self
+    }
+
+    pub fn allow_org(mut self, org: &str) -> Self {
+        self.allow.orgs.push(org.to_owned());
+        self
+    }
+
+    /// Allow the members of a team, named as `org/team-slug`.
+    pub fn allow_team(mut self, team: &str) -> Self {
+        self.allow.teams.push(team.to_owned());
+        self
+    }
+
+    pub fn deny_user(mut self, user_id: i64) -> Self {
+        self.deny.users.push(user_id);
+        self
+    }
+
+    pub fn deny_org(mut self, org: &str) -> Self {
+        self.deny.orgs.push(org.to_owned());
+        self
+    }
+
+    /// Deny the members of a team, named as `org/team-slug`.
+    pub fn deny_team(mut self, team: &str) -> Self {
+        self.deny.teams.push(team.to_owned());
+        self
+    }
+
+    pub fn require_email_domain(mut self, domain: &str) -> Self {
+        self.require_email_domains.push(domain.to_owned());
+        self
+    }
+
+    /// Combine two policies into one that denies whatever either denies,
+    /// requires each one's email domains, and allows whoever either one's
+    /// allow rules name. A policy without allow rules adds none, so it
+    /// doesn't open up the other.
+    pub fn merge(mut self, other: Policy) -> Self {
+        self.allow.users.extend(other.allow.users);
+        self.allow.orgs.extend(other.allow.orgs);
+        self.allow.teams.extend(other.allow.teams);
+        self.deny.users.extend(other.deny.users);
+        self.deny.orgs.extend(other.deny.orgs);
+        self.deny.teams.extend(other.deny.teams);
+        for domains in std::iter::once(other.require_email_domains)
+            .chain(other.also_require_email_domains)
+            .filter(|domains| !domains.is_empty())
+        {
+            if self.require_email_domains.is_empty() {
+                self.require_email_domains = domains;
+            } else {
+                self.also_require_email_domains.push(domains);
+            }
+        }
+        self
+    }
+
+    /// Decide whether to let a user in. The token is theirs, and needs the
+    /// `read:org` scope for org and team rules and the `user:email` scope
+    /// for email domain rules.
+    pub async fn evaluate(
+        &self,
+        github_client: &GithubClient,
+        access_token: &str,
+        user: &UserDetailResponse,
+    ) -> Result<Decision> {
+        let mut identity = Identity {
+            github_client,
+            access_token,
+            orgs: None,
+            teams: None,
+            emails: None,
+        };
+
+        if self.deny.users.contains(&user.id) {
+            return Ok(Decision::deny(Reason::DeniedUser(user.id)));
+        }
+        if let Some(org) = identity.member_of_org(&self.deny.orgs).await? {
+            return Ok(Decision::deny(Reason::DeniedOrg(org)));
+        }
+        if let Some(team) = identity.member_of_team(&self.deny.teams).await? {
+            return Ok(Decision::deny(Reason::DeniedTeam(team)));
+        }
+
+        for domains in std::iter::once(&self.require_email_domains)
+            .chain(&self.also_require_email_domains)
+            .filter(|domains| !domains.is_empty())
+        {
+            if identity.verified_email_in(domains).await?.is_none() {
+                return Ok(Decision::deny(Reason::MissingEmailDomain));
+            }
+        }
+
+        if self.allow.is_empty() {
+            return Ok(Decision::allow(Reason::AllowedByDefault));
+        }
+        if self.allow.users.contains(&user.id) {
+            return Ok(Decision::allow(Reason::AllowedUser(user.id)));
+        }
+        if let Some(org) = identity.member_of_org(&self.allow.orgs).await? {
+            return Ok(Decision::allow(Reason::AllowedOrg(org)));
+        }
+        if let Some(team) = identity.member_of_team(&self.allow.teams).await? {
+            return Ok(Decision::allow(Reason::AllowedTeam(team)));
+        }
+
+        Ok(Decision::deny(Reason::NoMatch))
+    }
+}
+
+/// The outcome of evaluating a [`Policy`], and why.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub reason: Reason,
+}
+
+impl Decision {
+    fn allow(reason: Reason) -> Self {
+        Self {
+            allowed: true,
+            reason,
+        }
+    }
+
+    fn deny(reason: Reason) -> Self {
+        Self {
+            allowed: false,
+            reason,
+        }
+    }
+}
+
+/// The rule that decided a [`Decision`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reason {
+    DeniedUser(i64),
+    DeniedOrg(String),
+    DeniedTeam(String),
+    MissingEmailDomain,
+    AllowedUser(i64),
+    AllowedOrg(String),
+    AllowedTeam(String),
+    /// The policy has no allow rules, so it lets in anyone it doesn't
+    /// deny.
+    AllowedByDefault,
+    /// The user matched none of the allow rules.
+    NoMatch,
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::DeniedUser(user_id) => write!(f, "user {} is denied", user_id),
+            Self::DeniedOrg(org) => write!(f, "members of org {} are denied", org),
+            Self::DeniedTeam(team) => write!(f, "members of team {} are denied", team),
+            Self::MissingEmailDomain => write!(f, "no verified email in a required domain"),
+            Self::AllowedUser(user_id) => write!(f, "user {} is allowed", user_id),
+            Self::AllowedOrg(org) => write!(f, "members of org {} are allowed", org),
+            Self::AllowedTeam(team) => write!(f, "members of team {} are allowed", team),
+            Self::AllowedByDefault => write!(f, "no allow rules, so everyone is allowed"),
+            Self::NoMatch => write!(f, "no allow rule matched"),
+        }
+    }
+}
+
+/// What Github knows about a user beyond their profile, fetched the
+/// first time a rule needs it.
+struct Identity<'a> {
+    github_client: &'a GithubClient,
+    access_token: &'a str,
+    orgs: Option<Vec<Organization>>,
+    teams: Option<Vec<Team>>,
+    emails: Option<Vec<Email>>,
+}
+
+impl<'a> Identity<'a> {
+    async fn member_of_org(&mut self, names: &[String]) -> Result<Option<String>> {
+        if names.is_empty() {
+            return Ok(None);
+        }
+        if self.orgs.is_none() {
+            self.orgs = Some(self.github_client.list_user_orgs(self.access_token).await?);
+        }
+
+        Ok(self.orgs.iter().flatten().find_map(|org| {
+            names
+                .iter()
+                .find(|name| name.eq_ignore_ascii_case(&org.login))
+                .cloned()
+        }))
+    }
+
+    async fn member_of_team(&mut self, names: &[String]) -> Result<Option<String>> {
+        if names.is_empty() {
+            return Ok(None);
+        }
+        if self.teams.is_none() {
+            self.teams = Some(
+                self.github_client
+                    .list_user_teams(self.access_token)
+                    .await?,
+            );
+        }
+
+        Ok(self.teams.iter().flatten().find_map(|team| {
+            let full_name = format!("{}/{}", team.organization.login, team.slug);
+
+            names
+                .iter()
+                .find(|name| name.eq_ignore_ascii_case(&full_name))
+                .cloned()
+        }))
+    }
+
+    async fn verified_email_in(&mut self, domains: &[String]) -> Result<Option<String>> {
+        if self.emails.is_none() {
+            self.emails = Some(
+                self.github_client
+                    .list_user_emails(self.access_token)
+                    .await?,
+            );
+        }
+
+        Ok(self
+            .emails
+            .iter()
+            .flatten()
+            .filter(|email| email.verified)
+            .find(|email| match email.email.rsplit_once('@') {
+                Some((_, domain)) => domains
+                    .iter()
+                    .any(|required| required.eq_ignore_ascii_case(domain)),
+                None => false,
+            })
+            .map(|email| email.email.clone()))
+    }
+}
+
+/// Enforces a [`Policy`] in [`crate::bearer::BearerAuthLayer`]. Users
+/// are turned away if the policy can't be evaluated, say because the
+/// token lacks a scope it needs.
+#[cfg(feature = "tower")]
+#[derive(Clone, Debug)]
+pub struct PolicyAuthorization {
+    github_client: GithubClient,
+    policy: Policy,
+}
+
+#[cfg(feature = "tower")]
+impl PolicyAuthorization {
+    /// Note that a policy without allow rules lets in every user with a
+    /// valid token who isn't denied.
+    pub fn new(github_client: GithubClient, policy: Policy) -> Self {
+        Self {
+            github_client,
+            policy,
+        }
+    }
+}
+
+#[cfg(feature = "tower")]
+#[async_trait::async_trait]
+impl crate::bearer::AuthorizationPolicy for PolicyAuthorization {
+    async fn authorize(&self, access_token: &str, user: &UserDetailResponse) -> bool {
+        match self
+            .policy
+            .evaluate(&self.github_client, access_token, user)
+            .await
+        {
+            Ok(decision) => decision.allowed,
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "fakehub")]
+    use crate::{
+        fakehub::{Fakehub, Org, Team},
+        testing::{add_test_user, CLIENT_ID, CLIENT_SECRET, ORG, ORG_ID, TEAM_ID, USER_ID},
+    };
+
+    use super::Policy;
+    #[cfg(feature = "fakehub")]
+    use super::Reason;
+
+    #[test]
+    fn config_formats() {
+        let built = Policy::new()
+            .require_email_domain("example.com")
+            .allow_user(1)
+            .allow_team("org/team")
+            .deny_org("evil");
+        let from_json = Policy::from_json_str(
+            r#"{
+                "require_email_domains": ["example.com"],
+                "allow": { "users": [1], "teams": ["org/team"] },
+                "deny": { "orgs": ["evil"] }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(from_json, built);
+        assert!(Policy::from_json_str(r#"{ "allow": { "user": [1] } }"#).is_err());
+    }
+
+    #[cfg(feature = "policy-toml")]
+    #[test]
+    fn toml_config() {
+        let from_toml = Policy::from_toml_str(
+            r#"
+            require_email_domains = ["example.com"]
+
+            [allow]
+            users = [1]
+            teams = ["org/team"]
+
+            [deny]
+            orgs = ["evil"]
+            "#,
+        )
+        .unwrap();
+        let built = Policy::new()
+            .require_email_domain("example.com")
+            .allow_user(1)
+            .allow_team("org/team")
+            .deny_org("evil");
+
+        assert_eq!(from_toml, built);
+        assert!(Policy::from_toml_str("[allow]\nuser = [1]").is_err());
+    }
+
+    #[test]
+    fn merge_keeps_each_email_requirement() {
+        let merged = Policy::new()
+            .require_email_domain("a.example")
+            .merge(Policy::new().require_email_domain("b.example"))
+            .merge(Policy::new().allow_user(1));
+
+        assert_eq!(vec!["a.example".to_string()], merged.require_email_domains);
+        assert_eq!(
+            vec![vec!["b.example".to_string()]],
+            merged.also_require_email_domains
+        );
+        assert_eq!(vec![1], merged.allow.users);
+
+        let merged = Policy::new().merge(Policy::new().require_email_domain("b.example"));
+        assert_eq!(vec!["b.example".to_string()], merged.require_email_domains);
+        assert!(merged.also_require_email_domains.is_empty());
+    }
+
+    #[cfg(feature = "fakehub")]
+    #[tokio::test]
+    async fn access_policy() {
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+        add_test_user(&fakehub).await;
+        fakehub
+            .add_org(
+                ORG_ID,
+                Org {
+                    login: ORG.to_string(),
+                },
+            )
+            .await;
+        fakehub
+            .add_team(
+                TEAM_ID,
+                Team {
+                    org_id: ORG_ID,
+                    name: "Admins".to_string(),
+                    slug: "admins".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+        fakehub.add_org_member(ORG_ID, USER_ID).await.unwrap();
+        fakehub.add_team_member(TEAM_ID, USER_ID).await.unwrap();
+        fakehub
+            .add_email(USER_ID, "user@personal.example", true)
+            .await
+            .unwrap();
+        fakehub
+            .add_email(USER_ID, "user@corp.example", false)
+            .await
+            .unwrap();
+
+        let code = fakehub.get_code(USER_ID).await.unwrap();
+        let token = github_client.get_access_token(&code).await.unwrap();
+        let user = github_client
+            .get_user_detail(&token.access_token)
+            .await
+            .unwrap();
+        let evaluate = |policy: Policy| {
+            let github_client = github_client.clone();
+            let access_token = token.access_token.clone();
+            let user = user.clone();
+
+            async move {
+                policy
+                    .evaluate(&github_client, &access_token, &user)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let decision = evaluate(Policy::new()).await;
+        assert!(decision.allowed);
+        assert_eq!(Reason::AllowedByDefault, decision.reason);
+
+        let decision = evaluate(Policy::new().allow_org("ORG")).await;
+        assert!(decision.allowed);
+        assert_eq!(Reason::AllowedOrg("ORG".to_string()), decision.reason);
+
+        let decision = evaluate(
+            Policy::from_toml_str(
+                r#"
+                [allow]
+                teams = ["org/admins"]
+                "#,
+            )
+            .unwrap(),
+        )
+        .await;
+        assert!(decision.allowed);
+        assert_eq!(
+            Reason::AllowedTeam("org/admins".to_string()),
+            decision.reason
+        );
+
+        let decision = evaluate(Policy::new().allow_org("other-org")).await;
+        assert!(!decision.allowed);
+        assert_eq!(Reason::NoMatch, decision.reason);
+
+        // deny rules win over allow rules
+        let decision = evaluate(Policy::new().allow_user(USER_ID).deny_team("org/admins")).await;
+        assert!(!decision.allowed);
+        assert_eq!(
+            Reason::DeniedTeam("org/admins".to_string()),
+            decision.reason
+        );
+
+        // only verified addresses count
+        let decision = evaluate(
+            Policy::new()
+                .allow_user(USER_ID)
+                .require_email_domain("corp.example"),
+        )
+        .await;
+        assert!(!decision.allowed);
+        assert_eq!(Reason::MissingEmailDomain, decision.reason);
+
+        let decision = evaluate(
+            Policy::new()
+                .allow_user(USER_ID)
+                .require_email_domain("personal.example"),
+        )
+        .await;
+        assert!(decision.allowed);
+        assert_eq!(Reason::AllowedUser(USER_ID), decision.reason);
+
+        // merged policies need an email in each one's domains
+        let decision = evaluate(
+            Policy::new()
+                .allow_user(USER_ID)
+                .require_email_domain("personal.example")
+                .merge(Policy::new().require_email_domain("other.example")),
+        )
+        .await;
+        assert!(!decision.allowed);
+        assert_eq!(Reason::MissingEmailDomain, decision.reason);
+
+        fakehub.shutdown().await;
+    }
+}
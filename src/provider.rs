@@ -0,0 +1,285 @@
+use serde_json::Value;
+
+use crate::{error::Error, UserDetailResponse};
+
+/// A Github-compatible forge to log in with: where it serves OAuth and
+/// its API, and how it describes users.
+///
+/// Only logging in and looking up the user are mapped. Every other call
+/// goes to Github's path under the API base url, which a forge other
+/// than Github may not serve.
+///
+/// ```
+/// # use ghoauth::{GithubClient, Provider, UserFields};
+/// # fn main() -> Result<(), ghoauth::Error> {
+/// let forgejo = GithubClient::new_with_provider(
+///     "client id",
+///     "client secret",
+///     Provider::gitea("https://codeberg.org"),
+/// )?;
+///
+/// // a forge that calls its logins something else
+/// let provider = Provider {
+///     user_fields: UserFields {
+///         login: "username".to_owned(),
+///         ..UserFields::default()
+///     },
+///     ..Provider::gitea("https://git.example.com")
+/// };
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Provider {
+    /// The base url of the web host, where users authorize apps.
+    pub base_url: String,
+    /// The base url of the REST API.
+    pub api_base_url: String,
+    /// Where the GraphQL API is, if there is one.
+    pub graphql_url: Option<String>,
+    /// The path, under the base url, users authorize apps at.
+    pub authorize_path: String,
+    /// The path, under the base url, codes are exchanged for tokens at.
+    pub token_path: String,
+    /// The path, under the API base url, of the user a token belongs to.
+    pub user_path: String,
+    pub user_fields: UserFields,
+    /// Whether code exchanges name their grant type, which Github does
+    /// without and Gitea insists on.
+    pub send_grant_type: bool,
+}
+
+impl Provider {
+    /// github.com.
+    pub fn github() -> Self {
+        Self::custom("https://github.com", "https://api.github.com")
+    }
+
+    /// A Github Enterprise Server, which serves its API from the same
+    /// host as its website.
+    pub fn github_enterprise(base_url: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/');
+
+        Self {
+            graphql_url: Some(format!("{}/api/graphql", base_url)),
+            ..Self::custom(base_url, &format!("{}/api/v3", base_url))
+        }
+    }
+
+    /// A Gitea or Forgejo instance. Their OAuth endpoints are Github's,
+    /// but the API lives under `/api/v1` and has no GraphQL.
+    pub fn gitea(base_url: &str) -> Self {
+        let base_url = base_url.trim_end_matches('/');
+
+        Self {
+            graphql_url: None,
+            send_grant_type: true,
+            ..Self::custom(base_url, &format!("{}/api/v1", base_url))
+        }
+    }
+
+    /// Github, or something that looks exactly like it, at arbitrary
+    /// urls.
+    pub fn custom(base_url: &str, api_base_url: &str) -> Self {
+        let api_base_url = api_base_url.trim_end_matches('/');
+
+        Self {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            api_base_url: api_base_url.to_owned(),
+            graphql_url: Some(format!("{}/graphql", api_base_url)),
+            authorize_path: "/login/oauth/authorize".to_owned(),
+            token_path: "/login/oauth/access_token".to_owned(),
+            user_path: "/user".to_owned(),
+            user_fields: UserFields::default(),
+            send_grant_type: false,
+        }
+    }
+
+    pub(crate) fn authorize_url(&self) -> String {
+        format!("{}{}", self.base_url, self.authorize_path)
+    }
+
+    pub(crate) fn token_url(&self) -> String {
+        format!("{}{}", self.base_url, self.token_path)
+    }
+
+    pub(crate) fn graphql_url(&self) -> Result<&str, Error> {
+        self.graphql_url
+            .as_deref()
+            .ok_or_else(|| Error::Unsupported(format!("{} has no GraphQL API", self.base_url)))
+    }
+
+    /// Read the user a token belongs to out of the provider's own
+    /// description of them.
+    pub(crate) fn user_detail(&self, user: &Value) -> Result<UserDetailResponse, Error> {
+        let field = |name: &str| {
+            user.get(name)
+                .ok_or_else(|| Error::Decode(format!("user has no {} field", name)))
+        };
+        let string = |name: &str| {
+            field(name)?
+                .as_str()
+                .map(ToOwned::to_owned)
+                .ok_or_else(|| Error::Decode(format!("user field {} is not a string", name)))
+        };
+
+        Ok(UserDetailResponse {
+            id: field(&self.user_fields.id)?.as_i64().ok_or_else(|| {
+                Error::Decode(format!("user field {} is not an id", self.user_fields.id))
+            })?,
+            login: string(&self.user_fields.login)?,
+            avatar_url: string(&self.user_fields.avatar_url)?,
+            html_url: string(&self.user_fields.html_url)?,
+        })
+    }
+}
+
+impl Default for Provider {
+    fn default() -> Self {
+        Self::github()
+    }
+}
+
+/// The names of the fields of a provider's user that hold what goes in
+/// a [`UserDetailResponse`]. They default to Github's.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserFields {
+    pub id: String,
+    pub login: String,
+    pub avatar_url: String,
+    pub html_url: String,
+}
+
+impl Default for UserFields {
+    fn default() -> Self {
+        Self {
+            id: "id".to_owned(),
+            login: "login".to_owned(),
+            avatar_url: "avatar_url".to_owned(),
+            html_url: "html_url".to_owned(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "fakehub"))]
+mod tests {
+    use crate::{
+        fakehub::{Fakehub, Flavor},
+        testing::{add_test_user, CLIENT_ID, CLIENT_SECRET, USER, USER_ID},
+        GithubClient,
+    };
+
+    use super::{Provider, UserFields};
+
+    #[tokio::test]
+    async fn provider_flavors() {
+        for flavor in [Flavor::Github, Flavor::GithubEnterprise, Flavor::Gitea] {
+            let fakehub =
+                Fakehub::new_with_flavor(flavor).expect("cannot start local fakehub server");
+            let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+            add_test_user(&fakehub).await;
+
+            assert!(github_client.authorization_url().starts_with(&format!(
+                "{}/login/oauth/authorize?",
+                fakehub.github_dot_com_url()
+            )));
+
+            let code = fakehub.get_code(USER_ID).await.unwrap();
+            let token = github_client.get_access_token(&code).await.unwrap();
+            let user_detail = github_client
+                .get_user_detail(&token.access_token)
+                .await
+                .unwrap();
+            assert_eq!(USER_ID, user_detail.id);
+            assert_eq!(USER, user_detail.login);
+
+            // a code is good for one exchange, however the refusal is
+            // phrased
+            let refusal = github_client.get_access_token(&code).await.unwrap_err();
+            let viewer_identity = github_client.viewer_identity(&token.access_token).await;
+
+            match flavor {
+                Flavor::Github | Flavor::GithubEnterprise => {
+                    assert!(
+                        matches!(refusal, crate::Error::OAuth(error, _) if error == "bad_verification_code")
+                    );
+                    assert_eq!(USER, viewer_identity.unwrap().user.login);
+                    assert!(token.refresh_token.is_none());
+                }
+                Flavor::Gitea => {
+                    assert!(
+                        matches!(refusal, crate::Error::OAuth(error, _) if error == "invalid_grant")
+                    );
+                    assert!(matches!(viewer_identity, Err(crate::Error::Unsupported(_))));
+                    // gitea's tokens expire, and come without scopes
+                    assert!(token.refresh_token.is_some());
+                    assert_eq!("", token.scope);
+
+                    let refreshed = github_client
+                        .refresh_access_token(token.refresh_token.as_deref().unwrap())
+                        .await
+                        .unwrap();
+                    assert_ne!(token.access_token, refreshed.access_token);
+
+                    // the api lives under /api/v1 on the web host, and
+                    // only answers code exchanges that name their grant
+                    let api_user = reqwest::Client::new()
+                        .get(format!("{}/api/v1/user", fakehub.github_dot_com_url()))
+                        .header("Authorization", format!("token {}", refreshed.access_token))
+                        .send()
+                        .await
+                        .unwrap()
+                        .json::<serde_json::Value>()
+                        .await
+                        .unwrap();
+                    assert_eq!(USER, api_user["username"]);
+
+                    let without_grant_type = GithubClient::new_with_provider(
+                        CLIENT_ID,
+                        CLIENT_SECRET,
+                        Provider {
+                            send_grant_type: false,
+                            ..fakehub.provider()
+                        },
+                    )
+                    .unwrap();
+                    let code = fakehub.get_code(USER_ID).await.unwrap();
+                    assert!(matches!(
+                        without_grant_type.get_access_token(&code).await,
+                        Err(crate::Error::OAuth(error, _)) if error == "unsupported_grant_type"
+                    ));
+
+                    // a forge that names the login differently
+                    let mapped = GithubClient::new_with_provider(
+                        CLIENT_ID,
+                        CLIENT_SECRET,
+                        Provider {
+                            user_fields: UserFields {
+                                login: "username".to_owned(),
+                                ..UserFields::default()
+                            },
+                            ..fakehub.provider()
+                        },
+                    )
+                    .unwrap();
+                    assert_eq!(
+                        USER,
+                        mapped
+                            .get_user_detail(&refreshed.access_token)
+                            .await
+                            .unwrap()
+                            .login
+                    );
+                }
+            }
+
+            fakehub.shutdown().await;
+        }
+
+        // a provider whose authorize url doesn't parse never makes a client
+        assert!(matches!(
+            GithubClient::new_with_urls(CLIENT_ID, CLIENT_SECRET, "not a url", "not a url"),
+            Err(crate::Error::ClientCreation(_))
+        ));
+    }
+}
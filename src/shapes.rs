@@ -1,25 +1,103 @@
+#[cfg(feature = "app")]
+use std::collections::BTreeMap;
+
+#[cfg(feature = "app")]
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct GetAccessTokenResponse {
     pub access_token: String,
     pub token_type: String,
+    /// The scopes granted, comma separated. Gitea leaves this out.
+    #[serde(default)]
     pub scope: String,
+    /// Seconds until the access token expires, for apps that opted in to
+    /// expiring user tokens.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<i64>,
+    /// A token to get a new access token with once this one expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    /// Seconds until the refresh token expires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh_token_expires_in: Option<i64>,
 }
 
-// Custom debug printer omits the access token, which should never be
-// logged for security reasons.
+// Custom debug printer omits the access and refresh tokens, which should
+// never be logged for security reasons.
 impl std::fmt::Debug for GetAccessTokenResponse {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
             f,
             "GetAccessTokenResponse {{ access_token: REDACTED, token_type: \
-                {}, scope: {} }}",
-            self.token_type, self.scope,
+                {}, scope: {}, expires_in: {:?}, refresh_token: {}, \
+                refresh_token_expires_in: {:?} }}",
+            self.token_type,
+            self.scope,
+            self.expires_in,
+            self.refresh_token
+                .as_ref()
+                .map(|_| "REDACTED")
+                .unwrap_or("None"),
+            self.refresh_token_expires_in,
         )
     }
 }
 
+/// What the token endpoint answers a code exchange with. Github refuses
+/// with a 200 and an OAuth error rather than an error status.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum AccessTokenExchange {
+    Token(GetAccessTokenResponse),
+    Error {
+        error: String,
+        error_description: Option<String>,
+    },
+}
+
+impl AccessTokenExchange {
+    pub(crate) fn into_result(self) -> Result<GetAccessTokenResponse, crate::Error> {
+        match self {
+            Self::Token(token) => Ok(token),
+            Self::Error {
+                error,
+                error_description,
+            } => Err(crate::Error::OAuth(error, error_description)),
+        }
+    }
+
+    /// The error in the body of a 400, which is how providers other than
+    /// Github refuse, as the OAuth spec has it.
+    pub(crate) fn refusal(body: &str) -> crate::Error {
+        match serde_json::from_str(body) {
+            Ok(Self::Error {
+                error,
+                error_description,
+            }) => crate::Error::OAuth(error, error_description),
+            _ => crate::Error::Http(Some(400), body.to_owned()),
+        }
+    }
+}
+
+/// Optional parameters for the URL that starts the OAuth workflow.
+#[derive(Clone, Debug, Default)]
+pub struct AuthorizationRequest {
+    /// Where Github sends the user back to. Must fall under the callback
+    /// URL registered for the app; leave empty to use that URL as-is.
+    pub redirect_uri: Option<String>,
+    /// The scopes to request, eg. `read:org`.
+    pub scopes: Vec<String>,
+    /// An unguessable value Github echoes back to the callback, which
+    /// protects against cross-site request forgery.
+    pub state: Option<String>,
+    /// A PKCE `S256` challenge, see [`crate::Pkce`].
+    pub code_challenge: Option<String>,
+    /// Suggest a specific account to log in with.
+    pub login: Option<String>,
+}
+
 /// The structure we map the user details from Github onto for an
 /// internal user record.
 ///
@@ -29,10 +107,225 @@ impl std::fmt::Debug for GetAccessTokenResponse {
 /// The login pre-populates a user's identity, and the avatar and link
 /// to their github might become useful in the future, though it's not a
 /// sure thing.
-#[derive(Deserialize, Debug, Serialize)]
+#[derive(Deserialize, Debug, Serialize, Clone)]
 pub struct UserDetailResponse {
     pub id: i64,
     pub login: String,
     pub avatar_url: String,
     pub html_url: String,
 }
+
+/// How much a user may do in a repository, from least to most, so
+/// levels can be compared, eg. `permission >= Permission::Write`.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    None,
+    Read,
+    Triage,
+    Write,
+    Maintain,
+    Admin,
+}
+
+/// What the collaborator permission endpoint answers with. `permission`
+/// only knows the legacy admin, write and read levels, so `role_name`
+/// is preferred when it names a level rather than a custom role.
+#[derive(Deserialize)]
+pub(crate) struct CollaboratorPermission {
+    pub permission: Permission,
+    #[serde(default)]
+    pub role_name: Option<String>,
+}
+
+impl CollaboratorPermission {
+    pub(crate) fn level(&self) -> Permission {
+        match self.role_name.as_deref() {
+            Some("admin") => Permission::Admin,
+            Some("maintain") => Permission::Maintain,
+            Some("write") => Permission::Write,
+            Some("triage") => Permission::Triage,
+            Some("read") => Permission::Read,
+            _ => self.permission,
+        }
+    }
+}
+
+/// Which of a user's repositories to list: those they own, those
+/// they've been made a collaborator on, and those of organizations they
+/// belong to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Affiliation {
+    Owner,
+    Collaborator,
+    OrganizationMember,
+}
+
+impl Affiliation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Owner => "owner",
+            Self::Collaborator => "collaborator",
+            Self::OrganizationMember => "organization_member",
+        }
+    }
+}
+
+/// A repository, as listed for a user.
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Repository {
+    pub id: i64,
+    pub name: String,
+    pub full_name: String,
+    pub owner: RepositoryOwner,
+    pub private: bool,
+    pub html_url: String,
+    /// What the user the repository was listed for may do in it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<RepositoryPermissions>,
+}
+
+impl Repository {
+    /// The highest level the user the repository was listed for has.
+    pub fn permission(&self) -> Permission {
+        match &self.permissions {
+            Some(permissions) if permissions.admin => Permission::Admin,
+            Some(permissions) if permissions.maintain => Permission::Maintain,
+            Some(permissions) if permissions.push => Permission::Write,
+            Some(permissions) if permissions.triage => Permission::Triage,
+            Some(permissions) if permissions.pull => Permission::Read,
+            _ => Permission::None,
+        }
+    }
+}
+
+/// The user or organization a repository belongs to.
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct RepositoryOwner {
+    pub id: i64,
+    pub login: String,
+}
+
+/// Which levels a user has in a repository, the way Github lists them.
+#[derive(Deserialize, Debug, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct RepositoryPermissions {
+    pub admin: bool,
+    #[serde(default)]
+    pub maintain: bool,
+    pub push: bool,
+    #[serde(default)]
+    pub triage: bool,
+    pub pull: bool,
+}
+
+impl From<Permission> for RepositoryPermissions {
+    fn from(value: Permission) -> Self {
+        Self {
+            admin: value >= Permission::Admin,
+            maintain: value >= Permission::Maintain,
+            push: value >= Permission::Write,
+            triage: value >= Permission::Triage,
+            pull: value >= Permission::Read,
+        }
+    }
+}
+
+/// An organization a user belongs to.
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Organization {
+    pub id: i64,
+    pub login: String,
+}
+
+/// A team a user belongs to, along with the organization it is part of.
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Team {
+    pub id: i64,
+    pub name: String,
+    pub slug: String,
+    pub organization: Organization,
+}
+
+/// One of a user's email addresses. Only verified addresses are proof
+/// that the user controls them.
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub email: String,
+    pub primary: bool,
+    pub verified: bool,
+    #[serde(default)]
+    pub visibility: Option<String>,
+}
+
+#[cfg(feature = "app")]
+/// The account a Github App is installed on, either a user or an
+/// organization.
+#[derive(Deserialize, Debug, Serialize, Clone)]
+pub struct InstallationAccount {
+    pub id: i64,
+    pub login: String,
+}
+
+#[cfg(feature = "app")]
+/// An installation of a Github App.
+#[derive(Deserialize, Debug, Serialize, Clone)]
+pub struct Installation {
+    pub id: i64,
+    pub app_id: i64,
+    pub account: InstallationAccount,
+    /// Either `all` or `selected`.
+    pub repository_selection: String,
+    pub permissions: BTreeMap<String, String>,
+}
+
+#[cfg(feature = "app")]
+/// Narrows an installation access token to fewer repositories or
+/// permissions than the installation itself has. Leave everything
+/// empty for a token with all of the installation's access.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct InstallationTokenRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repositories: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository_ids: Option<Vec<i64>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<BTreeMap<String, String>>,
+}
+
+#[cfg(feature = "app")]
+/// A repository an installation access token is limited to.
+#[derive(Deserialize, Debug, Serialize, Clone)]
+pub struct TokenRepository {
+    pub id: i64,
+    pub name: String,
+    pub full_name: String,
+}
+
+#[cfg(feature = "app")]
+/// A short-lived `ghs_` token for acting as a Github App installation.
+#[derive(Deserialize, Serialize)]
+pub struct InstallationAccessToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+    #[serde(default)]
+    pub permissions: BTreeMap<String, String>,
+    #[serde(default)]
+    pub repository_selection: Option<String>,
+    #[serde(default)]
+    pub repositories: Option<Vec<TokenRepository>>,
+}
+
+// Custom debug printer omits the token, which should never be logged
+// for security reasons.
+#[cfg(feature = "app")]
+impl std::fmt::Debug for InstallationAccessToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "InstallationAccessToken {{ token: REDACTED, expires_at: {}, \
+                permissions: {:?}, repository_selection: {:?}, \
+                repositories: {:?} }}",
+            self.expires_at, self.permissions, self.repository_selection, self.repositories,
+        )
+    }
+}
@@ -0,0 +1,459 @@
+//! Spans and metrics for the requests the clients make to Github, with
+//! the `tracing` and `metrics` features respectively. Without them, all
+//! of this compiles away to nothing.
+//!
+//! Span fields follow the OpenTelemetry HTTP conventions, so the spans
+//! export cleanly through `tracing-opentelemetry`. Only the method and
+//! the route template are recorded about a request, never its URL,
+//! headers or body, so tokens and secrets stay out of traces.
+//!
+//! The metrics, and their labels, are:
+//!
+//! - `ghoauth_authorization_urls_total`: authorization URLs handed out.
+//! - `ghoauth_code_exchanges_total{outcome}`: codes exchanged for
+//!   tokens, by `success` or `failure`.
+//! - `ghoauth_code_exchange_failures_total{error}`: failed exchanges, by
+//!   OAuth error code, eg. `bad_verification_code`, or by `http_<status>`
+//!   or `transport` when Github couldn't be reached at all.
+//! - `ghoauth_request_duration_seconds{method, route, status}`: how long
+//!   requests to Github took.
+//! - `ghoauth_rate_limit_remaining`: requests left in Github's rate
+//!   limit window, as of the last response.
+
+use std::{future::Future, time::Instant};
+
+use reqwest::{header::HeaderMap, Method, StatusCode};
+
+use crate::{Error, GetAccessTokenResponse};
+
+/// The header Github identifies each request with, for support tickets.
+const REQUEST_ID_HEADER: &str = "x-github-request-id";
+/// The header with how many requests are left in the rate limit window.
+const RATE_LIMIT_REMAINING_HEADER: &str = "x-ratelimit-remaining";
+
+/// Count an authorization URL handed out, the top of the login funnel.
+pub(crate) fn authorization_url_issued() {
+    #[cfg(feature = "metrics")]
+    metrics::counter!("ghoauth_authorization_urls_total").increment(1);
+}
+
+/// Count a code exchanged for a token, or the reason it wasn't.
+pub(crate) fn code_exchanged(token: &Result<GetAccessTokenResponse, Error>) {
+    #[cfg(not(feature = "metrics"))]
+    let _ = token;
+
+    #[cfg(feature = "metrics")]
+    match token {
+        Ok(_) => {
+            metrics::counter!("ghoauth_code_exchanges_total", "outcome" => "success").increment(1)
+        }
+        Err(error) => {
+            let error = match error {
+                Error::OAuth(error, _) => error.clone(),
+                Error::Http(Some(status), _) => format!("http_{}", status),
+                _ => "transport".to_owned(),
+            };
+
+            metrics::counter!("ghoauth_code_exchanges_total", "outcome" => "failure").increment(1);
+            metrics::counter!("ghoauth_code_exchange_failures_total", "error" => error)
+                .increment(1);
+        }
+    }
+}
+
+/// Times a request to Github, and traces it.
+pub(crate) struct RequestSpan {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "metrics")]
+    method: Method,
+    #[cfg(feature = "metrics")]
+    route: &'static str,
+    started: Instant,
+}
+
+impl RequestSpan {
+    /// Start timing a request. The route is the path template, eg.
+    /// `/users/{username}`, rather than the path itself.
+    pub(crate) fn new(method: &Method, route: &'static str) -> Self {
+        #[cfg(not(any(feature = "tracing", feature = "metrics")))]
+        let _ = (method, route);
+
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                "github.request",
+                otel.name = %format_args!("{} {}", method, route),
+                otel.kind = "client",
+                otel.status_code = tracing::field::Empty,
+                http.request.method = %method,
+                http.route = route,
+                http.response.status_code = tracing::field::Empty,
+                github.request_id = tracing::field::Empty,
+                github.rate_limit.remaining = tracing::field::Empty,
+                latency_ms = tracing::field::Empty,
+            ),
+            #[cfg(feature = "metrics")]
+            method: method.clone(),
+            #[cfg(feature = "metrics")]
+            route,
+            started: Instant::now(),
+        }
+    }
+
+    /// Run the request within the span.
+    pub(crate) async fn instrument<F: Future>(&self, request: F) -> F::Output {
+        #[cfg(feature = "tracing")]
+        {
+            use tracing::Instrument;
+
+            request.instrument(self.span.clone()).await
+        }
+
+        #[cfg(not(feature = "tracing"))]
+        request.await
+    }
+
+    /// Run a blocking request within the span.
+    #[cfg(feature = "blocking")]
+    pub(crate) fn in_scope<T>(&self, request: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "tracing")]
+        return self.span.in_scope(request);
+
+        #[cfg(not(feature = "tracing"))]
+        request()
+    }
+
+    /// Record how the request went.
+    pub(crate) fn record(&self, status: Option<StatusCode>, headers: Option<&HeaderMap>) {
+        let latency = self.started.elapsed();
+        let header = |name: &str| {
+            headers
+                .and_then(|headers| headers.get(name))
+                .and_then(|value| value.to_str().ok())
+        };
+        let request_id = header(REQUEST_ID_HEADER);
+        let rate_limit_remaining =
+            header(RATE_LIMIT_REMAINING_HEADER).and_then(|remaining| remaining.parse::<u64>().ok());
+
+        #[cfg(not(feature = "tracing"))]
+        let _ = request_id;
+        #[cfg(not(any(feature = "tracing", feature = "metrics")))]
+        let _ = (status, latency, rate_limit_remaining);
+
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("latency_ms", latency.as_millis() as u64);
+            match status {
+                Some(status) => {
+                    self.span
+                        .record("http.response.status_code", status.as_u16());
+                    if status.is_client_error() || status.is_server_error() {
+                        self.span.record("otel.status_code", "ERROR");
+                    }
+                }
+                None => {
+                    self.span.record("otel.status_code", "ERROR");
+                }
+            }
+            if let Some(request_id) = request_id {
+                self.span.record("github.request_id", request_id);
+            }
+            if let Some(remaining) = rate_limit_remaining {
+                self.span.record("github.rate_limit.remaining", remaining);
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        {
+            let status = match status {
+                Some(status) => status.as_u16().to_string(),
+                None => "error".to_owned(),
+            };
+
+            metrics::histogram!(
+                "ghoauth_request_duration_seconds",
+                "method" => self.method.to_string(),
+                "route" => self.route,
+                "status" => status,
+            )
+            .record(latency.as_secs_f64());
+            if let Some(remaining) = rate_limit_remaining {
+                metrics::gauge!("ghoauth_rate_limit_remaining").set(remaining as f64);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "fakehub"))]
+mod tests {
+    #[cfg(feature = "tracing")]
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[cfg(any(feature = "tracing", feature = "metrics"))]
+    use crate::{
+        fakehub::Fakehub,
+        testing::{add_test_user, CLIENT_ID, CLIENT_SECRET, USER_ID},
+    };
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn request_spans() {
+        let recorder = SpanRecorder::default();
+        let _guard =
+            tracing::subscriber::set_default(tracing_subscriber::registry().with(recorder.clone()));
+
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+        add_test_user(&fakehub).await;
+
+        let code = fakehub.get_code(USER_ID).await.unwrap();
+        let token = github_client.get_access_token(&code).await.unwrap();
+        github_client
+            .get_user_detail(&token.access_token)
+            .await
+            .unwrap();
+        assert!(github_client.get_user_detail("not a token").await.is_err());
+
+        fakehub.shutdown().await;
+
+        let spans = recorder.0.lock().unwrap().join("\n");
+        assert!(spans.contains("http.route=\"/login/oauth/access_token\""));
+        assert!(spans.contains("http.route=\"/user\""));
+        assert!(spans.contains("http.response.status_code=200"));
+        assert!(spans.contains("http.response.status_code=401"));
+        assert!(spans.contains("github.request_id=\"FAKE:"));
+        assert!(spans.contains("github.rate_limit.remaining=5000"));
+        assert!(spans.contains("latency_ms="));
+        for secret in [CLIENT_SECRET, &code, &token.access_token, "not a token"] {
+            assert!(!spans.contains(secret), "{} leaked into a span", secret);
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn login_funnel_metrics() {
+        let recorder = MetricsRecorder::default();
+        let fakehub_counters = metrics::with_local_recorder(&recorder, || {
+            // a runtime on this thread, so everything it runs sees the
+            // local recorder
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+
+            runtime.block_on(async {
+                let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+                let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+                add_test_user(&fakehub).await;
+
+                github_client.authorization_url();
+                let code = fakehub.get_code(USER_ID).await.unwrap();
+                let token = github_client.get_access_token(&code).await.unwrap();
+                github_client
+                    .get_user_detail(&token.access_token)
+                    .await
+                    .unwrap();
+                assert!(matches!(
+                    github_client.get_access_token(&code).await,
+                    Err(crate::Error::OAuth(error, Some(_))) if error == "bad_verification_code"
+                ));
+
+                let counters = fakehub.counters().await;
+                fakehub.shutdown().await;
+                counters
+            })
+        });
+
+        let metrics = recorder.0.lock().unwrap();
+        assert_eq!(Some(&1.0), metrics.get("ghoauth_authorization_urls_total"));
+        assert_eq!(
+            Some(&1.0),
+            metrics.get("ghoauth_code_exchanges_total{outcome=success}")
+        );
+        assert_eq!(
+            Some(&1.0),
+            metrics.get("ghoauth_code_exchanges_total{outcome=failure}")
+        );
+        assert_eq!(
+            Some(&1.0),
+            metrics.get("ghoauth_code_exchange_failures_total{error=bad_verification_code}")
+        );
+        assert_eq!(
+            Some(&1.0),
+            metrics.get("ghoauth_request_duration_seconds{method=GET,route=/user,status=200}")
+        );
+        assert_eq!(Some(&5000.0), metrics.get("ghoauth_rate_limit_remaining"));
+
+        assert_eq!(1, fakehub_counters.codes_issued);
+        assert_eq!(1, fakehub_counters.tokens_minted);
+        assert_eq!(1, fakehub_counters.code_exchange_failures);
+    }
+
+    /// Records every span and event, with all of their fields, as text.
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct SpanRecorder(std::sync::Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[cfg(feature = "tracing")]
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for SpanRecorder {
+        fn on_new_span(
+            &self,
+            attrs: &tracing::span::Attributes<'_>,
+            _id: &tracing::span::Id,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = FieldRecorder(attrs.metadata().name().to_string());
+            attrs.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+
+        fn on_record(
+            &self,
+            _id: &tracing::span::Id,
+            values: &tracing::span::Record<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = FieldRecorder(String::new());
+            values.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            let mut fields = FieldRecorder(event.metadata().name().to_string());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    struct FieldRecorder(String);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::field::Visit for FieldRecorder {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+
+    /// Keeps the value of every counter and gauge, and the number of
+    /// samples in every histogram, by name and labels.
+    #[cfg(feature = "metrics")]
+    #[derive(Clone, Default)]
+    struct MetricsRecorder(
+        std::sync::Arc<std::sync::Mutex<std::collections::BTreeMap<String, f64>>>,
+    );
+
+    #[cfg(feature = "metrics")]
+    impl MetricsRecorder {
+        fn handle(&self, key: &metrics::Key) -> std::sync::Arc<MetricHandle> {
+            let labels = key
+                .labels()
+                .map(|label| format!("{}={}", label.key(), label.value()))
+                .collect::<Vec<_>>();
+            let name = match labels.is_empty() {
+                true => key.name().to_string(),
+                false => format!("{}{{{}}}", key.name(), labels.join(",")),
+            };
+
+            std::sync::Arc::new(MetricHandle(name, self.0.clone()))
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    impl metrics::Recorder for MetricsRecorder {
+        fn describe_counter(
+            &self,
+            _: metrics::KeyName,
+            _: Option<metrics::Unit>,
+            _: metrics::SharedString,
+        ) {
+        }
+
+        fn describe_gauge(
+            &self,
+            _: metrics::KeyName,
+            _: Option<metrics::Unit>,
+            _: metrics::SharedString,
+        ) {
+        }
+
+        fn describe_histogram(
+            &self,
+            _: metrics::KeyName,
+            _: Option<metrics::Unit>,
+            _: metrics::SharedString,
+        ) {
+        }
+
+        fn register_counter(
+            &self,
+            key: &metrics::Key,
+            _: &metrics::Metadata<'_>,
+        ) -> metrics::Counter {
+            metrics::Counter::from_arc(self.handle(key))
+        }
+
+        fn register_gauge(&self, key: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Gauge {
+            metrics::Gauge::from_arc(self.handle(key))
+        }
+
+        fn register_histogram(
+            &self,
+            key: &metrics::Key,
+            _: &metrics::Metadata<'_>,
+        ) -> metrics::Histogram {
+            metrics::Histogram::from_arc(self.handle(key))
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    struct MetricHandle(
+        String,
+        std::sync::Arc<std::sync::Mutex<std::collections::BTreeMap<String, f64>>>,
+    );
+
+    #[cfg(feature = "metrics")]
+    impl MetricHandle {
+        fn update(&self, f: impl FnOnce(&mut f64)) {
+            f(self.1.lock().unwrap().entry(self.0.clone()).or_default());
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    impl metrics::CounterFn for MetricHandle {
+        fn increment(&self, value: u64) {
+            self.update(|total| *total += value as f64);
+        }
+
+        fn absolute(&self, value: u64) {
+            self.update(|total| *total = value as f64);
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    impl metrics::GaugeFn for MetricHandle {
+        fn increment(&self, value: f64) {
+            self.update(|total| *total += value);
+        }
+
+        fn decrement(&self, value: f64) {
+            self.update(|total| *total -= value);
+        }
+
+        fn set(&self, value: f64) {
+            self.update(|total| *total = value);
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    impl metrics::HistogramFn for MetricHandle {
+        fn record(&self, _: f64) {
+            self.update(|samples| *samples += 1.0);
+        }
+    }
+}
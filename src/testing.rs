@@ -0,0 +1,116 @@
+//! Fixtures shared by the tests of every module that drives a Fakehub.
+
+use std::net::{SocketAddr, TcpListener};
+
+use axum::{
+    extract::{FromRef, State},
+    routing::post,
+    Router, Server,
+};
+use reqwest::header::HeaderMap;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    fakehub::{Fakehub, User},
+    interceptor::{InterceptedRequest, InterceptedResponse, Interceptor, Outcome},
+    webhooks::{VerifiedWebhook, WebhookEvent, WebhookSecret},
+};
+
+pub(crate) const CLIENT_ID: &str = "1234567890";
+pub(crate) const CLIENT_SECRET: &str = "SECRET_SQUIRREL_STUFF";
+pub(crate) const USER: &str = "user";
+pub(crate) const USER_ID: i64 = 1;
+pub(crate) const USER_AVATAR_URL: &str = "https://github.com/";
+pub(crate) const USER_HTML_URL: &str = "https://github.com/";
+pub(crate) const WEBHOOK_SECRET: &str = "It's a Secret to Everybody";
+pub(crate) const ORG: &str = "org";
+pub(crate) const ORG_ID: i64 = 100;
+pub(crate) const TEAM_ID: i64 = 150;
+pub(crate) const APP_ID: i64 = 200;
+pub(crate) const REPO_ID: i64 = 250;
+pub(crate) const INSTALLATION_ID: i64 = 300;
+pub(crate) const AUDIENCE: &str = "https://deploy.example.com";
+pub(crate) const APP_PRIVATE_KEY: &str = include_str!("../testdata/app_private_key.pem");
+pub(crate) const APP_PUBLIC_KEY: &str = include_str!("../testdata/app_public_key.pem");
+pub(crate) const GPG_PUBLIC_KEY: &str = include_str!("../testdata/gpg_public_key.asc");
+
+/// Add the user most tests log in as.
+pub(crate) async fn add_test_user(fakehub: &Fakehub) {
+    fakehub.add_user(USER_ID, user_named(USER)).await;
+}
+
+/// A user like the one most tests log in as, but for their login.
+pub(crate) fn user_named(login: &str) -> User {
+    User {
+        login: login.to_string(),
+        avatar_url: USER_AVATAR_URL.to_string(),
+        html_url: USER_HTML_URL.to_string(),
+    }
+}
+
+#[derive(Clone)]
+struct WebhookReceiver {
+    secret: WebhookSecret,
+    events: UnboundedSender<WebhookEvent>,
+}
+
+impl FromRef<WebhookReceiver> for WebhookSecret {
+    fn from_ref(receiver: &WebhookReceiver) -> Self {
+        receiver.secret.clone()
+    }
+}
+
+/// Receive webhooks on a free port, verified with the secret.
+pub(crate) fn start_webhook_receiver(
+    secret: &str,
+) -> (SocketAddr, UnboundedReceiver<WebhookEvent>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let socket_addr = listener.local_addr().unwrap();
+    let (tx, rx) = unbounded_channel();
+    let app = Router::new()
+        .route(
+            "/hooks",
+            post(
+                |State(receiver): State<WebhookReceiver>, webhook: VerifiedWebhook| async move {
+                    receiver.events.send(webhook.event().unwrap()).ok();
+                },
+            ),
+        )
+        .with_state(WebhookReceiver {
+            secret: WebhookSecret::new(secret),
+            events: tx,
+        });
+
+    tokio::spawn(
+        Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+
+    (socket_addr, rx)
+}
+
+/// Records the path, headers, response status and response body of
+/// every request.
+#[derive(Clone, Default)]
+pub(crate) struct Audit(pub(crate) std::sync::Arc<std::sync::Mutex<Vec<Audited>>>);
+
+pub(crate) type Audited = (String, HeaderMap, u16, Vec<u8>);
+
+#[async_trait::async_trait]
+impl Interceptor for Audit {
+    async fn after(
+        &self,
+        request: &InterceptedRequest,
+        response: &mut InterceptedResponse,
+    ) -> Result<Outcome, crate::Error> {
+        self.0.lock().unwrap().push((
+            request.url().path().to_owned(),
+            request.headers(),
+            response.status.as_u16(),
+            response.body.clone(),
+        ));
+
+        Ok(Outcome::Continue)
+    }
+}
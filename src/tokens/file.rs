@@ -0,0 +1,199 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use aes_gcm::{aead::Aead, Aes256Gcm, Key, KeyInit, Nonce};
+use async_trait::async_trait;
+use rand::RngCore;
+
+use crate::error::Error;
+
+use super::{TokenSet, TokenStore};
+
+/// Bytes of the random nonce each write of the file starts with.
+const NONCE_LENGTH: usize = 12;
+
+/// Keeps tokens in a file, encrypted with AES-256-GCM, so they survive
+/// restarts without lying around in the clear.
+///
+/// The file is small and rewritten whole on every save, so this suits a
+/// CLI or a single service, not many processes sharing one file. It's
+/// read and written on tokio's blocking threads, so a slow disk doesn't
+/// hold up the runtime.
+pub struct EncryptedFileTokenStore {
+    file: Arc<EncryptedFile>,
+}
+
+struct EncryptedFile {
+    path: PathBuf,
+    cipher: Aes256Gcm,
+    /// Serializes reads and writes of the file within this process.
+    lock: Mutex<()>,
+}
+
+impl EncryptedFileTokenStore {
+    /// Keep tokens in the file at the path, encrypted with the key. The
+    /// file is created on the first save.
+    pub fn new(path: impl Into<PathBuf>, key: &[u8; 32]) -> Self {
+        Self {
+            file: Arc::new(EncryptedFile {
+                path: path.into(),
+                cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+                lock: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// A fresh random key. Keep it somewhere other than next to the file.
+    pub fn generate_key() -> [u8; 32] {
+        let mut key = [0u8; 32];
+
+        rand::thread_rng().fill_bytes(&mut key);
+
+        key
+    }
+
+    /// Run a read or write of the file on a blocking thread, with the
+    /// file to itself.
+    async fn with_file<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&EncryptedFile) -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error> {
+        let file = self.file.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let _lock = file.lock.lock().unwrap();
+
+            f(&file)
+        })
+        .await
+        .map_err(store_error)?
+    }
+}
+
+impl EncryptedFile {
+    fn read(&self) -> Result<HashMap<String, TokenSet>, Error> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(error) => return Err(store_error(error)),
+        };
+
+        if contents.len() < NONCE_LENGTH {
+            return Err(Error::TokenStore(format!(
+                "{} is truncated",
+                self.path.display()
+            )));
+        }
+
+        let (nonce, ciphertext) = contents.split_at(NONCE_LENGTH);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| {
+                Error::TokenStore(format!(
+                    "cannot decrypt {}, is it the right key?",
+                    self.path.display()
+                ))
+            })?;
+
+        serde_json::from_slice(&plaintext).map_err(store_error)
+    }
+
+    fn write(&self, token_sets: &HashMap<String, TokenSet>) -> Result<(), Error> {
+        let plaintext = serde_json::to_vec(token_sets).map_err(store_error)?;
+        let mut nonce = [0u8; NONCE_LENGTH];
+
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice())
+            .map_err(|_| Error::TokenStore("cannot encrypt tokens".to_owned()))?;
+
+        // write alongside and rename over, so a crash mid-write can't
+        // leave a corrupt file behind
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        let mut file = create_private(&temporary).map_err(store_error)?;
+
+        file.write_all(&nonce).map_err(store_error)?;
+        file.write_all(&ciphertext).map_err(store_error)?;
+        file.sync_all().map_err(store_error)?;
+        fs::rename(&temporary, &self.path).map_err(store_error)
+    }
+}
+
+/// Create a file only its owner can read.
+fn create_private(path: &std::path::Path) -> std::io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        options.mode(0o600);
+    }
+
+    options.open(path)
+}
+
+fn store_error(error: impl std::fmt::Display) -> Error {
+    Error::TokenStore(error.to_string())
+}
+
+#[async_trait]
+impl TokenStore for EncryptedFileTokenStore {
+    async fn load(&self, key: &str) -> Result<Option<TokenSet>, Error> {
+        let key = key.to_owned();
+
+        self.with_file(move |file| Ok(file.read()?.remove(&key)))
+            .await
+    }
+
+    async fn save(&self, key: &str, token_set: &TokenSet) -> Result<(), Error> {
+        let key = key.to_owned();
+        let token_set = token_set.clone();
+
+        self.with_file(move |file| {
+            let mut token_sets = file.read()?;
+
+            token_sets.insert(key, token_set);
+            file.write(&token_sets)
+        })
+        .await
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), Error> {
+        let key = key.to_owned();
+
+        self.with_file(move |file| {
+            let mut token_sets = file.read()?;
+
+            if token_sets.remove(&key).is_some() {
+                file.write(&token_sets)?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+// Custom debug printer omits the key.
+impl std::fmt::Debug for EncryptedFileTokenStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "EncryptedFileTokenStore {{ path: {}, key: REDACTED }}",
+            self.file.path.display()
+        )
+    }
+}
@@ -0,0 +1,336 @@
+//! Keeping user tokens between restarts, and refreshing them before
+//! they expire.
+//!
+//! A [`TokenSet`] is everything worth remembering about a token, and a
+//! [`TokenStore`] is somewhere to remember it. [`AuthorizedClient`] ties
+//! the two to a [`GithubApi`], loading the token for a key from the store
+//! and refreshing it when it's about to expire.
+//!
+//! ```no_run
+//! # use ghoauth::{tokens::{AuthorizedClient, MemoryTokenStore}, GithubClient};
+//! # async fn login(code: &str) -> Result<(), ghoauth::Error> {
+//! let github_client = GithubClient::new("client id", "client secret")?;
+//! let authorized = AuthorizedClient::new(github_client, MemoryTokenStore::new(), "octocat");
+//!
+//! authorized.authorize(code).await?;
+//!
+//! // later, perhaps after the token has been refreshed along the way
+//! let user = authorized.get_user_detail().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    error::Error,
+    shapes::{Email, GetAccessTokenResponse, Organization, Team},
+    GithubApi, UserDetailResponse,
+};
+
+#[cfg(feature = "encrypted-store")]
+mod file;
+mod store;
+
+#[cfg(feature = "encrypted-store")]
+pub use self::file::EncryptedFileTokenStore;
+pub use self::store::{MemoryTokenStore, TokenStore};
+
+/// How long before a token expires [`AuthorizedClient`] refreshes it.
+const DEFAULT_REFRESH_MARGIN: Duration = Duration::minutes(5);
+
+/// A user token and what is known about it, ready to be stored.
+#[derive(Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TokenSet {
+    pub access_token: String,
+    pub token_type: String,
+    pub scopes: Vec<String>,
+    /// When the access token expires, or `None` if it never does.
+    pub expires_at: Option<DateTime<Utc>>,
+    pub refresh_token: Option<String>,
+    pub refresh_token_expires_at: Option<DateTime<Utc>>,
+    pub issued_at: DateTime<Utc>,
+}
+
+impl TokenSet {
+    /// Whether the access token expires within the given margin, or
+    /// already has.
+    pub fn expires_within(&self, margin: Duration) -> bool {
+        self.expires_at
+            .map(|expires_at| expires_at - margin <= Utc::now())
+            .unwrap_or(false)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_within(Duration::zero())
+    }
+
+    /// Whether there is a refresh token that is still good.
+    pub fn can_refresh(&self) -> bool {
+        match (&self.refresh_token, self.refresh_token_expires_at) {
+            (Some(_), Some(expires_at)) => expires_at > Utc::now(),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+}
+
+impl From<GetAccessTokenResponse> for TokenSet {
+    fn from(value: GetAccessTokenResponse) -> Self {
+        let issued_at = Utc::now();
+
+        Self {
+            access_token: value.access_token,
+            token_type: value.token_type,
+            scopes: value
+                .scope
+                .split(',')
+                .map(str::trim)
+                .filter(|scope| !scope.is_empty())
+                .map(str::to_owned)
+                .collect(),
+            expires_at: value
+                .expires_in
+                .map(|seconds| issued_at + Duration::seconds(seconds)),
+            refresh_token: value.refresh_token,
+            refresh_token_expires_at: value
+                .refresh_token_expires_in
+                .map(|seconds| issued_at + Duration::seconds(seconds)),
+            issued_at,
+        }
+    }
+}
+
+// Custom debug printer omits the access and refresh tokens, which should
+// never be logged for security reasons.
+impl std::fmt::Debug for TokenSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "TokenSet {{ access_token: REDACTED, token_type: {}, scopes: {:?}, \
+            expires_at: {:?}, refresh_token: {}, refresh_token_expires_at: {:?}, \
+            issued_at: {} }}",
+            self.token_type,
+            self.scopes,
+            self.expires_at,
+            self.refresh_token
+                .as_ref()
+                .map(|_| "REDACTED")
+                .unwrap_or("None"),
+            self.refresh_token_expires_at,
+            self.issued_at,
+        )
+    }
+}
+
+/// A [`GithubApi`] acting as one user, whose token is kept in a
+/// [`TokenStore`] and refreshed whenever it is about to expire.
+#[derive(Clone)]
+pub struct AuthorizedClient {
+    github: Arc<dyn GithubApi>,
+    store: Arc<dyn TokenStore>,
+    key: String,
+    refresh_margin: Duration,
+    /// Refresh tokens can only be used once, so refreshes mustn't race.
+    refreshing: Arc<Mutex<()>>,
+}
+
+impl AuthorizedClient {
+    /// Act as whoever's token is stored under the key.
+    pub fn new(github: impl GithubApi + 'static, store: impl TokenStore, key: &str) -> Self {
+        Self {
+            github: Arc::new(github),
+            store: Arc::new(store),
+            key: key.to_owned(),
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+            refreshing: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// How long before a token expires to refresh it. Five minutes
+    /// unless set.
+    pub fn refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.refresh_margin = refresh_margin;
+        self
+    }
+
+    /// Exchange a login code for a token, and store it.
+    pub async fn authorize(&self, code: &str) -> Result<TokenSet, Error> {
+        let token_set = TokenSet::from(self.github.get_access_token(code).await?);
+
+        self.store.save(&self.key, &token_set).await?;
+
+        Ok(token_set)
+    }
+
+    /// The stored token, refreshed first if it is about to expire.
+    pub async fn token_set(&self) -> Result<TokenSet, Error> {
+        let _refreshing = self.refreshing.lock().await;
+        let token_set = self
+            .store
+            .load(&self.key)
+            .await?
+            .ok_or_else(|| Error::TokenStore(format!("no token stored for {}", self.key)))?;
+
+        if !token_set.expires_within(self.refresh_margin) {
+            return Ok(token_set);
+        }
+
+        match token_set.refresh_token.as_deref() {
+            Some(refresh_token) if token_set.can_refresh() => {
+                let refreshed =
+                    TokenSet::from(self.github.refresh_access_token(refresh_token).await?);
+
+                self.store.save(&self.key, &refreshed).await?;
+
+                Ok(refreshed)
+            }
+            _ if token_set.is_expired() => Err(Error::TokenExpired),
+            // it'll do until it expires
+            _ => Ok(token_set),
+        }
+    }
+
+    pub async fn access_token(&self) -> Result<String, Error> {
+        Ok(self.token_set().await?.access_token)
+    }
+
+    /// Forget the stored token.
+    pub async fn logout(&self) -> Result<(), Error> {
+        self.store.remove(&self.key).await
+    }
+
+    pub async fn get_user_detail(&self) -> Result<UserDetailResponse, Error> {
+        self.github
+            .get_user_detail(&self.access_token().await?)
+            .await
+    }
+
+    pub async fn list_user_orgs(&self) -> Result<Vec<Organization>, Error> {
+        self.github
+            .list_user_orgs(&self.access_token().await?)
+            .await
+    }
+
+    pub async fn list_user_teams(&self) -> Result<Vec<Team>, Error> {
+        self.github
+            .list_user_teams(&self.access_token().await?)
+            .await
+    }
+
+    pub async fn list_user_emails(&self) -> Result<Vec<Email>, Error> {
+        self.github
+            .list_user_emails(&self.access_token().await?)
+            .await
+    }
+}
+
+impl std::fmt::Debug for AuthorizedClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "AuthorizedClient {{ key: {}, refresh_margin: {} }}",
+            self.key, self.refresh_margin,
+        )
+    }
+}
+
+#[cfg(all(test, feature = "fakehub", feature = "encrypted-store"))]
+mod tests {
+    use crate::{
+        fakehub::Fakehub,
+        testing::{add_test_user, CLIENT_ID, CLIENT_SECRET, USER, USER_ID},
+    };
+
+    use super::{
+        AuthorizedClient, EncryptedFileTokenStore, MemoryTokenStore, TokenSet, TokenStore,
+    };
+
+    #[tokio::test]
+    async fn token_refresh() {
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let github_client = fakehub.add_client(CLIENT_ID, CLIENT_SECRET).await.unwrap();
+        add_test_user(&fakehub).await;
+        fakehub.expire_user_tokens(chrono::Duration::hours(8)).await;
+
+        let path = std::env::temp_dir().join(format!("ghoauth-tokens-{}.json", std::process::id()));
+        // a neighbour that writing the file mustn't touch
+        let neighbour = path.with_extension("tmp");
+        std::fs::write(&neighbour, "not tokens").unwrap();
+        let key = EncryptedFileTokenStore::generate_key();
+        let authorized = AuthorizedClient::new(
+            github_client.clone(),
+            EncryptedFileTokenStore::new(&path, &key),
+            USER,
+        );
+        // the same file, as another process would see it
+        let store = EncryptedFileTokenStore::new(&path, &key);
+
+        let code = fakehub.get_code(USER_ID).await.unwrap();
+        let token_set = authorized.authorize(&code).await.unwrap();
+        assert!(token_set.can_refresh());
+        assert!(!token_set.expires_within(chrono::Duration::hours(7)));
+        assert_eq!(Some(token_set.clone()), store.load(USER).await.unwrap());
+        assert!(!std::fs::read(&path)
+            .unwrap()
+            .windows(token_set.access_token.len())
+            .any(|window| window == token_set.access_token.as_bytes()));
+        assert_eq!(USER, authorized.get_user_detail().await.unwrap().login);
+
+        // close enough to expiry that it's refreshed before use
+        let expiring = TokenSet {
+            expires_at: Some(chrono::Utc::now() + chrono::Duration::minutes(1)),
+            ..token_set.clone()
+        };
+        store.save(USER, &expiring).await.unwrap();
+        assert_eq!(USER, authorized.get_user_detail().await.unwrap().login);
+
+        let refreshed = store.load(USER).await.unwrap().unwrap();
+        assert_ne!(token_set.access_token, refreshed.access_token);
+        assert_ne!(token_set.refresh_token, refreshed.refresh_token);
+        assert!(matches!(
+            github_client
+                .refresh_access_token(token_set.refresh_token.as_deref().unwrap())
+                .await,
+            Err(crate::Error::OAuth(error, _)) if error == "bad_refresh_token"
+        ));
+
+        let wrong_key =
+            EncryptedFileTokenStore::new(&path, &EncryptedFileTokenStore::generate_key());
+        assert!(matches!(
+            wrong_key.load(USER).await,
+            Err(crate::Error::TokenStore(_))
+        ));
+
+        authorized.logout().await.unwrap();
+        assert_eq!(None, store.load(USER).await.unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!("not tokens", std::fs::read_to_string(&neighbour).unwrap());
+        std::fs::remove_file(&neighbour).unwrap();
+
+        // without a refresh token, an expired token is no use
+        let memory = MemoryTokenStore::new();
+        memory
+            .save(
+                USER,
+                &TokenSet {
+                    expires_at: Some(chrono::Utc::now() - chrono::Duration::minutes(1)),
+                    refresh_token: None,
+                    ..refreshed
+                },
+            )
+            .await
+            .unwrap();
+        let authorized = AuthorizedClient::new(github_client, memory, USER);
+        assert!(matches!(
+            authorized.get_user_detail().await,
+            Err(crate::Error::TokenExpired)
+        ));
+
+        fakehub.shutdown().await;
+    }
+}
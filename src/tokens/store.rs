@@ -0,0 +1,51 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use async_trait::async_trait;
+
+use crate::error::Error;
+
+use super::TokenSet;
+
+/// Somewhere to keep tokens between restarts, keyed by whatever the
+/// application knows its users by. Implement this over your database or
+/// secret manager of choice.
+#[async_trait]
+pub trait TokenStore: Send + Sync + 'static {
+    async fn load(&self, key: &str) -> Result<Option<TokenSet>, Error>;
+    async fn save(&self, key: &str, token_set: &TokenSet) -> Result<(), Error>;
+    async fn remove(&self, key: &str) -> Result<(), Error>;
+}
+
+/// Keeps tokens in memory, so they are lost when the process exits.
+#[derive(Debug, Default)]
+pub struct MemoryTokenStore {
+    token_sets: Mutex<HashMap<String, TokenSet>>,
+}
+
+impl MemoryTokenStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl TokenStore for MemoryTokenStore {
+    async fn load(&self, key: &str) -> Result<Option<TokenSet>, Error> {
+        Ok(self.token_sets.lock().unwrap().get(key).cloned())
+    }
+
+    async fn save(&self, key: &str, token_set: &TokenSet) -> Result<(), Error> {
+        self.token_sets
+            .lock()
+            .unwrap()
+            .insert(key.to_owned(), token_set.clone());
+
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), Error> {
+        self.token_sets.lock().unwrap().remove(key);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+//! How [`GithubClient`](crate::GithubClient) sends its requests. By
+//! default they go over the network through reqwest; with the `tower`
+//! feature, a [`ServiceTransport`] hands them to a `tower::Service`,
+//! such as an axum `Router`, without ever touching a socket.
+//!
+//! ```no_run
+//! # use ghoauth::{transport::ServiceTransport, GithubClient};
+//! # fn client(router: axum::Router) -> Result<GithubClient, ghoauth::Error> {
+//! let github_client = GithubClient::new_with_urls(
+//!     "client id",
+//!     "client secret",
+//!     "http://github.invalid",
+//!     "http://github.invalid",
+//! )?
+//! .with_transport(ServiceTransport::new(router));
+//! # Ok(github_client)
+//! # }
+//! ```
+
+use async_trait::async_trait;
+use reqwest::{Client as ReqwestClient, Request, Response};
+
+use crate::error::Error;
+
+/// Sends a [`GithubClient`](crate::GithubClient)'s requests, after its
+/// interceptors have had their way with them, and brings back the
+/// responses.
+#[async_trait]
+pub trait Transport: Send + Sync + 'static {
+    async fn send(&self, request: Request) -> Result<Response, Error>;
+}
+
+#[async_trait]
+impl Transport for ReqwestClient {
+    async fn send(&self, request: Request) -> Result<Response, Error> {
+        Ok(self.execute(request).await?)
+    }
+}
+
+#[cfg(feature = "tower")]
+pub use self::service::ServiceTransport;
+
+#[cfg(feature = "tower")]
+mod service {
+    use std::{fmt::Display, future::poll_fn, marker::PhantomData, sync::Mutex};
+
+    use async_trait::async_trait;
+    use bytes::Buf;
+    use http::header::HOST;
+    use http_body::Body;
+    use reqwest::{header::HeaderValue, Request, Response};
+    use tower_service::Service;
+
+    use super::Transport;
+    use crate::error::Error;
+
+    /// A transport that calls a `tower::Service` in memory with each
+    /// request, as though the service were listening at the request's
+    /// URL. Requests keep their absolute URL and get the `Host` header
+    /// reqwest would have sent.
+    pub struct ServiceTransport<S, B> {
+        // services like axum's Router are Send but not Sync, so each
+        // request gets a clone of its own
+        service: Mutex<S>,
+        request_body: PhantomData<fn() -> B>,
+    }
+
+    impl<S, B> ServiceTransport<S, B> {
+        pub fn new(service: S) -> Self {
+            Self {
+                service: Mutex::new(service),
+                request_body: PhantomData,
+            }
+        }
+    }
+
+    impl<S: Clone, B> ServiceTransport<S, B> {
+        fn service(&self) -> S {
+            self.service.lock().expect("service lock poisoned").clone()
+        }
+    }
+
+    impl<S: Clone, B> Clone for ServiceTransport<S, B> {
+        fn clone(&self) -> Self {
+            Self::new(self.service())
+        }
+    }
+
+    impl<S, B> std::fmt::Debug for ServiceTransport<S, B> {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.debug_struct("ServiceTransport").finish_non_exhaustive()
+        }
+    }
+
+    #[async_trait]
+    impl<S, B, R> Transport for ServiceTransport<S, B>
+    where
+        S: Service<http::Request<B>, Response = http::Response<R>> + Clone + Send + 'static,
+        S::Error: Display,
+        S::Future: Send,
+        B: From<Vec<u8>> + Send + 'static,
+        R: Body + Send + 'static,
+        R::Data: Send,
+        R::Error: Display,
+    {
+        async fn send(&self, request: Request) -> Result<Response, Error> {
+            let request = into_http_request(request)?;
+            let mut service = self.service();
+
+            poll_fn(|cx| service.poll_ready(cx))
+                .await
+                .map_err(|error| Error::OtherHttp(error.to_string()))?;
+
+            let (parts, body) = service
+                .call(request)
+                .await
+                .map_err(|error| Error::OtherHttp(error.to_string()))?
+                .into_parts();
+            let mut body = Box::pin(body);
+            let mut bytes = Vec::new();
+
+            while let Some(chunk) = poll_fn(|cx| body.as_mut().poll_data(cx)).await {
+                let mut chunk = chunk.map_err(|error| Error::OtherHttp(error.to_string()))?;
+
+                while chunk.has_remaining() {
+                    let read = chunk.chunk();
+                    let len = read.len();
+
+                    bytes.extend_from_slice(read);
+                    chunk.advance(len);
+                }
+            }
+
+            Ok(http::Response::from_parts(parts, bytes).into())
+        }
+    }
+
+    /// Turn a reqwest request into the `http` request a server would
+    /// have read off the wire.
+    fn into_http_request<B: From<Vec<u8>>>(request: Request) -> Result<http::Request<B>, Error> {
+        let body = match request.body() {
+            Some(body) => body
+                .as_bytes()
+                .ok_or_else(|| {
+                    Error::OtherHttp("streamed bodies cannot be sent in memory".to_owned())
+                })?
+                .to_vec(),
+            None => Vec::new(),
+        };
+        let url = request.url();
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => return Err(Error::OtherHttp(format!("{} has no host", url))),
+        };
+        let mut http_request = http::Request::builder()
+            .method(request.method().clone())
+            .uri(url.as_str())
+            .body(B::from(body))
+            .map_err(|error| Error::OtherHttp(error.to_string()))?;
+
+        *http_request.headers_mut() = request.headers().clone();
+        http_request.headers_mut().insert(
+            HOST,
+            HeaderValue::from_str(&host).map_err(|error| Error::OtherHttp(error.to_string()))?,
+        );
+
+        Ok(http_request)
+    }
+}
@@ -0,0 +1,263 @@
+//! Payloads for the webhook events that bear on who a user is and what
+//! they belong to. Only the fields useful for keeping a user directory
+//! in sync are modelled; everything else Github sends is ignored.
+
+use serde::{Deserialize, Serialize};
+
+use super::Event;
+
+/// A user or organization account as it appears in event payloads.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Account {
+    pub id: i64,
+    pub login: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub html_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Organization {
+    pub id: i64,
+    pub login: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Team {
+    pub id: i64,
+    pub name: String,
+    pub slug: String,
+}
+
+/// A user's membership in an organization.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct OrgMembership {
+    pub user: Account,
+    /// Either `admin` or `member`.
+    pub role: String,
+    /// Either `active` or `pending`.
+    pub state: String,
+}
+
+/// An invitation to join an organization.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Invitation {
+    pub id: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub login: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    pub role: String,
+}
+
+/// An installation of a Github App on a user or organization account.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Installation {
+    pub id: i64,
+    pub app_id: i64,
+    pub account: Account,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GithubAppAuthorizationAction {
+    Revoked,
+}
+
+/// Sent when a user revokes their authorization of a Github App. Any
+/// tokens held for the user stop working.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct GithubAppAuthorizationEvent {
+    pub action: GithubAppAuthorizationAction,
+    pub sender: Account,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrganizationAction {
+    MemberAdded,
+    MemberRemoved,
+    MemberInvited,
+    Renamed,
+    Deleted,
+    #[serde(other)]
+    Other,
+}
+
+/// Sent when an organization's membership changes, or the organization
+/// itself is renamed or deleted.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct OrganizationEvent {
+    pub action: OrganizationAction,
+    /// Present for `member_added` and `member_removed`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub membership: Option<OrgMembership>,
+    /// Present for `member_invited`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invitation: Option<Invitation>,
+    /// Present for `member_invited`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<Account>,
+    pub organization: Organization,
+    pub sender: Account,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MembershipAction {
+    Added,
+    Removed,
+}
+
+/// Sent when a user is added to or removed from a team.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct MembershipEvent {
+    pub action: MembershipAction,
+    /// Always `team`.
+    pub scope: String,
+    pub member: Account,
+    pub team: Team,
+    pub organization: Organization,
+    pub sender: Account,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TeamAction {
+    Created,
+    Deleted,
+    Edited,
+    AddedToRepository,
+    RemovedFromRepository,
+    #[serde(other)]
+    Other,
+}
+
+/// Sent when a team is created, deleted or changed.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TeamEvent {
+    pub action: TeamAction,
+    pub team: Team,
+    pub organization: Organization,
+    pub sender: Account,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallationAction {
+    Created,
+    Deleted,
+    Suspend,
+    Unsuspend,
+    NewPermissionsAccepted,
+    #[serde(other)]
+    Other,
+}
+
+/// Sent when a Github App is installed, uninstalled, or has its
+/// installation suspended.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct InstallationEvent {
+    pub action: InstallationAction,
+    pub installation: Installation,
+    pub sender: Account,
+}
+
+/// A webhook payload, typed according to its `X-GitHub-Event` header.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WebhookEvent {
+    GithubAppAuthorization(GithubAppAuthorizationEvent),
+    Organization(OrganizationEvent),
+    Membership(MembershipEvent),
+    Team(TeamEvent),
+    Installation(InstallationEvent),
+    /// Any event this crate doesn't model, left as raw JSON.
+    Other(Event, serde_json::Value),
+}
+
+impl WebhookEvent {
+    /// Parse a payload according to the event it was delivered as.
+    pub fn parse(event: &Event, payload: &[u8]) -> serde_json::Result<Self> {
+        Ok(match event {
+            Event::GithubAppAuthorization => {
+                Self::GithubAppAuthorization(serde_json::from_slice(payload)?)
+            }
+            Event::Organization => Self::Organization(serde_json::from_slice(payload)?),
+            Event::Membership => Self::Membership(serde_json::from_slice(payload)?),
+            Event::Team => Self::Team(serde_json::from_slice(payload)?),
+            Event::Installation => Self::Installation(serde_json::from_slice(payload)?),
+            other => Self::Other(other.clone(), serde_json::from_slice(payload)?),
+        })
+    }
+
+    /// The event this payload is delivered as.
+    pub fn event(&self) -> Event {
+        match self {
+            Self::GithubAppAuthorization(_) => Event::GithubAppAuthorization,
+            Self::Organization(_) => Event::Organization,
+            Self::Membership(_) => Event::Membership,
+            Self::Team(_) => Event::Team,
+            Self::Installation(_) => Event::Installation,
+            Self::Other(event, _) => event.clone(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "fakehub"))]
+mod tests {
+    use crate::{
+        fakehub::{Fakehub, Org},
+        testing::{
+            add_test_user, start_webhook_receiver, ORG, ORG_ID, USER, USER_ID, WEBHOOK_SECRET,
+        },
+        webhooks::WebhookEvent,
+    };
+
+    use super::OrganizationAction;
+
+    #[tokio::test]
+    async fn org_membership_events() {
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let (receiver, mut events) = start_webhook_receiver(WEBHOOK_SECRET);
+
+        add_test_user(&fakehub).await;
+        fakehub
+            .add_org(
+                ORG_ID,
+                Org {
+                    login: ORG.to_string(),
+                },
+            )
+            .await;
+        fakehub
+            .add_webhook(&format!("http://{}/hooks", receiver), WEBHOOK_SECRET)
+            .await
+            .unwrap();
+
+        fakehub.add_org_member(ORG_ID, USER_ID).await.unwrap();
+        fakehub.remove_org_member(ORG_ID, USER_ID).await.unwrap();
+
+        let mut actions = Vec::new();
+        for _ in 0..2 {
+            match events.recv().await.unwrap() {
+                WebhookEvent::Organization(event) => {
+                    assert_eq!(ORG, event.organization.login);
+                    assert_eq!(USER, event.membership.unwrap().user.login);
+                    actions.push(event.action);
+                }
+                other => panic!("unexpected event {:?}", other),
+            }
+        }
+
+        assert_eq!(
+            vec![
+                OrganizationAction::MemberAdded,
+                OrganizationAction::MemberRemoved
+            ],
+            actions
+        );
+
+        fakehub.shutdown().await;
+    }
+}
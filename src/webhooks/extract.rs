@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    body::{Bytes, HttpBody},
+    extract::{FromRef, FromRequest},
+    http::{Request, StatusCode},
+    response::{IntoResponse, Response},
+    BoxError,
+};
+use serde::de::DeserializeOwned;
+
+use super::{Error, WebhookEvent, WebhookHeaders};
+
+/// The secret webhook deliveries are signed with. Make it reachable
+/// from your router state with [`FromRef`] to use [`VerifiedWebhook`].
+#[derive(Clone)]
+pub struct WebhookSecret(Arc<[u8]>);
+
+impl WebhookSecret {
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        Self(Arc::from(secret.as_ref()))
+    }
+}
+
+// Custom debug printer omits the secret, which should never be logged
+// for security reasons.
+impl std::fmt::Debug for WebhookSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "WebhookSecret(REDACTED)")
+    }
+}
+
+/// An axum extractor for a webhook delivery whose signature has been
+/// checked. Unsigned or mis-signed deliveries are rejected before the
+/// handler runs.
+#[derive(Debug)]
+pub struct VerifiedWebhook {
+    pub headers: WebhookHeaders,
+    pub body: Bytes,
+}
+
+impl VerifiedWebhook {
+    /// Deserialize the payload.
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.body)
+    }
+
+    /// Deserialize the payload according to its event type.
+    pub fn event(&self) -> serde_json::Result<WebhookEvent> {
+        WebhookEvent::parse(&self.headers.event, &self.body)
+    }
+}
+
+#[async_trait]
+impl<S, B> FromRequest<S, B> for VerifiedWebhook
+where
+    WebhookSecret: FromRef<S>,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let secret = WebhookSecret::from_ref(state);
+        let headers = WebhookHeaders::from_headers(req.headers()).map_err(|e| e.into_response())?;
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|e| e.into_response())?;
+
+        headers
+            .verify(&secret.0, &body)
+            .map_err(|e| e.into_response())?;
+
+        Ok(Self { headers, body })
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Self::MissingHeader(_) => {
+                (StatusCode::UNAUTHORIZED, format!("{}", self)).into_response()
+            }
+            Self::SignatureMismatch => {
+                (StatusCode::UNAUTHORIZED, format!("{}", self)).into_response()
+            }
+            _ => (StatusCode::BAD_REQUEST, format!("{}", self)).into_response(),
+        }
+    }
+}
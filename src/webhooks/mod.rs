@@ -0,0 +1,365 @@
+//! Verification of Github webhook deliveries.
+//!
+//! Github signs every webhook payload with an HMAC of the secret you
+//! configured when creating the hook. Check that signature before
+//! trusting anything in the payload.
+//!
+//! ```
+//! # use ghoauth::webhooks::{Signature, WebhookHeaders};
+//! # use reqwest::header::HeaderMap;
+//! # fn main() -> Result<(), ghoauth::webhooks::Error> {
+//! let secret = b"It's a Secret to Everybody";
+//! let payload = b"Hello, World!";
+//!
+//! let mut headers = HeaderMap::new();
+//! headers.insert("X-GitHub-Event", "ping".parse().unwrap());
+//! headers.insert(
+//!     "X-GitHub-Delivery",
+//!     "72d3162e-cc78-11e3-81ab-4c9367dc0958".parse().unwrap(),
+//! );
+//! headers.insert(
+//!     "X-Hub-Signature-256",
+//!     Signature::sha256(secret, payload).to_string().parse().unwrap(),
+//! );
+//!
+//! let webhook_headers = WebhookHeaders::from_headers(&headers)?;
+//! webhook_headers.verify(secret, payload)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{fmt, str::FromStr};
+
+use hmac::{Hmac, Mac};
+use reqwest::header::HeaderMap;
+use sha1::Sha1;
+use sha2::Sha256;
+use thiserror::Error;
+
+pub mod events;
+#[cfg(feature = "axum")]
+mod extract;
+
+pub use self::events::WebhookEvent;
+#[cfg(feature = "axum")]
+pub use self::extract::{VerifiedWebhook, WebhookSecret};
+
+/// The name of the event that triggered the delivery.
+pub const EVENT_HEADER: &str = "X-GitHub-Event";
+/// A unique identifier for the delivery.
+pub const DELIVERY_HEADER: &str = "X-GitHub-Delivery";
+/// The HMAC-SHA256 signature of the payload.
+pub const SIGNATURE_256_HEADER: &str = "X-Hub-Signature-256";
+/// The legacy HMAC-SHA1 signature of the payload. Github still sends
+/// it for compatibility, but prefer [`SIGNATURE_256_HEADER`].
+pub const SIGNATURE_HEADER: &str = "X-Hub-Signature";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Header {0} is missing")]
+    MissingHeader(&'static str),
+    #[error("Header {0} contains a non-ASCII value")]
+    InvalidHeader(&'static str),
+    #[error("Malformed signature {0}")]
+    MalformedSignature(String),
+    #[error("Signature does not match the payload")]
+    SignatureMismatch,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A signature from either of the signature headers.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Signature {
+    Sha256(Vec<u8>),
+    Sha1(Vec<u8>),
+}
+
+impl Signature {
+    /// Sign a payload with HMAC-SHA256, as Github does for the
+    /// `X-Hub-Signature-256` header.
+    pub fn sha256(secret: &[u8], payload: &[u8]) -> Self {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("hmac accepts any key length");
+        mac.update(payload);
+        Self::Sha256(mac.finalize().into_bytes().to_vec())
+    }
+
+    /// Sign a payload with HMAC-SHA1, as Github does for the legacy
+    /// `X-Hub-Signature` header.
+    pub fn sha1(secret: &[u8], payload: &[u8]) -> Self {
+        let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("hmac accepts any key length");
+        mac.update(payload);
+        Self::Sha1(mac.finalize().into_bytes().to_vec())
+    }
+
+    /// Check this signature against a payload. The comparison is done
+    /// in constant time so as not to leak how much of a forged
+    /// signature was correct.
+    pub fn verify(&self, secret: &[u8], payload: &[u8]) -> Result<()> {
+        let verified = match self {
+            Self::Sha256(tag) => {
+                let mut mac =
+                    Hmac::<Sha256>::new_from_slice(secret).expect("hmac accepts any key length");
+                mac.update(payload);
+                mac.verify_slice(tag)
+            }
+            Self::Sha1(tag) => {
+                let mut mac =
+                    Hmac::<Sha1>::new_from_slice(secret).expect("hmac accepts any key length");
+                mac.update(payload);
+                mac.verify_slice(tag)
+            }
+        };
+
+        verified.map_err(|_| Error::SignatureMismatch)
+    }
+}
+
+impl FromStr for Signature {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (algorithm, digest) = value
+            .split_once('=')
+            .ok_or_else(|| Error::MalformedSignature(value.to_owned()))?;
+        let digest =
+            hex::decode(digest).map_err(|_| Error::MalformedSignature(value.to_owned()))?;
+
+        match algorithm {
+            "sha256" => Ok(Self::Sha256(digest)),
+            "sha1" => Ok(Self::Sha1(digest)),
+            _ => Err(Error::MalformedSignature(value.to_owned())),
+        }
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Sha256(digest) => write!(f, "sha256={}", hex::encode(digest)),
+            Self::Sha1(digest) => write!(f, "sha1={}", hex::encode(digest)),
+        }
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Signature({})", self)
+    }
+}
+
+/// The kind of event named in the `X-GitHub-Event` header. Events this
+/// crate doesn't know about by name are kept in [`Event::Other`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Event {
+    Ping,
+    Push,
+    PullRequest,
+    Issues,
+    IssueComment,
+    Installation,
+    InstallationRepositories,
+    GithubAppAuthorization,
+    Organization,
+    Membership,
+    Member,
+    Team,
+    Repository,
+    Release,
+    WorkflowRun,
+    Other(String),
+}
+
+impl Event {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Ping => "ping",
+            Self::Push => "push",
+            Self::PullRequest => "pull_request",
+            Self::Issues => "issues",
+            Self::IssueComment => "issue_comment",
+            Self::Installation => "installation",
+            Self::InstallationRepositories => "installation_repositories",
+            Self::GithubAppAuthorization => "github_app_authorization",
+            Self::Organization => "organization",
+            Self::Membership => "membership",
+            Self::Member => "member",
+            Self::Team => "team",
+            Self::Repository => "repository",
+            Self::Release => "release",
+            Self::WorkflowRun => "workflow_run",
+            Self::Other(event) => event,
+        }
+    }
+}
+
+impl From<&str> for Event {
+    fn from(value: &str) -> Self {
+        match value {
+            "ping" => Self::Ping,
+            "push" => Self::Push,
+            "pull_request" => Self::PullRequest,
+            "issues" => Self::Issues,
+            "issue_comment" => Self::IssueComment,
+            "installation" => Self::Installation,
+            "installation_repositories" => Self::InstallationRepositories,
+            "github_app_authorization" => Self::GithubAppAuthorization,
+            "organization" => Self::Organization,
+            "membership" => Self::Membership,
+            "member" => Self::Member,
+            "team" => Self::Team,
+            "repository" => Self::Repository,
+            "release" => Self::Release,
+            "workflow_run" => Self::WorkflowRun,
+            other => Self::Other(other.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The unique id Github assigns each delivery, useful for spotting
+/// redeliveries.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DeliveryId(pub String);
+
+impl DeliveryId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for DeliveryId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// The Github-specific headers of a webhook delivery.
+#[derive(Clone, Debug)]
+pub struct WebhookHeaders {
+    pub event: Event,
+    pub delivery: DeliveryId,
+    /// The SHA-256 signature if Github sent one, otherwise the legacy
+    /// SHA-1 signature.
+    pub signature: Signature,
+}
+
+impl WebhookHeaders {
+    /// Pull the webhook headers out of a request. Fails if the request
+    /// is unsigned.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self> {
+        let event = required_header(headers, EVENT_HEADER)?;
+        let delivery = required_header(headers, DELIVERY_HEADER)?;
+        let signature = match optional_header(headers, SIGNATURE_256_HEADER)? {
+            Some(signature) => signature,
+            None => required_header(headers, SIGNATURE_HEADER)?,
+        };
+
+        Ok(Self {
+            event: Event::from(event),
+            delivery: DeliveryId(delivery.to_owned()),
+            signature: signature.parse()?,
+        })
+    }
+
+    /// Check the payload against the delivery's signature.
+    pub fn verify(&self, secret: &[u8], payload: &[u8]) -> Result<()> {
+        self.signature.verify(secret, payload)
+    }
+}
+
+fn optional_header<'a>(headers: &'a HeaderMap, name: &'static str) -> Result<Option<&'a str>> {
+    match headers.get(name) {
+        Some(value) => match value.to_str() {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(Error::InvalidHeader(name)),
+        },
+        None => Ok(None),
+    }
+}
+
+fn required_header<'a>(headers: &'a HeaderMap, name: &'static str) -> Result<&'a str> {
+    optional_header(headers, name)?.ok_or(Error::MissingHeader(name))
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "fakehub")]
+    use serde_json::json;
+
+    #[cfg(feature = "fakehub")]
+    use crate::{
+        fakehub::Fakehub,
+        testing::{start_webhook_receiver, WEBHOOK_SECRET},
+    };
+
+    #[cfg(feature = "fakehub")]
+    use super::Event;
+    use super::Signature;
+
+    // the worked example from Github's documentation on validating
+    // webhook deliveries
+    const SECRET: &[u8] = b"It's a Secret to Everybody";
+    const PAYLOAD: &[u8] = b"Hello, World!";
+    const SIGNATURE: &str =
+        "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+
+    #[test]
+    fn verify_signatures() {
+        assert_eq!(SIGNATURE, Signature::sha256(SECRET, PAYLOAD).to_string());
+
+        let signature: Signature = SIGNATURE.parse().unwrap();
+        signature.verify(SECRET, PAYLOAD).unwrap();
+        assert!(signature.verify(b"wrong secret", PAYLOAD).is_err());
+
+        let legacy: Signature = Signature::sha1(SECRET, PAYLOAD)
+            .to_string()
+            .parse()
+            .unwrap();
+        legacy.verify(SECRET, PAYLOAD).unwrap();
+        assert!(legacy.verify(SECRET, b"Goodbye, World!").is_err());
+    }
+
+    #[cfg(feature = "fakehub")]
+    #[tokio::test]
+    async fn webhook_delivery() {
+        let fakehub = Fakehub::new().expect("cannot start local fakehub server");
+        let (receiver, _) = start_webhook_receiver(WEBHOOK_SECRET);
+
+        fakehub
+            .add_webhook(&format!("http://{}/hooks", receiver), WEBHOOK_SECRET)
+            .await
+            .unwrap();
+
+        let deliveries = fakehub
+            .deliver_webhook(
+                &Event::Ping,
+                &json!({ "zen": "Keep it logically awesome." }),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(1, deliveries.len());
+
+        // a receiver expecting a different secret rejects the delivery
+        fakehub
+            .add_webhook(&format!("http://{}/hooks", receiver), "wrong secret")
+            .await
+            .unwrap();
+
+        assert!(fakehub
+            .deliver_webhook(
+                &Event::Ping,
+                &json!({ "zen": "Keep it logically awesome." })
+            )
+            .await
+            .is_err());
+
+        fakehub.shutdown().await;
+    }
+}